@@ -1,22 +1,27 @@
 //! An OpenAI-compatible API server for the Qwen 2.5B models using the candle-qwen2-5-core library.
 use anyhow::Result;
 use axum::{
-    extract::{Json, State},
-    http::StatusCode,
-    response::{sse::Event, IntoResponse, Sse},
-    routing::post,
+    extract::{DefaultBodyLimit, Json, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{sse::Event, IntoResponse, Response, Sse},
+    routing::{get, post},
     Router,
 };
-use candle_qwen2_5_core::{ModelArgs, Qwen2Model, Which as CoreWhich};
+use candle_qwen2_5_core::{
+    defaults, GenerationStats, Grammar, ModelArgs, Quant as CoreQuant, Qwen2Model, Role,
+    Which as CoreWhich,
+};
 use clap::{Parser, ValueEnum};
+use futures_util::future::BoxFuture;
 use serde::{Deserialize, Serialize};
 use std::{
     convert::Infallible,
     net::SocketAddr,
-    sync::{Arc, Mutex},
+    sync::{Arc, RwLock},
 };
-use tokio::sync::mpsc;
-use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use subtle::ConstantTimeEq;
+use tokio::sync::{mpsc, Mutex, OwnedMutexGuard};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 use tracing::info;
 use uuid::Uuid;
 
@@ -43,6 +48,44 @@ impl From<Which> for CoreWhich {
     }
 }
 
+#[derive(Clone, Debug, Copy, PartialEq, Eq, ValueEnum)]
+enum Quant {
+    #[value(name = "q2_k")]
+    Q2K,
+    #[value(name = "q3_k_m")]
+    Q3KM,
+    #[value(name = "q4_0")]
+    Q4_0,
+    #[value(name = "q4_k_m")]
+    Q4KM,
+    #[value(name = "q5_0")]
+    Q5_0,
+    #[value(name = "q5_k_m")]
+    Q5KM,
+    #[value(name = "q6_k")]
+    Q6K,
+    #[value(name = "q8_0")]
+    Q8_0,
+    #[value(name = "fp16")]
+    F16,
+}
+
+impl From<Quant> for CoreQuant {
+    fn from(q: Quant) -> Self {
+        match q {
+            Quant::Q2K => CoreQuant::Q2K,
+            Quant::Q3KM => CoreQuant::Q3KM,
+            Quant::Q4_0 => CoreQuant::Q4_0,
+            Quant::Q4KM => CoreQuant::Q4KM,
+            Quant::Q5_0 => CoreQuant::Q5_0,
+            Quant::Q5KM => CoreQuant::Q5KM,
+            Quant::Q6K => CoreQuant::Q6K,
+            Quant::Q8_0 => CoreQuant::Q8_0,
+            Quant::F16 => CoreQuant::F16,
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
@@ -55,7 +98,7 @@ struct Args {
     tokenizer: Option<String>,
 
     /// The temperature used to generate samples.
-    #[arg(long, default_value_t = 0.0)]
+    #[arg(long, default_value_t = defaults::TEMPERATURE)]
     temperature: f64,
 
     /// Nucleus sampling probability cutoff.
@@ -67,7 +110,7 @@ struct Args {
     top_k: Option<usize>,
 
     /// The seed to use when generating random samples.
-    #[arg(long, default_value_t = 299792458)]
+    #[arg(long, default_value_t = defaults::SEED)]
     seed: u64,
 
     /// Run on CPU rather than GPU.
@@ -75,17 +118,29 @@ struct Args {
     cpu: bool,
 
     /// Penalty for repeating tokens.
-    #[arg(long, default_value_t = 1.1)]
+    #[arg(long, default_value_t = defaults::REPEAT_PENALTY)]
     repeat_penalty: f32,
 
     /// Context size for repeat penalty.
-    #[arg(long, default_value_t = 64)]
+    #[arg(long, default_value_t = defaults::REPEAT_LAST_N)]
     repeat_last_n: usize,
 
     /// The model size to use.
     #[arg(long, default_value = "3b")]
     which: Which,
 
+    /// The GGUF quantization to use.
+    #[arg(long, default_value = "q4_k_m")]
+    quant: Quant,
+
+    /// Bearer token required by the /admin endpoints. They are disabled when unset.
+    #[arg(long)]
+    admin_key: Option<String>,
+
+    /// Largest request body accepted, in bytes. Bigger requests get a 413.
+    #[arg(long, default_value_t = DEFAULT_MAX_BODY_BYTES)]
+    max_body_bytes: usize,
+
     /// Port to listen on.
     #[arg(long, default_value = "42069")]
     port: u16,
@@ -104,10 +159,36 @@ struct ChatCompletionRequest {
     stream: bool,
     #[serde(default = "default_sample_len")]
     max_tokens: usize,
+    #[serde(default)]
+    response_format: Option<ResponseFormat>,
+    /// OpenAI's end-user identifier for abuse tracking. Only logged.
+    #[serde(default)]
+    user: Option<String>,
+    /// Arbitrary caller tags. Only logged.
+    #[serde(default)]
+    metadata: Option<serde_json::Value>,
+}
+
+/// OpenAI's `response_format`. `json_object` constrains decoding so the reply is one JSON
+/// object; streamed deltas are then pieces of it.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseFormat {
+    Text,
+    JsonObject,
+}
+
+impl ResponseFormat {
+    fn grammar(self) -> Option<Grammar> {
+        match self {
+            ResponseFormat::Text => None,
+            ResponseFormat::JsonObject => Some(Grammar::JsonObject),
+        }
+    }
 }
 
 fn default_sample_len() -> usize {
-    1000
+    defaults::SAMPLE_LEN
 }
 
 #[derive(Deserialize, Debug, Serialize, Clone)]
@@ -122,6 +203,8 @@ struct ChatCompletionResponse {
     object: String,
     created: u64,
     model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_fingerprint: Option<String>,
     choices: Vec<Choice>,
 }
 
@@ -138,6 +221,8 @@ struct ChatCompletionChunk {
     object: String,
     created: u64,
     model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_fingerprint: Option<String>,
     choices: Vec<ChunkChoice>,
 }
 
@@ -148,7 +233,268 @@ struct ChunkChoice {
     finish_reason: Option<String>,
 }
 
-type AppState = Arc<Mutex<Qwen2Model>>;
+#[derive(Serialize, Debug)]
+struct ModelList {
+    object: String,
+    data: Vec<ModelObject>,
+}
+
+#[derive(Serialize, Debug)]
+struct ModelObject {
+    id: String,
+    object: String,
+    owned_by: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SwapModelRequest {
+    which: String,
+    /// Keeps the current quantization when omitted.
+    #[serde(default)]
+    quant: Option<String>,
+}
+
+/// The part of [Qwen2Model] the handlers use, so tests can serve a mock model.
+trait ChatModel: Send {
+    /// Generates the next assistant turn of `messages`, freely or under `grammar` when one is
+    /// given.
+    fn generate(
+        &mut self,
+        messages: &[(Role, String)],
+        sample_len: usize,
+        grammar: Option<Grammar>,
+        callback: &mut dyn FnMut(String) -> Result<()>,
+    ) -> Result<GenerationStats>;
+}
+
+impl ChatModel for Qwen2Model {
+    fn generate(
+        &mut self,
+        messages: &[(Role, String)],
+        sample_len: usize,
+        grammar: Option<Grammar>,
+        callback: &mut dyn FnMut(String) -> Result<()>,
+    ) -> Result<GenerationStats> {
+        let stats = match grammar {
+            Some(grammar) => Qwen2Model::generate_constrained_chat(
+                self,
+                messages,
+                sample_len,
+                grammar,
+                &mut *callback,
+            ),
+            None => Qwen2Model::generate_chat(self, messages, sample_len, &mut *callback),
+        }?;
+        self.finish_generation(callback)?;
+        Ok(stats)
+    }
+}
+
+/// Loads a model for the given args.
+type ModelLoader =
+    Arc<dyn Fn(ModelArgs) -> BoxFuture<'static, Result<Box<dyn ChatModel>>> + Send + Sync>;
+
+fn qwen_loader() -> ModelLoader {
+    Arc::new(|args: ModelArgs| {
+        Box::pin(async move {
+            let model = Qwen2Model::new(&args).await?;
+            Ok(Box::new(model) as Box<dyn ChatModel>)
+        })
+    })
+}
+
+/// Which model is currently being served.
+#[derive(Clone, Copy, Debug)]
+struct LoadedModel {
+    which: Which,
+    quant: Quant,
+}
+
+impl LoadedModel {
+    /// Model id reported to clients, matching the GGUF file name, e.g. `qwen2.5-3b-instruct-q4_k_m`.
+    fn id(&self) -> String {
+        format!(
+            "qwen2.5-{}-instruct-{}",
+            value_name(self.which),
+            value_name(self.quant)
+        )
+    }
+
+    /// `system_fingerprint` reported with every completion: a 64-bit FNV-1a hash of [Self::id],
+    /// so it stays the same for as long as this model is served and changes on a swap.
+    fn fingerprint(&self) -> String {
+        let hash = self
+            .id()
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+            });
+        format!("fp_{:016x}", hash)
+    }
+}
+
+/// Seconds since the Unix epoch, for the `created` field.
+fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn value_name<T: ValueEnum>(value: T) -> String {
+    value
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Produces the unique part of response ids (`cmpl-<id>`).
+type IdGenerator = Arc<dyn Fn() -> String + Send + Sync>;
+
+fn uuid_ids() -> IdGenerator {
+    Arc::new(|| Uuid::new_v4().to_string())
+}
+
+/// The served model together with which one it is, behind one lock, so a completion always
+/// reports the model that generated it.
+struct Served {
+    model: Box<dyn ChatModel>,
+    loaded: LoadedModel,
+}
+
+#[derive(Clone)]
+struct AppState {
+    /// Generation holds this lock for the whole request, so swapping models waits for
+    /// in-flight requests to drain.
+    served: Arc<Mutex<Served>>,
+    /// Copy of `served.loaded` for /v1/models, which shouldn't wait for a generation. Only
+    /// written while holding `served`.
+    loaded: Arc<RwLock<LoadedModel>>,
+    /// Sampling settings reused when loading a replacement model.
+    base_args: Arc<ModelArgs>,
+    loader: ModelLoader,
+    admin_key: Option<Arc<str>>,
+    next_id: IdGenerator,
+}
+
+impl AppState {
+    fn new(
+        model: Box<dyn ChatModel>,
+        loaded: LoadedModel,
+        base_args: ModelArgs,
+        loader: ModelLoader,
+        admin_key: Option<String>,
+    ) -> Self {
+        Self {
+            served: Arc::new(Mutex::new(Served { model, loaded })),
+            loaded: Arc::new(RwLock::new(loaded)),
+            base_args: Arc::new(base_args),
+            loader,
+            admin_key: admin_key.map(Into::into),
+            next_id: uuid_ids(),
+        }
+    }
+
+    /// Replaces the default UUID ids, e.g. with a counter so tests can assert exact ids.
+    #[cfg(test)]
+    fn with_id_generator(mut self, next_id: IdGenerator) -> Self {
+        self.next_id = next_id;
+        self
+    }
+
+    fn completion_id(&self) -> String {
+        format!("cmpl-{}", (self.next_id)())
+    }
+
+    fn model_id(&self) -> String {
+        self.loaded.read().unwrap().id()
+    }
+}
+
+/// Fields shared by the response, or by every chunk of the stream, of one completion.
+#[derive(Clone, Debug)]
+struct CompletionMeta {
+    id: String,
+    created: u64,
+    model: String,
+    fingerprint: String,
+}
+
+impl CompletionMeta {
+    /// Metadata for completion `id` generated by `loaded`; read it while holding the model lock.
+    fn new(id: String, loaded: &LoadedModel) -> Self {
+        Self {
+            id,
+            // Every chunk of a stream carries the same creation time as the request.
+            created: unix_timestamp(),
+            model: loaded.id(),
+            fingerprint: loaded.fingerprint(),
+        }
+    }
+}
+
+/// Request body limit used unless `--max-body-bytes` is given. Set explicitly rather than
+/// relying on axum's default so large DOT payloads have a known, adjustable limit.
+const DEFAULT_MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+fn router(state: AppState, max_body_bytes: usize) -> Router {
+    Router::new()
+        .route("/v1/chat/completions", post(chat_completions_handler))
+        .route("/v1/models", get(models_handler))
+        .route("/admin/model", post(swap_model_handler))
+        .layer(DefaultBodyLimit::max(max_body_bytes))
+        .with_state(state)
+}
+
+/// Logs how fast a finished completion was generated, at INFO.
+fn log_throughput(completion_id: &str, stats: &GenerationStats) {
+    info!(
+        completion_id,
+        prompt_tokens = stats.prompt_tokens,
+        generated_tokens = stats.generated_tokens,
+        tokens_per_sec = format_args!("{:.2}", stats.generated_tokens_per_sec()),
+        "Completion finished"
+    );
+}
+
+/// Logs the caller's `user` and `metadata` against the completion id, at INFO, if either was
+/// sent.
+fn log_request_context(
+    completion_id: &str,
+    user: Option<&str>,
+    metadata: Option<&serde_json::Value>,
+) {
+    if user.is_none() && metadata.is_none() {
+        return;
+    }
+    info!(
+        completion_id,
+        user,
+        metadata = metadata.map(tracing::field::display),
+        "Completion requested"
+    );
+}
+
+/// The request's messages as conversation turns. OpenAI's `developer` role is treated as
+/// `system`; other roles the chat template has no turn for are rejected.
+fn chat_turns(messages: &[ChatMessage]) -> Result<Vec<(Role, String)>, String> {
+    messages
+        .iter()
+        .map(|m| {
+            let role = match m.role.as_str() {
+                "system" | "developer" => Role::System,
+                "user" => Role::User,
+                "assistant" => Role::Assistant,
+                other => return Err(format!("Unsupported message role '{}'", other)),
+            };
+            Ok((role, m.content.clone()))
+        })
+        .collect()
+}
+
+fn error_response(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, Json(serde_json::json!({"error": message.into()}))).into_response()
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -166,30 +512,32 @@ async fn main() -> Result<()> {
 
     info!("Starting server with args: {:?}", args);
 
-    let model_args = ModelArgs {
-        model: args.model,
-        sample_len: 0, // This will be overridden by request
-        tokenizer: args.tokenizer,
-        temperature: args.temperature,
-        top_p: args.top_p,
-        top_k: args.top_k,
-        seed: args.seed,
-        tracing: false,
-        split_prompt: false,
-        cpu: args.cpu,
-        repeat_penalty: args.repeat_penalty,
-        repeat_last_n: args.repeat_last_n,
-        which: args.which.into(),
-    };
+    let model_args = ModelArgs::builder()
+        .model(args.model)
+        .sample_len(0) // This will be overridden by request
+        .tokenizer(args.tokenizer)
+        .temperature(args.temperature)
+        .top_p(args.top_p)
+        .top_k(args.top_k)
+        .seed(args.seed)
+        .cpu(args.cpu)
+        .repeat_penalty(args.repeat_penalty)
+        .repeat_last_n(args.repeat_last_n)
+        .which(args.which.into())
+        .quant(args.quant.into())
+        .build();
 
     info!("Loading model...");
-    let model = Qwen2Model::new(&model_args).await?;
-    let app_state = Arc::new(Mutex::new(model));
+    let loader = qwen_loader();
+    let model = loader(model_args.clone()).await?;
+    let loaded = LoadedModel {
+        which: args.which,
+        quant: args.quant,
+    };
+    let app_state = AppState::new(model, loaded, model_args, loader, args.admin_key);
     info!("Model loaded successfully.");
 
-    let app = Router::new()
-        .route("/v1/chat/completions", post(chat_completions_handler))
-        .with_state(app_state);
+    let app = router(app_state, args.max_body_bytes);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], args.port));
     info!("Listening on http://{}", addr);
@@ -203,22 +551,85 @@ async fn chat_completions_handler(
     State(state): State<AppState>,
     Json(payload): Json<ChatCompletionRequest>,
 ) -> impl IntoResponse {
-    let prompt = payload
-        .messages
-        .last()
-        .map(|m| m.content.clone())
-        .unwrap_or_default();
+    let messages = match chat_turns(&payload.messages) {
+        Ok(messages) => messages,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
     let sample_len = payload.max_tokens;
-    let model_name = "qwen2.5-gguf"; // Or derive from args
+    let grammar = payload.response_format.and_then(ResponseFormat::grammar);
+    let id = state.completion_id();
+    log_request_context(&id, payload.user.as_deref(), payload.metadata.as_ref());
+
+    // Waits for any other generation or swap, and keeps the model until this one is done.
+    let mut served = Arc::clone(&state.served).lock_owned().await;
+    let meta = CompletionMeta::new(id, &served.loaded);
 
     if payload.stream {
-        let (tx, rx) = mpsc::channel::<Result<String, anyhow::Error>>(100);
+        return stream_completion(served, messages, sample_len, grammar, meta);
+    }
+
+    let generation_task = tokio::task::spawn_blocking(move || {
+        let mut full_response = String::new();
+        let result = served
+            .model
+            .generate(&messages, sample_len, grammar, &mut |token| {
+                full_response.push_str(&token);
+                Ok(())
+            });
+        (full_response, result)
+    });
+
+    let (full_response, result) = match generation_task.await {
+        Ok(output) => output,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+
+    match result {
+        Ok(stats) => log_throughput(&meta.id, &stats),
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response();
+        }
+    }
+
+    let response = ChatCompletionResponse {
+        id: meta.id,
+        object: "chat.completion".to_string(),
+        created: meta.created,
+        model: meta.model,
+        system_fingerprint: Some(meta.fingerprint),
+        choices: vec![Choice {
+            index: 0,
+            message: ChatMessage {
+                role: "assistant".to_string(),
+                content: full_response,
+            },
+            finish_reason: "stop".to_string(),
+        }],
+    };
 
-        let stream = ReceiverStream::new(rx);
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+/// Generates on a blocking thread and streams the tokens as [completion_events]. Every endpoint
+/// that honors `"stream": true` goes through here so clients see one SSE format.
+fn stream_completion(
+    mut served: OwnedMutexGuard<Served>,
+    messages: Vec<(Role, String)>,
+    sample_len: usize,
+    grammar: Option<Grammar>,
+    meta: CompletionMeta,
+) -> Response {
+    let (tx, rx) = mpsc::channel::<Result<String, anyhow::Error>>(100);
 
-        tokio::task::spawn_blocking(move || {
-            let mut model_guard = state.lock().unwrap();
-            let res = model_guard.generate(&prompt, sample_len, |token| {
+    let stream_id = meta.id.clone();
+    tokio::task::spawn_blocking(move || {
+        let res = served
+            .model
+            .generate(&messages, sample_len, grammar, &mut |token| {
                 if tx.blocking_send(Ok(token)).is_err() {
                     // If the receiver is dropped, stop generation.
                     return Err(anyhow::anyhow!("Client disconnected"));
@@ -226,87 +637,599 @@ async fn chat_completions_handler(
                 Ok(())
             });
 
-            if let Err(e) = res {
+        match res {
+            Ok(stats) => log_throughput(&stream_id, &stats),
+            Err(e) => {
                 let _ = tx.blocking_send(Err(e.into()));
             }
-        });
+        }
+    });
 
-        let sse_stream = stream.map(move |res| {
-            let event = match res {
-                Ok(token) => {
-                    let chunk_id = format!("cmpl-{}", Uuid::new_v4());
-                    let created = std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs();
-                    let chunk = ChatCompletionChunk {
-                        id: chunk_id,
-                        object: "chat.completion.chunk".to_string(),
-                        created,
-                        model: model_name.to_string(),
-                        choices: vec![ChunkChoice {
-                            index: 0,
-                            delta: ChatMessage {
-                                role: "assistant".to_string(),
-                                content: token,
-                            },
-                            finish_reason: None,
-                        }],
-                    };
-                    Event::default()
-                        .json_data(chunk)
-                        .unwrap_or_else(|_| Event::default().data("Error serializing chunk"))
-                }
-                Err(e) => Event::default().data(format!("[ERROR]: {}", e)),
+    Sse::new(completion_events(ReceiverStream::new(rx), meta)).into_response()
+}
+
+/// SSE events for a streamed completion: a `chat.completion.chunk` per token, an
+/// `[ERROR]: ...` event if generation fails, then `[DONE]`.
+fn completion_events(
+    tokens: impl Stream<Item = Result<String, anyhow::Error>> + Send + 'static,
+    meta: CompletionMeta,
+) -> impl Stream<Item = Result<Event, Infallible>> + Send + 'static {
+    let sse_stream = tokens.map(move |res| {
+        let event = match res {
+            Ok(token) => {
+                let chunk = ChatCompletionChunk {
+                    id: meta.id.clone(),
+                    object: "chat.completion.chunk".to_string(),
+                    created: meta.created,
+                    model: meta.model.clone(),
+                    system_fingerprint: Some(meta.fingerprint.clone()),
+                    choices: vec![ChunkChoice {
+                        index: 0,
+                        delta: ChatMessage {
+                            role: "assistant".to_string(),
+                            content: token,
+                        },
+                        finish_reason: None,
+                    }],
+                };
+                Event::default()
+                    .json_data(chunk)
+                    .unwrap_or_else(|_| Event::default().data("Error serializing chunk"))
+            }
+            Err(e) => Event::default().data(format!("[ERROR]: {}", e)),
+        };
+        Ok::<_, Infallible>(event)
+    });
+
+    sse_stream.chain(futures_util::stream::once(async {
+        Ok(Event::default().data("[DONE]"))
+    }))
+}
+
+async fn models_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ModelList {
+        object: "list".to_string(),
+        data: vec![ModelObject {
+            id: state.model_id(),
+            object: "model".to_string(),
+            owned_by: "qwen".to_string(),
+        }],
+    })
+}
+
+/// Loads a different model size/quantization and replaces the served one without a restart.
+async fn swap_model_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<SwapModelRequest>,
+) -> Response {
+    let Some(admin_key) = state.admin_key.as_deref() else {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "Admin endpoints are disabled, start the server with --admin-key",
+        );
+    };
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        // Constant time, so response timing doesn't reveal how much of a guess was right.
+        .is_some_and(|key| bool::from(key.as_bytes().ct_eq(admin_key.as_bytes())));
+    if !authorized {
+        return error_response(StatusCode::UNAUTHORIZED, "Invalid admin key");
+    }
+
+    let which = match Which::from_str(&payload.which, true) {
+        Ok(which) => which,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let quant = match payload.quant.as_deref().map(|q| Quant::from_str(q, true)) {
+        Some(Ok(quant)) => quant,
+        Some(Err(e)) => return error_response(StatusCode::BAD_REQUEST, e),
+        None => state.loaded.read().unwrap().quant,
+    };
+    let loaded = LoadedModel { which, quant };
+
+    // An explicit --model path would always load the same file, so resolve from the hub.
+    let args = ModelArgs {
+        model: None,
+        which: which.into(),
+        quant: quant.into(),
+        ..(*state.base_args).clone()
+    };
+
+    info!("Loading model {}...", loaded.id());
+    let new_model = match (state.loader)(args).await {
+        Ok(model) => model,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+
+    // Taking the lock waits for the in-flight generation (if any) to finish.
+    let mut served = state.served.lock().await;
+    served.model = new_model;
+    served.loaded = loaded;
+    *state.loaded.write().unwrap() = loaded;
+    drop(served);
+    info!("Now serving {}", loaded.id());
+
+    Json(ModelObject {
+        id: loaded.id(),
+        object: "model".to_string(),
+        owned_by: "qwen".to_string(),
+    })
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    /// Streams a fixed reply word by word without loading any weights. Under a grammar the
+    /// reply is wrapped in a JSON object, as a constrained model would produce.
+    struct MockModel {
+        reply: &'static str,
+    }
+
+    impl ChatModel for MockModel {
+        fn generate(
+            &mut self,
+            _messages: &[(Role, String)],
+            _sample_len: usize,
+            grammar: Option<Grammar>,
+            callback: &mut dyn FnMut(String) -> Result<()>,
+        ) -> Result<GenerationStats> {
+            let reply = match grammar {
+                Some(Grammar::JsonObject) => format!(r#"{{"reply": "{}"}}"#, self.reply),
+                None => self.reply.to_string(),
             };
-            Ok::<_, Infallible>(event)
-        });
+            let mut generated_tokens = 0;
+            for token in reply.split_inclusive(' ') {
+                callback(token.to_string())?;
+                generated_tokens += 1;
+            }
+            Ok(GenerationStats {
+                prompt_tokens: 1,
+                prompt_processing_time: Duration::from_millis(1),
+                generated_tokens,
+                generation_time: Duration::from_millis(1),
+            })
+        }
+    }
 
-        let final_stream = sse_stream.chain(futures_util::stream::once(async {
-            Ok(Event::default().data("[DONE]"))
-        }));
-
-        Sse::new(final_stream).into_response()
-    } else {
-        let model_clone = Arc::clone(&state);
-        let generation_task = tokio::task::spawn_blocking(move || {
-            let mut model_guard = model_clone.lock().unwrap();
-            let mut full_response = String::new();
-            let result = model_guard.generate(&prompt, sample_len, |token| {
-                full_response.push_str(&token);
-                Ok(())
+    /// Replies "ok" and keeps the conversation it was last asked to continue.
+    #[derive(Clone, Default)]
+    struct RecordingModel(Arc<Mutex<Vec<(Role, String)>>>);
+
+    impl ChatModel for RecordingModel {
+        fn generate(
+            &mut self,
+            messages: &[(Role, String)],
+            _sample_len: usize,
+            _grammar: Option<Grammar>,
+            callback: &mut dyn FnMut(String) -> Result<()>,
+        ) -> Result<GenerationStats> {
+            *self.0.lock().unwrap() = messages.to_vec();
+            callback("ok".to_string())?;
+            Ok(GenerationStats {
+                prompt_tokens: messages.len(),
+                prompt_processing_time: Duration::from_millis(1),
+                generated_tokens: 1,
+                generation_time: Duration::from_millis(1),
+            })
+        }
+    }
+
+    fn mock_loader() -> ModelLoader {
+        Arc::new(|_args: ModelArgs| {
+            Box::pin(async {
+                Ok(Box::new(MockModel {
+                    reply: "Hello world",
+                }) as Box<dyn ChatModel>)
+            })
+        })
+    }
+
+    fn test_state(admin_key: Option<&str>) -> AppState {
+        AppState::new(
+            Box::new(MockModel {
+                reply: "Hello world",
+            }),
+            LoadedModel {
+                which: Which::W25_3b,
+                quant: Quant::Q4KM,
+            },
+            ModelArgs::default(),
+            mock_loader(),
+            admin_key.map(String::from),
+        )
+    }
+
+    fn counter_ids() -> IdGenerator {
+        let counter = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        Arc::new(move || {
+            let n = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            n.to_string()
+        })
+    }
+
+    async fn body_json(response: Response) -> serde_json::Value {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    async fn served_model(app: &Router) -> String {
+        let response = app
+            .clone()
+            .oneshot(Request::get("/v1/models").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let json = body_json(response).await;
+        json["data"][0]["id"].as_str().unwrap().to_string()
+    }
+
+    fn swap_request(body: &'static str, auth: Option<&str>) -> Request<Body> {
+        let mut builder =
+            Request::post("/admin/model").header(header::CONTENT_TYPE, "application/json");
+        if let Some(auth) = auth {
+            builder = builder.header(header::AUTHORIZATION, auth);
+        }
+        builder.body(Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_swap_model() {
+        let app = router(test_state(Some("secret")), DEFAULT_MAX_BODY_BYTES);
+        assert_eq!(served_model(&app).await, "qwen2.5-3b-instruct-q4_k_m");
+
+        let response = app
+            .clone()
+            .oneshot(swap_request(r#"{"which": "1.5b"}"#, Some("Bearer secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(served_model(&app).await, "qwen2.5-1.5b-instruct-q4_k_m");
+    }
+
+    #[tokio::test]
+    async fn test_swap_model_requires_admin_key() {
+        let app = router(test_state(Some("secret")), DEFAULT_MAX_BODY_BYTES);
+        let response = app
+            .clone()
+            .oneshot(swap_request(r#"{"which": "1.5b"}"#, Some("Bearer wrong")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let disabled = router(test_state(None), DEFAULT_MAX_BODY_BYTES);
+        let response = disabled
+            .oneshot(swap_request(r#"{"which": "1.5b"}"#, Some("Bearer secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        assert_eq!(served_model(&app).await, "qwen2.5-3b-instruct-q4_k_m");
+    }
+
+    #[tokio::test]
+    async fn test_completion_ids_from_generator() {
+        let app = router(
+            test_state(None).with_id_generator(counter_ids()),
+            DEFAULT_MAX_BODY_BYTES,
+        );
+        let completion = || {
+            Request::post("/v1/chat/completions")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    r#"{"messages": [{"role": "user", "content": "Hi"}], "stream": false}"#,
+                ))
+                .unwrap()
+        };
+
+        let json = body_json(app.clone().oneshot(completion()).await.unwrap()).await;
+        assert_eq!(json["id"], "cmpl-1");
+        assert_eq!(json["choices"][0]["message"]["content"], "Hello world");
+
+        let json = body_json(app.oneshot(completion()).await.unwrap()).await;
+        assert_eq!(json["id"], "cmpl-2");
+    }
+
+    #[tokio::test]
+    async fn test_body_size_limit() {
+        let app = router(test_state(None), 256);
+        let completion = |content: &str| {
+            let body = serde_json::json!({
+                "messages": [{"role": "user", "content": content}],
+                "stream": false,
             });
-            (full_response, result)
-        });
+            Request::post("/v1/chat/completions")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
 
-        let (full_response, result) = generation_task.await.unwrap();
+        let response = app.clone().oneshot(completion("Hi")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-        if let Err(e) = result {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error": e.to_string()})),
-            )
-                .into_response();
+        let too_big = "digraph { a -> b } ".repeat(20);
+        let response = app.oneshot(completion(&too_big)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_system_fingerprint_is_stable() {
+        let app = router(test_state(None), DEFAULT_MAX_BODY_BYTES);
+        let completion = |stream: bool| {
+            let body = serde_json::json!({
+                "messages": [{"role": "user", "content": "Hi"}],
+                "stream": stream,
+            });
+            Request::post("/v1/chat/completions")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let first = body_json(app.clone().oneshot(completion(false)).await.unwrap()).await;
+        let second = body_json(app.clone().oneshot(completion(false)).await.unwrap()).await;
+        let fingerprint = first["system_fingerprint"].as_str().unwrap();
+        assert!(fingerprint.starts_with("fp_"));
+        assert_eq!(second["system_fingerprint"], fingerprint);
+        assert_eq!(first["model"], "qwen2.5-3b-instruct-q4_k_m");
+        assert!(first["created"].as_u64().unwrap() > 0);
+
+        let response = app.oneshot(completion(true)).await.unwrap();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let chunks: Vec<serde_json::Value> = String::from_utf8(bytes.to_vec())
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter(|data| *data != "[DONE]")
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+        assert!(!chunks.is_empty());
+        for chunk in &chunks {
+            assert_eq!(chunk["system_fingerprint"], fingerprint);
+            assert_eq!(chunk["created"], chunks[0]["created"]);
+        }
+    }
+
+    /// Log output collected by a test subscriber.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
         }
 
-        let response = ChatCompletionResponse {
-            id: format!("cmpl-{}", Uuid::new_v4()),
-            object: "chat.completion".to_string(),
-            created: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_throughput_logged_per_request() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        // Non-streaming completions log from the handler, which runs on this thread.
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = router(
+            test_state(None).with_id_generator(counter_ids()),
+            DEFAULT_MAX_BODY_BYTES,
+        );
+        let completion = || {
+            Request::post("/v1/chat/completions")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    r#"{"messages": [{"role": "user", "content": "Hi"}], "stream": false}"#,
+                ))
+                .unwrap()
+        };
+        app.clone().oneshot(completion()).await.unwrap();
+        app.oneshot(completion()).await.unwrap();
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = logs
+            .lines()
+            .filter(|line| line.contains("Completion finished"))
+            .collect();
+        assert_eq!(lines.len(), 2, "{logs}");
+        assert!(lines[0].contains("INFO"));
+        assert!(lines[0].contains("completion_id=\"cmpl-1\""));
+        assert!(lines[1].contains("completion_id=\"cmpl-2\""));
+        // MockModel reports 2 tokens in 1ms.
+        assert!(lines[0].contains("tokens_per_sec=2000.00"));
+    }
+
+    #[tokio::test]
+    async fn test_user_and_metadata_logged() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = router(
+            test_state(None).with_id_generator(counter_ids()),
+            DEFAULT_MAX_BODY_BYTES,
+        );
+        let body = serde_json::json!({
+            "messages": [{"role": "user", "content": "Hi"}],
+            "user": "user-42",
+            "metadata": {"session": "abc"},
+        });
+        let response = app
+            .oneshot(
+                Request::post("/v1/chat/completions")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let json = body_json(response).await;
+        assert_eq!(json["choices"][0]["message"]["content"], "Hello world");
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let line = logs
+            .lines()
+            .find(|line| line.contains("Completion requested"))
+            .unwrap_or_else(|| panic!("{logs}"));
+        assert!(line.contains("completion_id=\"cmpl-1\""));
+        assert!(line.contains("user=\"user-42\""));
+        assert!(line.contains(r#"metadata={"session":"abc"}"#));
+    }
+
+    #[tokio::test]
+    async fn test_whole_conversation_reaches_model() {
+        let model = RecordingModel::default();
+        let state = AppState::new(
+            Box::new(model.clone()),
+            LoadedModel {
+                which: Which::W25_3b,
+                quant: Quant::Q4KM,
+            },
+            ModelArgs::default(),
+            mock_loader(),
+            None,
+        );
+        let app = router(state, DEFAULT_MAX_BODY_BYTES);
+        let completion = |messages: serde_json::Value| {
+            let body = serde_json::json!({"messages": messages});
+            Request::post("/v1/chat/completions")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
                 .unwrap()
-                .as_secs(),
-            model: model_name.to_string(),
-            choices: vec![Choice {
-                index: 0,
-                message: ChatMessage {
-                    role: "assistant".to_string(),
-                    content: full_response,
-                },
-                finish_reason: "stop".to_string(),
-            }],
         };
 
-        (StatusCode::OK, Json(response)).into_response()
+        let response = app
+            .clone()
+            .oneshot(completion(serde_json::json!([
+                {"role": "system", "content": "Answer in French."},
+                {"role": "user", "content": "My name is Ada."},
+                {"role": "assistant", "content": "Enchanté, Ada."},
+                {"role": "user", "content": "What is my name?"},
+            ])))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *model.0.lock().unwrap(),
+            [
+                (Role::System, "Answer in French.".to_string()),
+                (Role::User, "My name is Ada.".to_string()),
+                (Role::Assistant, "Enchanté, Ada.".to_string()),
+                (Role::User, "What is my name?".to_string()),
+            ]
+        );
+
+        let response = app
+            .oneshot(completion(serde_json::json!([
+                {"role": "tool", "content": "42"},
+            ])))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_json_object_response_format() {
+        let app = router(test_state(None), DEFAULT_MAX_BODY_BYTES);
+        let completion = |stream: bool| {
+            let body = serde_json::json!({
+                "messages": [{"role": "user", "content": "Hi"}],
+                "stream": stream,
+                "response_format": {"type": "json_object"},
+            });
+            Request::post("/v1/chat/completions")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let json = body_json(app.clone().oneshot(completion(false)).await.unwrap()).await;
+        let content = json["choices"][0]["message"]["content"].as_str().unwrap();
+        let reply: serde_json::Value = serde_json::from_str(content).unwrap();
+        assert_eq!(reply["reply"], "Hello world");
+
+        let response = app.oneshot(completion(true)).await.unwrap();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let streamed: String = String::from_utf8(bytes.to_vec())
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter(|data| *data != "[DONE]")
+            .map(|data| {
+                let chunk: serde_json::Value = serde_json::from_str(data).unwrap();
+                chunk["choices"][0]["delta"]["content"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&streamed).unwrap(),
+            reply
+        );
+    }
+
+    #[tokio::test]
+    async fn test_completion_events_shape() {
+        let meta = CompletionMeta {
+            id: "cmpl-1".to_string(),
+            created: 1_700_000_000,
+            model: "qwen2.5-3b-instruct-q4_k_m".to_string(),
+            fingerprint: "fp_0123456789abcdef".to_string(),
+        };
+        let tokens = futures_util::stream::iter(vec![
+            Ok("Hello ".to_string()),
+            Ok("world".to_string()),
+            Err(anyhow::anyhow!("out of memory")),
+        ]);
+
+        let response = Sse::new(completion_events(tokens, meta)).into_response();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        let data: Vec<&str> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .collect();
+
+        assert_eq!(data.len(), 4, "{body}");
+        for (data, token) in data.iter().zip(["Hello ", "world"]) {
+            let chunk: serde_json::Value = serde_json::from_str(data).unwrap();
+            assert_eq!(
+                chunk,
+                serde_json::json!({
+                    "id": "cmpl-1",
+                    "object": "chat.completion.chunk",
+                    "created": 1_700_000_000,
+                    "model": "qwen2.5-3b-instruct-q4_k_m",
+                    "system_fingerprint": "fp_0123456789abcdef",
+                    "choices": [{
+                        "index": 0,
+                        "delta": {"role": "assistant", "content": token},
+                        "finish_reason": null,
+                    }],
+                })
+            );
+        }
+        assert_eq!(data[2], "[ERROR]: out of memory");
+        assert_eq!(data[3], "[DONE]");
     }
 }
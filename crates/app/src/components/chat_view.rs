@@ -1,4 +1,5 @@
 use crate::modules::api_client::ApiClient;
+use crate::modules::token_batcher::{TokenBatcher, FRAME_INTERVAL};
 use dioxus::prelude::*;
 
 #[component]
@@ -27,15 +28,26 @@ pub fn ChatView() -> Element {
                     let prompt_val = prompt.read().clone();
 
                     spawn(async move {
-                        let mut first_token = true;
-                        let result = api_client().generate_stream(prompt_val, move |token| {
-                            if first_token {
-                                output.set(token);
-                                first_token = false;
+                        let mut first_batch = true;
+                        let mut append = move |text: String| {
+                            if first_batch {
+                                output.set(text);
+                                first_batch = false;
                             } else {
-                                output.with_mut(|out| out.push_str(&token));
+                                output.with_mut(|out| out.push_str(&text));
+                            }
+                        };
+
+                        // Batch tokens per frame so the output isn't re-rendered on every token
+                        let mut batcher = TokenBatcher::new(FRAME_INTERVAL);
+                        let result = api_client().generate_stream(prompt_val, |token| {
+                            if let Some(batch) = batcher.push(&token) {
+                                append(batch);
                             }
                         }).await;
+                        if let Some(rest) = batcher.flush() {
+                            append(rest);
+                        }
 
                         if let Err(e) = result {
                             output.set(format!("API request failed: {}", e));
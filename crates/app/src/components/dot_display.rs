@@ -2,7 +2,9 @@
 mod render;
 use render::{GraphvizSvg, SvgBuildConfig};
 
+use crate::modules::dot_document::DotDocument;
 use dioxus::prelude::*;
+use graph_delta::commands::DotCommand;
 use graphvizm::Graphvizm;
 
 // read kitchen_sink.dot from assets
@@ -58,10 +60,21 @@ pub fn DotDisplay(dot_source: ReadSignal<String>) -> Element {
 // Example usage component with live editing
 #[component]
 pub fn GraphEditor() -> Element {
-    let mut dot_input = use_signal(|| {
-        let kitchen = KITCHEN_SINK_DOT.to_string();
-        kitchen
+    // The chunks are the source of truth; the text panel and the preview are views of them.
+    let mut document = use_signal(|| DotDocument::new(KITCHEN_SINK_DOT));
+    let dot_source = use_memo(move || document.read().valid_text().to_string());
+    let node_ids = use_memo(move || {
+        document
+            .read()
+            .chunks()
+            .iter()
+            .filter(|c| c.kind == "node" || c.kind == "bare_node")
+            .filter_map(|c| c.id.clone())
+            .collect::<Vec<_>>()
     });
+    let mut edge_from = use_signal(String::new);
+    let mut edge_to = use_signal(String::new);
+    let mut edit_error = use_signal(|| None::<String>);
 
     rsx! {
         div {
@@ -80,10 +93,63 @@ pub fn GraphEditor() -> Element {
                             focus:outline-none focus:ring-2 focus:ring-blue-500 focus:border-transparent
                             resize-none bg-white shadow-sm",
                     rows: 10,
-                    value: "{dot_input}",
-                    oninput: move |e| dot_input.set(e.value()),
+                    value: "{document.read().text()}",
+                    oninput: move |e| document.write().set_text(e.value()),
                     placeholder: "Enter your DOT graph here..."
                 }
+
+                if let Some(error) = document.read().error() {
+                    pre {
+                        class: "text-red-600 text-xs font-mono whitespace-pre-wrap p-2 bg-red-50 rounded",
+                        "{error}"
+                    }
+                }
+
+                // Structural edits go through the chunks and rewrite the graph body.
+                div {
+                    class: "flex gap-2",
+                    input {
+                        class: "flex-1 font-mono text-sm p-2 border border-gray-300 rounded-lg bg-white",
+                        list: "dot-node-ids",
+                        value: "{edge_from}",
+                        oninput: move |e| edge_from.set(e.value()),
+                        placeholder: "From"
+                    }
+                    input {
+                        class: "flex-1 font-mono text-sm p-2 border border-gray-300 rounded-lg bg-white",
+                        list: "dot-node-ids",
+                        value: "{edge_to}",
+                        oninput: move |e| edge_to.set(e.value()),
+                        placeholder: "To"
+                    }
+                    button {
+                        class: "px-4 py-2 text-sm font-semibold text-white bg-blue-600 rounded-lg hover:bg-blue-700",
+                        disabled: edge_from.read().is_empty() || edge_to.read().is_empty(),
+                        onclick: move |_| {
+                            let command = DotCommand::CreateEdge {
+                                from: edge_from(),
+                                to: edge_to(),
+                                attrs: None,
+                                parent: None,
+                            };
+                            edit_error.set(document.write().apply(&command).err());
+                        },
+                        "Add edge"
+                    }
+                    datalist {
+                        id: "dot-node-ids",
+                        for id in node_ids() {
+                            option { key: "{id}", value: "{id}" }
+                        }
+                    }
+                }
+
+                if let Some(error) = edit_error() {
+                    pre {
+                        class: "text-red-600 text-xs font-mono whitespace-pre-wrap p-2 bg-red-50 rounded",
+                        "{error}"
+                    }
+                }
             }
 
             div {
@@ -97,7 +163,7 @@ pub fn GraphEditor() -> Element {
                 div {
                     class: "flex-1 border border-gray-200 rounded-lg overflow-hidden bg-white shadow-sm",
                     DotDisplay {
-                        dot_source: dot_input
+                        dot_source
                     }
                 }
 
@@ -12,7 +12,9 @@ const API_ENDPOINT: &str = "/v1/chat/completions";
 pub struct ChatCompletionRequest {
     pub messages: Vec<ChatMessage>,
     pub stream: bool,
-    pub max_tokens: usize,
+    /// Left out by default so the server applies its shared default length.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -56,7 +58,7 @@ impl ApiClient {
                 content: prompt,
             }],
             stream: true,
-            max_tokens: 1000,
+            max_tokens: None,
         };
 
         let mut stream = self
@@ -71,15 +73,12 @@ impl ApiClient {
             match item {
                 Ok(bytes) => {
                     let s = String::from_utf8_lossy(&bytes);
-                    for line in s.lines().filter(|l| l.starts_with("data:")) {
-                        let data = &line["data: ".len()..];
-                        if data.trim() == "[DONE]" {
+                    for data in s.lines().filter_map(sse_data) {
+                        if data == "[DONE]" {
                             break;
                         }
-                        if let Ok(chunk) = serde_json::from_str::<ChatCompletionChunk>(data) {
-                            if let Some(choice) = chunk.choices.first() {
-                                on_token(choice.delta.content.clone());
-                            }
+                        if let Some(content) = chunk_content(data) {
+                            on_token(content);
                         }
                     }
                 }
@@ -93,3 +92,40 @@ impl ApiClient {
         Ok(())
     }
 }
+
+/// Payload of an SSE `data:` line, trimmed. The space after the colon is optional.
+fn sse_data(line: &str) -> Option<&str> {
+    line.strip_prefix("data:").map(str::trim)
+}
+
+/// Delta text of the first choice in a streamed completion chunk.
+fn chunk_content(data: &str) -> Option<String> {
+    let chunk = serde_json::from_str::<ChatCompletionChunk>(data).ok()?;
+    chunk
+        .choices
+        .into_iter()
+        .next()
+        .map(|choice| choice.delta.content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_streams(line: &str, expected: Option<&str>) {
+        let content = sse_data(line).and_then(chunk_content);
+        assert_eq!(content.as_deref(), expected, "line: {line:?}");
+    }
+
+    #[test]
+    fn test_sse_data_spacing() {
+        let chunk = r#"{"choices":[{"delta":{"role":"assistant","content":"Hi"}}]}"#;
+        assert_streams(&format!("data: {chunk}"), Some("Hi"));
+        assert_streams(&format!("data:{chunk}"), Some("Hi"));
+        assert_streams(&format!("data:  {chunk} "), Some("Hi"));
+        assert_streams(&format!("event: {chunk}"), None);
+
+        assert_eq!(sse_data("data:[DONE]"), Some("[DONE]"));
+        assert_eq!(sse_data("data: [DONE]"), Some("[DONE]"));
+    }
+}
@@ -0,0 +1,210 @@
+//! The DOT source shown in the editor, kept in sync with its parsed chunks.
+//!
+//! The chunk vector is the source of truth for structural edits: editing the text re-parses it
+//! into chunks, and applying a [DotCommand] regenerates the text from the chunks.
+use graph_delta::commands::{self, DotCommand};
+use graph_delta::parser::{self, Chunk, DotOptions};
+
+pub struct DotDocument {
+    text: String,
+    chunks: Vec<Chunk>,
+    /// The most recent text that parsed, so the preview keeps rendering while the user types.
+    valid_text: String,
+    error: Option<String>,
+}
+
+impl DotDocument {
+    pub fn new(text: impl Into<String>) -> Self {
+        let mut doc = Self {
+            text: String::new(),
+            chunks: Vec::new(),
+            valid_text: String::new(),
+            error: None,
+        };
+        doc.set_text(text);
+        doc
+    }
+
+    /// Replaces the text and re-parses it. On a parse error the previous chunks are kept and
+    /// the error is reported through [DotDocument::error].
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        self.text = text.into();
+        match parser::parse_dot_to_chunks(&self.text) {
+            Ok(chunks) => {
+                self.chunks = chunks;
+                self.valid_text = self.text.clone();
+                self.error = None;
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// Applies a structural edit to the chunks and regenerates the graph body from them. The
+    /// text around the body is kept as written: the `strict`/`graph`/`digraph` header with the
+    /// graph name, and anything before or after the graph. Comments inside the body are lost.
+    pub fn apply(&mut self, command: &DotCommand) -> Result<(), String> {
+        commands::apply_command(&mut self.chunks, command)?;
+        let text = splice_body(&self.valid_text, &self.chunks);
+        self.set_text(text);
+        Ok(())
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// DOT to render: the current text, or the last text that parsed if it currently doesn't.
+    pub fn valid_text(&self) -> &str {
+        &self.valid_text
+    }
+
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+/// `source` with the body of its first graph replaced by `chunks`, rendered. Without a body to
+/// replace, the chunks are rendered as a whole new graph.
+fn splice_body(source: &str, chunks: &[Chunk]) -> String {
+    let directed = parser::parse_all_graphs(source)
+        .ok()
+        .and_then(|graphs| graphs.first().map(|g| g.directed))
+        .unwrap_or(true);
+    let options = DotOptions {
+        undirected: !directed,
+        ..Default::default()
+    };
+    let rendered = parser::chunks_to_dot_with_options(chunks, None, &options);
+    let Some((open, close)) = body_braces(source) else {
+        return rendered;
+    };
+    let body = body_braces(&rendered)
+        .map(|(start, end)| &rendered[start + 1..end])
+        .unwrap_or_default();
+    format!("{}{}{}", &source[..=open], body, &source[close..])
+}
+
+/// Byte offsets of the `{` opening the first graph's body and the `}` closing it. Braces in
+/// quoted ids and comments are skipped.
+fn body_braces(source: &str) -> Option<(usize, usize)> {
+    let bytes = source.as_bytes();
+    let mut open = None;
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = source[i + 2..]
+                    .find("*/")
+                    .map_or(bytes.len(), |end| i + 2 + end + 1);
+            }
+            b'{' => {
+                open.get_or_insert(i);
+                depth += 1;
+            }
+            b'}' if open.is_some() => {
+                depth -= 1;
+                if depth == 0 {
+                    return open.map(|open| (open, i));
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_ids(doc: &DotDocument) -> Vec<&str> {
+        doc.chunks()
+            .iter()
+            .filter(|c| c.kind == "node")
+            .filter_map(|c| c.id.as_deref())
+            .collect()
+    }
+
+    #[test]
+    fn test_text_and_chunks_stay_in_sync() {
+        let mut doc = DotDocument::new("digraph G {\n    A [label=\"A\"];\n}");
+        assert_eq!(node_ids(&doc), vec!["A"]);
+
+        // Editing the text re-parses into chunks.
+        doc.set_text("digraph G {\n    A [label=\"A\"];\n    B [label=\"B\"];\n}");
+        assert_eq!(node_ids(&doc), vec!["A", "B"]);
+
+        // A broken edit is reported but keeps the last good chunks and preview.
+        doc.set_text("digraph G {\n    A [label=\"A\"");
+        assert!(doc.error().is_some());
+        assert_eq!(node_ids(&doc), vec!["A", "B"]);
+        assert!(doc.valid_text().contains("B [label=\"B\"]"));
+
+        // Structural edits update the text.
+        doc.apply(&DotCommand::CreateEdge {
+            from: "A".to_string(),
+            to: "B".to_string(),
+            attrs: None,
+            parent: None,
+        })
+        .unwrap();
+        assert!(doc.error().is_none());
+        assert!(doc.text().contains("A -> B"));
+        assert_eq!(doc.text(), doc.valid_text());
+    }
+
+    #[test]
+    fn test_apply_keeps_header_and_surrounding_text() {
+        let source =
+            "// Services\nstrict graph \"my {graph}\" {\n    A;\n    \"x y\":n  --  B;\n}\n// end\n";
+        let mut doc = DotDocument::new(source);
+        doc.apply(&DotCommand::CreateEdge {
+            from: "A".to_string(),
+            to: "B".to_string(),
+            attrs: None,
+            parent: None,
+        })
+        .unwrap();
+
+        let text = doc.text();
+        assert!(
+            text.starts_with("// Services\nstrict graph \"my {graph}\" {\n"),
+            "{text}"
+        );
+        assert!(text.ends_with("}\n// end\n"), "{text}");
+        assert!(text.contains("    A -- B;\n"), "{text}");
+        assert!(text.contains("    \"x y\":n -- B;\n"), "{text}");
+        assert!(!text.contains("->"), "{text}");
+        assert!(doc.error().is_none());
+    }
+
+    #[test]
+    fn test_body_braces() {
+        assert_eq!(body_braces("digraph G { A; }"), Some((10, 15)));
+        assert_eq!(
+            body_braces("/* { */ digraph { A [label=\"}\"]; subgraph { B; } }"),
+            Some((16, 49))
+        );
+        assert_eq!(body_braces("digraph G"), None);
+    }
+}
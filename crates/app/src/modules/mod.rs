@@ -1,2 +1,4 @@
 pub mod api_client;
+pub mod dot_document;
 pub mod server_manager;
+pub mod token_batcher;
@@ -0,0 +1,90 @@
+//! Coalesces streamed tokens so the UI re-renders at most once per frame instead of once per token.
+use std::time::Duration;
+use web_time::Instant;
+
+/// Roughly one frame at 60Hz.
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+pub struct TokenBatcher {
+    buffer: String,
+    interval: Duration,
+    last_flush: Instant,
+}
+
+impl TokenBatcher {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            buffer: String::new(),
+            interval,
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// Buffers `token` and returns everything accumulated so far once `interval` has passed since
+    /// the last flush. Call [TokenBatcher::flush] when the stream ends to get the remainder.
+    pub fn push(&mut self, token: &str) -> Option<String> {
+        self.push_at(token, Instant::now())
+    }
+
+    fn push_at(&mut self, token: &str, now: Instant) -> Option<String> {
+        self.buffer.push_str(token);
+        if now.duration_since(self.last_flush) >= self.interval {
+            self.last_flush = now;
+            self.take()
+        } else {
+            None
+        }
+    }
+
+    /// Returns whatever is still buffered, if anything.
+    pub fn flush(&mut self) -> Option<String> {
+        self.last_flush = Instant::now();
+        self.take()
+    }
+
+    fn take(&mut self) -> Option<String> {
+        if self.buffer.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.buffer))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coalesces_rapid_pushes() {
+        let mut batcher = TokenBatcher::new(FRAME_INTERVAL);
+        let start = batcher.last_flush;
+
+        // Tokens arriving within one frame are held back...
+        assert_eq!(
+            batcher.push_at("Hel", start + Duration::from_millis(1)),
+            None
+        );
+        assert_eq!(
+            batcher.push_at("lo", start + Duration::from_millis(5)),
+            None
+        );
+        assert_eq!(
+            batcher.push_at(",", start + Duration::from_millis(10)),
+            None
+        );
+
+        // ...and released together once the frame has elapsed.
+        assert_eq!(
+            batcher.push_at(" wor", start + Duration::from_millis(17)),
+            Some("Hello, wor".to_string())
+        );
+
+        assert_eq!(
+            batcher.push_at("ld", start + Duration::from_millis(20)),
+            None
+        );
+        assert_eq!(batcher.flush(), Some("ld".to_string()));
+        assert_eq!(batcher.flush(), None);
+    }
+}
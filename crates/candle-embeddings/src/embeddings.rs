@@ -7,6 +7,11 @@ pub struct Embeddings {
 }
 
 impl Embeddings {
+    /// All embeddings, one per input sentence and in input order.
+    pub fn vectors(&self) -> &[Vec<f32>] {
+        &self.data
+    }
+
     /// Gets the nth embedding.
     pub fn get(&self, n: usize) -> Result<Tensor, Error> {
         if n >= self.data.len() {
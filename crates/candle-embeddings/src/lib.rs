@@ -2,7 +2,7 @@ mod error;
 pub use error::Error;
 
 mod model;
-pub use model::Model;
+pub use model::{Model, Params};
 
 #[cfg(feature = "tokio")]
 pub mod model_args;
@@ -15,7 +15,6 @@ pub use embeddings::Embeddings;
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::model::Params;
 
     // Hub root (default): ~/.cache/huggingface/hub
     // Token file (default): ~/.cache/huggingface/token
@@ -11,6 +11,15 @@ pub struct Params {
     pub(crate) normalize_embeddings: bool,
 }
 
+impl Params {
+    pub fn new(sentences: Vec<String>, normalize_embeddings: bool) -> Self {
+        Self {
+            sentences,
+            normalize_embeddings,
+        }
+    }
+}
+
 pub struct Model {
     bert: BertModel,
     tokenizer: Tokenizer,
@@ -50,6 +59,9 @@ impl Model {
     }
 
     /// Get embeddings for the given input sentences.
+    ///
+    /// Inference only: candle's BERT dropout is a no-op and nothing here samples, so the same
+    /// input always produces bit-identical embeddings.
     pub fn get_embeddings(&mut self, input: Params) -> Result<Embeddings, Error> {
         let sentences = input.sentences;
         let normalize_embeddings = input.normalize_embeddings;
@@ -104,3 +116,57 @@ impl Model {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_nn::VarMap;
+
+    /// A tiny randomly-initialised BERT with a word-level tokenizer, so no download is needed.
+    fn tiny_model() -> Model {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "vocab_size": 8, "hidden_size": 8, "num_hidden_layers": 1,
+                "num_attention_heads": 2, "intermediate_size": 16, "hidden_act": "gelu",
+                "hidden_dropout_prob": 0.5, "max_position_embeddings": 16,
+                "type_vocab_size": 2, "initializer_range": 0.02, "layer_norm_eps": 1e-12,
+                "pad_token_id": 0, "classifier_dropout": null, "model_type": "bert"
+            }"#,
+        )
+        .unwrap();
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        let bert = BertModel::load(vb, &config).unwrap();
+
+        let tokenizer = Tokenizer::from_bytes(
+            r#"{
+                "version": "1.0",
+                "pre_tokenizer": {"type": "Whitespace"},
+                "model": {
+                    "type": "WordLevel",
+                    "vocab": {"[PAD]": 0, "[UNK]": 1, "the": 2, "cat": 3, "sits": 4, "outside": 5},
+                    "unk_token": "[UNK]"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        Model { bert, tokenizer }
+    }
+
+    #[test]
+    fn test_embeddings_are_deterministic() {
+        let mut model = tiny_model();
+        let params = || Params {
+            sentences: vec!["the cat sits outside".to_string()],
+            normalize_embeddings: true,
+        };
+
+        let first = model.get_embeddings(params()).unwrap();
+        let second = model.get_embeddings(params()).unwrap();
+
+        let bits = |e: &Embeddings| -> Vec<u32> { e.data[0].iter().map(|x| x.to_bits()).collect() };
+        assert!(first.data[0].iter().all(|x| x.is_finite()));
+        assert_eq!(bits(&first), bits(&second));
+    }
+}
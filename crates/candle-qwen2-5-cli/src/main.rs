@@ -5,7 +5,7 @@ use std::io::Write;
 use tracing_chrome::ChromeLayerBuilder;
 use tracing_subscriber::prelude::*;
 
-use candle_qwen2_5_core::{ModelArgs, Qwen2Model, Which as CoreWhich};
+use candle_qwen2_5_core::{defaults, GenerationStats, ModelArgs, Qwen2Model, Which as CoreWhich};
 
 const DEFAULT_PROMPT: &str = "Write a Rust function to calculate the factorial of a given number.";
 
@@ -33,11 +33,11 @@ struct Args {
     prompt: Option<String>,
 
     /// Optionally use a prompt file instead of a prompt string.
-    #[arg(long)]
+    #[arg(long, conflicts_with = "prompt")]
     prompt_file: Option<String>,
 
     /// The length of the sample to generate (in tokens).
-    #[arg(short = 'n', long, default_value_t = 1000)]
+    #[arg(short = 'n', long, default_value_t = defaults::SAMPLE_LEN)]
     sample_len: usize,
 
     /// The tokenizer config in json format.
@@ -45,7 +45,7 @@ struct Args {
     tokenizer: Option<String>,
 
     /// The temperature used to generate samples, use 0 for greedy sampling.
-    #[arg(long, default_value_t = 0.8)]
+    #[arg(long, default_value_t = defaults::CLI_TEMPERATURE)]
     temperature: f64,
 
     /// Nucleus sampling probability cutoff.
@@ -57,7 +57,7 @@ struct Args {
     top_k: Option<usize>,
 
     /// The seed to use when generating random samples.
-    #[arg(long, default_value_t = 299792458)]
+    #[arg(long, default_value_t = defaults::SEED)]
     seed: u64,
 
     /// Enable tracing (generates a trace-timestamp.json file).
@@ -73,11 +73,11 @@ struct Args {
     cpu: bool,
 
     /// Penalty to be applied for repeating tokens, 1. means no penalty.
-    #[arg(long, default_value_t = 1.1)]
+    #[arg(long, default_value_t = defaults::REPEAT_PENALTY)]
     repeat_penalty: f32,
 
     /// The context size to consider for the repeat penalty.
-    #[arg(long, default_value_t = 64)]
+    #[arg(long, default_value_t = defaults::REPEAT_LAST_N)]
     repeat_last_n: usize,
 
     /// The model size to use.
@@ -87,6 +87,24 @@ struct Args {
     /// Log level (error, warn, info, debug, trace)
     #[arg(long, default_value = "info")]
     log_level: String,
+
+    /// Emit newline-delimited JSON (`{"token": ...}` per token, then `{"done": true, "stats": ...}`)
+    /// instead of plain text. Logs go to stderr so stdout stays parseable.
+    #[arg(long)]
+    ndjson: bool,
+}
+
+impl Args {
+    /// The prompt from `--prompt-file`, `--prompt`, or the default one.
+    fn prompt(&self) -> Result<String> {
+        if let Some(prompt_file) = &self.prompt_file {
+            Ok(std::fs::read_to_string(prompt_file)?)
+        } else if let Some(prompt) = &self.prompt {
+            Ok(prompt.clone())
+        } else {
+            Ok(DEFAULT_PROMPT.to_string())
+        }
+    }
 }
 
 impl From<Which> for CoreWhich {
@@ -113,7 +131,14 @@ async fn main() -> Result<()> {
             "trace" => tracing::Level::TRACE,
             _ => tracing::Level::INFO,
         };
-        tracing_subscriber::fmt().with_max_level(level).init();
+        if args.ndjson {
+            tracing_subscriber::fmt()
+                .with_max_level(level)
+                .with_writer(std::io::stderr)
+                .init();
+        } else {
+            tracing_subscriber::fmt().with_max_level(level).init();
+        }
     }
 
     let _guard = if args.tracing {
@@ -126,38 +151,42 @@ async fn main() -> Result<()> {
 
     tracing::info!("Starting Qwen2 CLI with model: {:?}", args.model);
 
-    let model_args = ModelArgs {
-        model: args.model,
-        sample_len: args.sample_len,
-        tokenizer: args.tokenizer,
-        temperature: args.temperature,
-        top_p: args.top_p,
-        top_k: args.top_k,
-        seed: args.seed,
-        tracing: args.tracing,
-        split_prompt: args.split_prompt,
-        cpu: args.cpu,
-        repeat_penalty: args.repeat_penalty,
-        repeat_last_n: args.repeat_last_n,
-        which: args.which.into(),
-    };
+    // Read the prompt before the (slow) model load so a bad path fails fast.
+    let prompt_str = args.prompt()?;
+
+    let model_args = ModelArgs::builder()
+        .model(args.model)
+        .sample_len(args.sample_len)
+        .tokenizer(args.tokenizer)
+        .temperature(args.temperature)
+        .top_p(args.top_p)
+        .top_k(args.top_k)
+        .seed(args.seed)
+        .tracing(args.tracing)
+        .split_prompt(args.split_prompt)
+        .cpu(args.cpu)
+        .repeat_penalty(args.repeat_penalty)
+        .repeat_last_n(args.repeat_last_n)
+        .which(args.which.into())
+        .build();
 
     let mut model = Qwen2Model::new(&model_args).await?;
 
-    // prompt ir either from prompt arg or prompt file
-    let prompt_str = if let Some(prompt_file) = args.prompt_file {
-        std::fs::read_to_string(prompt_file)?
-    } else if let Some(prompt) = args.prompt {
-        prompt
-    } else {
-        DEFAULT_PROMPT.to_string()
-    };
+    if args.ndjson {
+        let mut on_token = |token: String| write_ndjson_token(&mut std::io::stdout(), &token);
+        let stats = model.generate(&prompt_str, model_args.sample_len, &mut on_token)?;
+        model.finish_generation(&mut on_token)?;
+        write_ndjson_done(&mut std::io::stdout(), &stats)?;
+        return Ok(());
+    }
 
-    let stats = model.generate(&prompt_str, model_args.sample_len, |token| {
+    let mut on_token = |token: String| -> Result<()> {
         print!("{token}");
         std::io::stdout().flush()?;
         Ok(())
-    })?;
+    };
+    let stats = model.generate(&prompt_str, model_args.sample_len, &mut on_token)?;
+    model.finish_generation(&mut on_token)?;
 
     println!(
         "\n\n{:4} prompt tokens processed: {:.2} token/s",
@@ -172,3 +201,82 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+fn write_ndjson_token(out: &mut impl Write, token: &str) -> Result<()> {
+    writeln!(out, "{}", serde_json::json!({ "token": token }))?;
+    out.flush()?;
+    Ok(())
+}
+
+fn write_ndjson_done(out: &mut impl Write, stats: &GenerationStats) -> Result<()> {
+    writeln!(
+        out,
+        "{}",
+        serde_json::json!({ "done": true, "stats": stats.to_json() })
+    )?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_prompt_file() {
+        let path = std::env::temp_dir().join(format!("qwen-prompt-{}.txt", std::process::id()));
+        std::fs::write(&path, "Summarize this\nlong prompt.").unwrap();
+        let path_str = path.to_str().unwrap();
+
+        let args = Args::try_parse_from(["cli", "--prompt-file", path_str]).unwrap();
+        assert_eq!(args.prompt().unwrap(), "Summarize this\nlong prompt.");
+
+        let both = Args::try_parse_from(["cli", "--prompt", "hi", "--prompt-file", path_str]);
+        assert!(both.is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let args = Args::try_parse_from(["cli"]).unwrap();
+        assert_eq!(args.prompt().unwrap(), DEFAULT_PROMPT);
+    }
+
+    #[test]
+    fn test_shared_defaults() {
+        let args = Args::try_parse_from(["cli"]).unwrap();
+        assert_eq!(args.sample_len, defaults::SAMPLE_LEN);
+        assert_eq!(args.temperature, defaults::CLI_TEMPERATURE);
+        assert_eq!(args.seed, defaults::SEED);
+        assert_eq!(args.repeat_penalty, defaults::REPEAT_PENALTY);
+        assert_eq!(args.repeat_last_n, defaults::REPEAT_LAST_N);
+    }
+
+    #[test]
+    fn test_ndjson_output() {
+        let mut out = Vec::new();
+        for token in ["Hello", ",", " \"world\"\n"] {
+            write_ndjson_token(&mut out, token).unwrap();
+        }
+        let stats = GenerationStats {
+            prompt_tokens: 4,
+            prompt_processing_time: Duration::from_millis(100),
+            generated_tokens: 3,
+            generation_time: Duration::from_millis(300),
+        };
+        write_ndjson_done(&mut out, &stats).unwrap();
+
+        let output = String::from_utf8(out).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+
+        let tokens: String = lines[..3]
+            .iter()
+            .map(|l| l["token"].as_str().unwrap())
+            .collect();
+        assert_eq!(tokens, "Hello, \"world\"\n");
+        assert_eq!(lines[3]["done"], true);
+        assert_eq!(lines[3]["stats"]["generated_tokens"], 3);
+    }
+}
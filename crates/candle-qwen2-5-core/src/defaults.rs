@@ -0,0 +1,14 @@
+//! Sampling defaults shared by [crate::ModelArgs::default], the CLI flags and the api-server, so
+//! every front end starts from the same settings.
+
+/// Tokens to generate when the caller does not say.
+pub const SAMPLE_LEN: usize = 1000;
+/// Greedy decoding.
+pub const TEMPERATURE: f64 = 0.0;
+/// The CLI samples instead, so running one prompt again gives a different answer.
+pub const CLI_TEMPERATURE: f64 = 0.8;
+pub const SEED: u64 = 299792458;
+/// Penalty for repeating tokens, 1.0 means no penalty.
+pub const REPEAT_PENALTY: f32 = 1.1;
+/// Context size for the repeat penalty.
+pub const REPEAT_LAST_N: usize = 64;
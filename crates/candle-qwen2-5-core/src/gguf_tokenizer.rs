@@ -0,0 +1,153 @@
+//! Rebuilds a Hugging Face tokenizer from the `tokenizer.ggml.*` metadata embedded in GGUF files.
+//!
+//! Only the `gpt2` (byte-level BPE) tokenizer model used by Qwen2 is supported. The GGUF does not
+//! store the pre-tokenizer, so Qwen2's split regex is assumed: the result matches the Hub
+//! `tokenizer.json` for Qwen2.5 models but may split differently for other BPE vocabularies.
+use anyhow::{bail, Context, Result};
+use candle::quantized::gguf_file::{Content, Value};
+use serde_json::json;
+use std::collections::HashMap;
+use tokenizers::Tokenizer;
+
+/// Pre-tokenizer split pattern from the Qwen2 `tokenizer.json`.
+const QWEN2_SPLIT_REGEX: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// GGUF token type of control tokens such as `<|im_end|>`.
+const TOKEN_TYPE_CONTROL: i32 = 3;
+
+/// Builds a [Tokenizer] from the vocabulary, merges and token types stored in the GGUF.
+pub fn tokenizer_from_gguf(content: &Content) -> Result<Tokenizer> {
+    tokenizer_from_metadata(&content.metadata)
+}
+
+fn strings(value: &Value) -> Result<Vec<String>> {
+    Ok(value
+        .to_vec()?
+        .iter()
+        .map(|v| v.to_string().cloned())
+        .collect::<candle::Result<_>>()?)
+}
+
+fn tokenizer_from_metadata(metadata: &HashMap<String, Value>) -> Result<Tokenizer> {
+    let get = |key: &str| {
+        metadata
+            .get(key)
+            .with_context(|| format!("GGUF metadata has no {key}"))
+    };
+
+    let model = get("tokenizer.ggml.model")?.to_string()?;
+    if model != "gpt2" {
+        bail!(
+            "unsupported GGUF tokenizer model '{model}', only gpt2 (byte-level BPE) is supported"
+        );
+    }
+    let tokens = strings(get("tokenizer.ggml.tokens")?)?;
+    let merges = strings(get("tokenizer.ggml.merges")?)?;
+    let token_types = match metadata.get("tokenizer.ggml.token_type") {
+        Some(types) => types
+            .to_vec()?
+            .iter()
+            .map(Value::to_i32)
+            .collect::<candle::Result<Vec<_>>>()?,
+        None => Vec::new(),
+    };
+
+    let vocab: serde_json::Map<String, serde_json::Value> = tokens
+        .iter()
+        .enumerate()
+        .map(|(id, token)| (token.clone(), json!(id)))
+        .collect();
+    let added_tokens: Vec<serde_json::Value> = tokens
+        .iter()
+        .enumerate()
+        .filter(|(id, _)| token_types.get(*id) == Some(&TOKEN_TYPE_CONTROL))
+        .map(|(id, token)| {
+            json!({
+                "id": id, "content": token, "single_word": false, "lstrip": false,
+                "rstrip": false, "normalized": false, "special": true
+            })
+        })
+        .collect();
+    let byte_level = json!({
+        "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": false, "use_regex": false
+    });
+
+    let config = json!({
+        "version": "1.0",
+        "added_tokens": added_tokens,
+        "normalizer": {"type": "NFC"},
+        "pre_tokenizer": {
+            "type": "Sequence",
+            "pretokenizers": [
+                {
+                    "type": "Split",
+                    "pattern": {"Regex": QWEN2_SPLIT_REGEX},
+                    "behavior": "Isolated",
+                    "invert": false
+                },
+                byte_level
+            ]
+        },
+        "post_processor": byte_level,
+        "decoder": byte_level,
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "fuse_unk": false,
+            "byte_fallback": false,
+            "vocab": vocab,
+            "merges": merges
+        }
+    });
+
+    Tokenizer::from_bytes(serde_json::to_vec(&config)?).map_err(anyhow::Error::msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string_array(items: &[&str]) -> Value {
+        Value::Array(items.iter().map(|s| Value::String(s.to_string())).collect())
+    }
+
+    #[test]
+    fn test_tokenizer_from_metadata() {
+        let metadata = HashMap::from([
+            (
+                "tokenizer.ggml.model".to_string(),
+                Value::String("gpt2".to_string()),
+            ),
+            (
+                "tokenizer.ggml.tokens".to_string(),
+                string_array(&["h", "i", "hi", "<|im_end|>"]),
+            ),
+            ("tokenizer.ggml.merges".to_string(), string_array(&["h i"])),
+            (
+                "tokenizer.ggml.token_type".to_string(),
+                Value::Array(vec![
+                    Value::I32(1),
+                    Value::I32(1),
+                    Value::I32(1),
+                    Value::I32(3),
+                ]),
+            ),
+        ]);
+
+        let tokenizer = tokenizer_from_metadata(&metadata).unwrap();
+        let encoding = tokenizer.encode("hi<|im_end|>", false).unwrap();
+        assert_eq!(encoding.get_ids(), &[2, 3]);
+        assert_eq!(tokenizer.decode(&[2, 3], true).unwrap(), "hi");
+        assert_eq!(tokenizer.get_vocab(true).get("<|im_end|>"), Some(&3));
+    }
+
+    #[test]
+    fn test_rejects_non_bpe_tokenizer() {
+        let metadata = HashMap::from([(
+            "tokenizer.ggml.model".to_string(),
+            Value::String("llama".to_string()),
+        )]);
+        assert!(tokenizer_from_metadata(&metadata).is_err());
+    }
+}
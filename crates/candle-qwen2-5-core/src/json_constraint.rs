@@ -0,0 +1,268 @@
+//! Incremental JSON validation used by [crate::Qwen2Model::generate_constrained] to keep the
+//! output a valid prefix of a single JSON object, token by token.
+
+/// Output shapes [crate::Qwen2Model::generate_constrained] can enforce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grammar {
+    /// One JSON object (`{...}`), as in OpenAI's `response_format: {"type": "json_object"}`.
+    JsonObject,
+}
+
+impl Grammar {
+    pub fn validator(&self) -> JsonPrefix {
+        match self {
+            Grammar::JsonObject => JsonPrefix::object(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Object,
+    Array,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Number {
+    Minus,
+    Zero,
+    Int,
+    Dot,
+    Frac,
+    Exp,
+    ExpSign,
+    ExpDigits,
+}
+
+impl Number {
+    /// Whether the number may end here.
+    fn is_complete(self) -> bool {
+        matches!(
+            self,
+            Number::Zero | Number::Int | Number::Frac | Number::ExpDigits
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Before the top-level object.
+    Start,
+    /// After `:`, or after `,` in an array.
+    Value,
+    /// After `[`.
+    ValueOrClose,
+    /// After `{`.
+    KeyOrClose,
+    /// After `,` in an object.
+    Key,
+    /// After a key.
+    Colon,
+    /// After a value inside a container.
+    AfterValue,
+    /// Inside a string; `escape` is `Some(n)` after a backslash (`n` hex digits still due for
+    /// `\u`, 0 for a one-character escape).
+    Str {
+        key: bool,
+        escape: Option<u8>,
+    },
+    Number(Number),
+    /// Inside `true`, `false` or `null`, with the characters still expected.
+    Literal(&'static str),
+    /// The top-level object is closed; only whitespace may follow.
+    Done,
+}
+
+/// Tracks whether the text fed so far is a prefix of a valid JSON document of one object.
+#[derive(Debug, Clone)]
+pub struct JsonPrefix {
+    stack: Vec<Container>,
+    state: State,
+}
+
+impl JsonPrefix {
+    /// Accepts a single JSON object, with optional surrounding whitespace.
+    pub fn object() -> Self {
+        Self {
+            stack: Vec::new(),
+            state: State::Start,
+        }
+    }
+
+    /// True once the top-level object has been closed.
+    pub fn is_complete(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Feeds `text`, returning false (and leaving `self` in an unspecified state) if it cannot
+    /// continue a valid document. Clone first to try a candidate.
+    pub fn push_str(&mut self, text: &str) -> bool {
+        text.chars().all(|c| self.push(c))
+    }
+
+    fn push(&mut self, c: char) -> bool {
+        let ws = matches!(c, ' ' | '\t' | '\n' | '\r');
+        match self.state {
+            State::Start => match c {
+                '{' => self.open(Container::Object),
+                _ => ws,
+            },
+            State::Done => ws,
+            State::Value => ws || self.start_value(c),
+            State::ValueOrClose => {
+                ws || c == ']' && self.close(Container::Array) || self.start_value(c)
+            }
+            State::KeyOrClose => match c {
+                '"' => self.set(State::Str {
+                    key: true,
+                    escape: None,
+                }),
+                '}' => self.close(Container::Object),
+                _ => ws,
+            },
+            State::Key => match c {
+                '"' => self.set(State::Str {
+                    key: true,
+                    escape: None,
+                }),
+                _ => ws,
+            },
+            State::Colon => match c {
+                ':' => self.set(State::Value),
+                _ => ws,
+            },
+            State::AfterValue => match (c, self.stack.last()) {
+                (',', Some(Container::Object)) => self.set(State::Key),
+                (',', Some(Container::Array)) => self.set(State::Value),
+                ('}', _) => self.close(Container::Object),
+                (']', _) => self.close(Container::Array),
+                _ => ws,
+            },
+            State::Str { key, escape } => match (escape, c) {
+                (None, '"') => self.set(if key { State::Colon } else { State::AfterValue }),
+                (None, '\\') => self.set(State::Str {
+                    key,
+                    escape: Some(0),
+                }),
+                // Raw control characters must be escaped.
+                (None, c) => c as u32 >= 0x20,
+                (Some(0), 'u') => self.set(State::Str {
+                    key,
+                    escape: Some(4),
+                }),
+                (Some(0), '"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't') => {
+                    self.set(State::Str { key, escape: None })
+                }
+                (Some(0), _) => false,
+                (Some(n), c) if c.is_ascii_hexdigit() => {
+                    let escape = if n == 1 { None } else { Some(n - 1) };
+                    self.set(State::Str { key, escape })
+                }
+                (Some(_), _) => false,
+            },
+            State::Number(number) => {
+                let next = match (number, c) {
+                    (Number::Minus, '0') => Some(Number::Zero),
+                    (Number::Minus, '1'..='9') => Some(Number::Int),
+                    (Number::Int, '0'..='9') => Some(Number::Int),
+                    (Number::Zero | Number::Int, '.') => Some(Number::Dot),
+                    (Number::Dot | Number::Frac, '0'..='9') => Some(Number::Frac),
+                    (Number::Zero | Number::Int | Number::Frac, 'e' | 'E') => Some(Number::Exp),
+                    (Number::Exp, '+' | '-') => Some(Number::ExpSign),
+                    (Number::Exp | Number::ExpSign | Number::ExpDigits, '0'..='9') => {
+                        Some(Number::ExpDigits)
+                    }
+                    _ => None,
+                };
+                match next {
+                    Some(next) => self.set(State::Number(next)),
+                    // The number ended; `c` belongs to whatever follows it.
+                    None if number.is_complete() => {
+                        self.state = State::AfterValue;
+                        self.push(c)
+                    }
+                    None => false,
+                }
+            }
+            State::Literal(rest) => match rest.strip_prefix(c) {
+                Some("") => self.set(State::AfterValue),
+                Some(rest) => self.set(State::Literal(rest)),
+                None => false,
+            },
+        }
+    }
+
+    fn set(&mut self, state: State) -> bool {
+        self.state = state;
+        true
+    }
+
+    fn start_value(&mut self, c: char) -> bool {
+        match c {
+            '{' => self.open(Container::Object),
+            '[' => self.open(Container::Array),
+            '"' => self.set(State::Str {
+                key: false,
+                escape: None,
+            }),
+            '-' => self.set(State::Number(Number::Minus)),
+            '0' => self.set(State::Number(Number::Zero)),
+            '1'..='9' => self.set(State::Number(Number::Int)),
+            't' => self.set(State::Literal("rue")),
+            'f' => self.set(State::Literal("alse")),
+            'n' => self.set(State::Literal("ull")),
+            _ => false,
+        }
+    }
+
+    fn open(&mut self, container: Container) -> bool {
+        self.stack.push(container);
+        self.set(match container {
+            Container::Object => State::KeyOrClose,
+            Container::Array => State::ValueOrClose,
+        })
+    }
+
+    fn close(&mut self, container: Container) -> bool {
+        if self.stack.pop() != Some(container) {
+            return false;
+        }
+        self.set(if self.stack.is_empty() {
+            State::Done
+        } else {
+            State::AfterValue
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepts(text: &str) -> Option<bool> {
+        let mut json = JsonPrefix::object();
+        json.push_str(text).then(|| json.is_complete())
+    }
+
+    #[test]
+    fn test_json_prefix() {
+        let doc = r#" {"a": [1, -0.5e+3, true, null, {"b": "x\"é"}], "c": {}} "#;
+        assert_eq!(accepts(doc), Some(true));
+        // Every prefix of a valid document is accepted, but only the whole is complete.
+        for (i, _) in doc
+            .char_indices()
+            .filter(|(i, _)| *i < doc.trim_end().len() - 1)
+        {
+            assert_eq!(accepts(&doc[..i]), Some(false), "prefix {:?}", &doc[..i]);
+        }
+
+        assert_eq!(accepts("[1]"), None);
+        assert_eq!(accepts(r#"{"a" 1}"#), None);
+        assert_eq!(accepts(r#"{"a": 01}"#), None);
+        assert_eq!(accepts(r#"{"a": tru}"#), None);
+        assert_eq!(accepts(r#"{"a": 1,}"#), None);
+        assert_eq!(accepts(r#"{"a": [1}"#), None);
+        assert_eq!(accepts("{\"a\": \"line\nbreak\"}"), None);
+        assert_eq!(accepts("{} {}"), None);
+    }
+}
@@ -8,6 +8,14 @@ use candle_transformers::generation::{LogitsProcessor, Sampling};
 
 use candle_transformers::models::quantized_qwen2::ModelWeights as Qwen2;
 
+pub mod defaults;
+mod gguf_tokenizer;
+pub use gguf_tokenizer::tokenizer_from_gguf;
+mod json_constraint;
+pub use json_constraint::{Grammar, JsonPrefix};
+mod stop_sequences;
+use stop_sequences::StopSequences;
+
 #[derive(Clone, Debug, Copy, PartialEq, Eq)]
 pub enum Which {
     W25_0_5b,
@@ -16,7 +24,72 @@ pub enum Which {
     W25_7b,
 }
 
-#[derive(Debug)]
+impl Which {
+    /// Hub repo with the GGUF weights.
+    pub fn gguf_repo(&self) -> &'static str {
+        match self {
+            Which::W25_0_5b => "Qwen/Qwen2.5-0.5B-Instruct-GGUF",
+            Which::W25_1_5b => "Qwen/Qwen2.5-1.5B-Instruct-GGUF",
+            Which::W25_3b => "Qwen/Qwen2.5-3B-Instruct-GGUF",
+            Which::W25_7b => "Qwen/Qwen2.5-7B-Instruct-GGUF",
+        }
+    }
+
+    /// Hub repo to fetch `tokenizer.json` from. The GGUF repos don't always ship one, so this
+    /// is the original (non-GGUF) instruct repo.
+    pub fn tokenizer_repo(&self) -> &'static str {
+        match self {
+            Which::W25_0_5b => "Qwen/Qwen2.5-0.5B-Instruct",
+            Which::W25_1_5b => "Qwen/Qwen2.5-1.5B-Instruct",
+            Which::W25_3b => "Qwen/Qwen2.5-3B-Instruct",
+            Which::W25_7b => "Qwen/Qwen2.5-7B-Instruct",
+        }
+    }
+
+    /// Size as written in the GGUF file names, e.g. `0.5b`.
+    fn size(&self) -> &'static str {
+        match self {
+            Which::W25_0_5b => "0.5b",
+            Which::W25_1_5b => "1.5b",
+            Which::W25_3b => "3b",
+            Which::W25_7b => "7b",
+        }
+    }
+}
+
+/// Quantization of the GGUF weights, as published in the Qwen GGUF repos.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Default)]
+pub enum Quant {
+    Q2K,
+    Q3KM,
+    Q4_0,
+    #[default]
+    Q4KM,
+    Q5_0,
+    Q5KM,
+    Q6K,
+    Q8_0,
+    F16,
+}
+
+impl Quant {
+    /// The suffix used in the GGUF file names, e.g. `q4_k_m`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Quant::Q2K => "q2_k",
+            Quant::Q3KM => "q3_k_m",
+            Quant::Q4_0 => "q4_0",
+            Quant::Q4KM => "q4_k_m",
+            Quant::Q5_0 => "q5_0",
+            Quant::Q5KM => "q5_k_m",
+            Quant::Q6K => "q6_k",
+            Quant::Q8_0 => "q8_0",
+            Quant::F16 => "fp16",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ModelArgs {
     pub model: Option<String>,
     pub sample_len: usize,
@@ -31,41 +104,155 @@ pub struct ModelArgs {
     pub repeat_penalty: f32,
     pub repeat_last_n: usize,
     pub which: Which,
+    pub quant: Quant,
+    /// Drop special tokens (`<|im_start|>`, ...) from the decoded output.
+    pub skip_special_tokens: bool,
+    /// Let the tokenizer's post-processor add its special tokens (a BOS, say) when encoding
+    /// the prompt. Off by default: the chat template already writes every control token
+    /// Qwen expects, so anything added on top would be a duplicate.
+    pub add_special_tokens: bool,
+    /// Strings that end the generation when the model produces them. The output is cut just
+    /// before the first one, which itself is never passed to the callback.
+    pub stop: Vec<String>,
 }
 
 impl Default for ModelArgs {
     fn default() -> Self {
         Self {
             model: None,
-            sample_len: 1000,
+            sample_len: defaults::SAMPLE_LEN,
             tokenizer: None,
-            temperature: 0.0,
+            temperature: defaults::TEMPERATURE,
             top_p: None,
             top_k: None,
-            seed: 299792458,
+            seed: defaults::SEED,
             tracing: false,
             split_prompt: false,
             cpu: false,
-            repeat_penalty: 1.1,
-            repeat_last_n: 64,
+            repeat_penalty: defaults::REPEAT_PENALTY,
+            repeat_last_n: defaults::REPEAT_LAST_N,
             which: Which::W25_0_5b,
+            quant: Quant::default(),
+            skip_special_tokens: true,
+            add_special_tokens: false,
+            stop: Vec::new(),
         }
     }
 }
 
+impl ModelArgs {
+    /// Starts from [ModelArgs::default], so call sites only name the options they change.
+    pub fn builder() -> ModelArgsBuilder {
+        ModelArgsBuilder::default()
+    }
+}
+
+/// Fluent builder for [ModelArgs]: `ModelArgs::builder().which(Which::W25_3b).temperature(0.7).build()`.
+#[derive(Debug, Clone, Default)]
+pub struct ModelArgsBuilder {
+    args: ModelArgs,
+}
+
+impl ModelArgsBuilder {
+    /// GGUF file path; `None` downloads from the hub.
+    pub fn model(mut self, model: Option<String>) -> Self {
+        self.args.model = model;
+        self
+    }
+
+    pub fn sample_len(mut self, sample_len: usize) -> Self {
+        self.args.sample_len = sample_len;
+        self
+    }
+
+    /// tokenizer.json path; `None` downloads from the hub.
+    pub fn tokenizer(mut self, tokenizer: Option<String>) -> Self {
+        self.args.tokenizer = tokenizer;
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f64) -> Self {
+        self.args.temperature = temperature;
+        self
+    }
+
+    pub fn top_p(mut self, top_p: Option<f64>) -> Self {
+        self.args.top_p = top_p;
+        self
+    }
+
+    pub fn top_k(mut self, top_k: Option<usize>) -> Self {
+        self.args.top_k = top_k;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.args.seed = seed;
+        self
+    }
+
+    pub fn tracing(mut self, tracing: bool) -> Self {
+        self.args.tracing = tracing;
+        self
+    }
+
+    pub fn split_prompt(mut self, split_prompt: bool) -> Self {
+        self.args.split_prompt = split_prompt;
+        self
+    }
+
+    pub fn cpu(mut self, cpu: bool) -> Self {
+        self.args.cpu = cpu;
+        self
+    }
+
+    pub fn repeat_penalty(mut self, repeat_penalty: f32) -> Self {
+        self.args.repeat_penalty = repeat_penalty;
+        self
+    }
+
+    pub fn repeat_last_n(mut self, repeat_last_n: usize) -> Self {
+        self.args.repeat_last_n = repeat_last_n;
+        self
+    }
+
+    pub fn which(mut self, which: Which) -> Self {
+        self.args.which = which;
+        self
+    }
+
+    pub fn quant(mut self, quant: Quant) -> Self {
+        self.args.quant = quant;
+        self
+    }
+
+    pub fn skip_special_tokens(mut self, skip_special_tokens: bool) -> Self {
+        self.args.skip_special_tokens = skip_special_tokens;
+        self
+    }
+
+    pub fn add_special_tokens(mut self, add_special_tokens: bool) -> Self {
+        self.args.add_special_tokens = add_special_tokens;
+        self
+    }
+
+    pub fn stop(mut self, stop: Vec<String>) -> Self {
+        self.args.stop = stop;
+        self
+    }
+
+    pub fn build(self) -> ModelArgs {
+        self.args
+    }
+}
+
 impl ModelArgs {
     async fn tokenizer(&self) -> Result<Tokenizer> {
         let tokenizer_path = match &self.tokenizer {
             Some(config) => std::path::PathBuf::from(config),
             None => {
                 let api = Api::new()?;
-                let repo = match self.which {
-                    Which::W25_0_5b => "Qwen/Qwen2.5-0.5B-Instruct",
-                    Which::W25_1_5b => "Qwen/Qwen2.5-1.5B-Instruct",
-                    Which::W25_3b => "Qwen/Qwen2.5-3B-Instruct",
-                    Which::W25_7b => "Qwen/Qwen2.5-7B-Instruct",
-                };
-                let api = api.model(repo.to_string());
+                let api = api.model(self.which.tokenizer_repo().to_string());
                 api.get("tokenizer.json").await?
             }
         };
@@ -76,26 +263,15 @@ impl ModelArgs {
         let model_path = match &self.model {
             Some(config) => std::path::PathBuf::from(config),
             None => {
-                let (repo, filename) = match self.which {
-                    Which::W25_0_5b => (
-                        "Qwen/Qwen2.5-0.5B-Instruct-GGUF",
-                        "qwen2.5-0.5b-instruct-q4_k_m.gguf",
-                    ),
-                    Which::W25_1_5b => (
-                        "Qwen/Qwen2.5-1.5B-Instruct-GGUF",
-                        "qwen2.5-1.5b-instruct-q4_k_m.gguf",
-                    ),
-                    Which::W25_3b => (
-                        "Qwen/Qwen2.5-3B-Instruct-GGUF",
-                        "qwen2.5-3b-instruct-q4_k_m.gguf",
-                    ),
-                    Which::W25_7b => (
-                        "Qwen/Qwen2.5-7B-Instruct-GGUF",
-                        "qwen2.5-7b-instruct-q4_k_m.gguf",
-                    ),
-                };
+                let filename = format!(
+                    "qwen2.5-{}-instruct-{}.gguf",
+                    self.which.size(),
+                    self.quant.as_str()
+                );
                 let api = Api::new()?;
-                api.model(repo.to_string()).get(filename).await?
+                api.model(self.which.gguf_repo().to_string())
+                    .get(&filename)
+                    .await?
             }
         };
         Ok(model_path)
@@ -109,6 +285,9 @@ pub struct TokenOutputStream {
     tokens: Vec<u32>,
     prev_index: usize,
     current_index: usize,
+    skip_special_tokens: bool,
+    /// Bytes of an incomplete codepoint held back by [TokenOutputStream::next_token_utf8].
+    pending: Vec<u8>,
 }
 
 impl TokenOutputStream {
@@ -118,11 +297,19 @@ impl TokenOutputStream {
             tokens: Vec::new(),
             prev_index: 0,
             current_index: 0,
+            skip_special_tokens: true,
+            pending: Vec::new(),
         }
     }
 
+    /// Whether special tokens are dropped when decoding (the default) or kept in the text.
+    pub fn with_skip_special_tokens(mut self, skip_special_tokens: bool) -> Self {
+        self.skip_special_tokens = skip_special_tokens;
+        self
+    }
+
     fn decode(&self, tokens: &[u32]) -> candle::Result<String> {
-        match self.tokenizer.decode(tokens, true) {
+        match self.tokenizer.decode(tokens, self.skip_special_tokens) {
             Ok(str) => Ok(str),
             Err(err) => candle::bail!("cannot decode: {err}"),
         }
@@ -138,7 +325,9 @@ impl TokenOutputStream {
         };
         self.tokens.push(token);
         let text = self.decode(&self.tokens[self.prev_index..])?;
-        if text.len() > prev_text.len() {
+        // A trailing replacement character means the last token ended mid-codepoint; wait for
+        // the rest of it instead of emitting the placeholder.
+        if text.len() > prev_text.len() && !text.ends_with('\u{FFFD}') {
             let text = text.split_at(prev_text.len());
             self.prev_index = self.current_index;
             self.current_index = self.tokens.len();
@@ -164,11 +353,72 @@ impl TokenOutputStream {
         }
     }
 
+    /// Like [TokenOutputStream::next_token], but works on the raw bytes behind byte-level BPE
+    /// tokens: they are buffered until they form complete codepoints, so the text never
+    /// contains replacement characters. Bytes that cannot start or continue valid UTF-8 are an
+    /// error.
+    pub fn next_token_utf8(&mut self, token: u32) -> candle::Result<Option<String>> {
+        if let Some(added) = self.tokenizer.get_added_tokens_decoder().get(&token) {
+            if added.special && self.skip_special_tokens {
+                return Ok(None);
+            }
+            self.pending.extend_from_slice(added.content.as_bytes());
+        } else {
+            let Some(piece) = self.tokenizer.id_to_token(token) else {
+                candle::bail!("cannot decode: unknown token {token}")
+            };
+            let bytes: Option<Vec<u8>> = piece.chars().map(byte_level_byte).collect();
+            // Pieces outside the byte-level alphabet are plain text.
+            self.pending
+                .extend(bytes.unwrap_or_else(|| piece.into_bytes()));
+        }
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(err) => candle::bail!(
+                "invalid UTF-8 in model output: {:02x?}",
+                &self.pending[err.valid_up_to()..]
+            ),
+        };
+        if valid == 0 {
+            return Ok(None);
+        }
+        let rest = self.pending.split_off(valid);
+        let text = std::mem::replace(&mut self.pending, rest);
+        Ok(Some(String::from_utf8(text).map_err(candle::Error::wrap)?))
+    }
+
+    /// Errors if generation stopped in the middle of a codepoint fed to
+    /// [TokenOutputStream::next_token_utf8].
+    pub fn finish_utf8(&self) -> candle::Result<()> {
+        if !self.pending.is_empty() {
+            candle::bail!(
+                "incomplete UTF-8 at end of model output: {:02x?}",
+                self.pending
+            )
+        }
+        Ok(())
+    }
+
     pub fn tokenizer(&self) -> &tokenizers::Tokenizer {
         &self.tokenizer
     }
 }
 
+/// The byte a character of the GPT-2 byte-level alphabet stands for. Printable Latin-1 bytes
+/// are their own character, the rest map in order to U+0100 onwards.
+fn byte_level_byte(c: char) -> Option<u8> {
+    let printable = |b: u32| matches!(b, 0x21..=0x7e | 0xa1..=0xac | 0xae..=0xff);
+    let c = c as u32;
+    if c < 0x100 {
+        return printable(c).then_some(c as u8);
+    }
+    (0..0x100u32)
+        .filter(|&b| !printable(b))
+        .nth((c - 0x100) as usize)
+        .map(|b| b as u8)
+}
+
 pub fn device(cpu: bool) -> candle::Result<Device> {
     if cpu {
         Ok(Device::Cpu)
@@ -178,6 +428,7 @@ pub fn device(cpu: bool) -> candle::Result<Device> {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct GenerationStats {
     pub prompt_tokens: usize,
     pub prompt_processing_time: std::time::Duration,
@@ -185,15 +436,225 @@ pub struct GenerationStats {
     pub generation_time: std::time::Duration,
 }
 
+fn per_sec(tokens: usize, dt: std::time::Duration) -> f64 {
+    let secs = dt.as_secs_f64();
+    if secs > 0. {
+        tokens as f64 / secs
+    } else {
+        0.
+    }
+}
+
+impl GenerationStats {
+    /// Prompt processing throughput, 0 if it took no measurable time.
+    pub fn prompt_tokens_per_sec(&self) -> f64 {
+        per_sec(self.prompt_tokens, self.prompt_processing_time)
+    }
+
+    /// Sampling throughput, 0 if it took no measurable time.
+    pub fn generated_tokens_per_sec(&self) -> f64 {
+        per_sec(self.generated_tokens, self.generation_time)
+    }
+
+    /// Structured summary for logging pipelines: token counts, durations in milliseconds and
+    /// throughput in tokens per second.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "prompt_tokens": self.prompt_tokens,
+            "prompt_processing_ms": self.prompt_processing_time.as_secs_f64() * 1000.,
+            "prompt_tokens_per_sec": self.prompt_tokens_per_sec(),
+            "generated_tokens": self.generated_tokens,
+            "generation_ms": self.generation_time.as_secs_f64() * 1000.,
+            "generated_tokens_per_sec": self.generated_tokens_per_sec(),
+        })
+    }
+}
+
+/// [GenerationStats] plus the time spent on each generated token (forward pass + sampling),
+/// useful for finding latency spikes. Only collected by [Qwen2Model::generate_with_trace].
+#[derive(Debug, Clone)]
+pub struct TracedGenerationStats {
+    pub stats: GenerationStats,
+    /// One entry per generated token, so `token_times.len() == stats.generated_tokens`.
+    pub token_times: Vec<std::time::Duration>,
+}
+
+/// Picks the next token from a step's logits. [Qwen2Model] samples with candle's
+/// [LogitsProcessor] built from [ModelArgs]; implement this for anything it doesn't cover
+/// (typical sampling, mirostat, ...) and install it with [Qwen2Model::set_sampler].
+pub trait Sampler: Send {
+    fn sample(&mut self, logits: &Tensor) -> candle::Result<u32>;
+}
+
+impl Sampler for LogitsProcessor {
+    fn sample(&mut self, logits: &Tensor) -> candle::Result<u32> {
+        LogitsProcessor::sample(self, logits)
+    }
+}
+
+/// What was loaded, read from the GGUF metadata and tensor table. See [Qwen2Model::model_info].
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ModelInfo {
+    /// `general.architecture`, e.g. `qwen2`
+    pub architecture: Option<String>,
+    /// `general.name`
+    pub name: Option<String>,
+    /// Storage type of most of the tensors, e.g. `Q4K`. GGUF "Q4_K_M" files mix in a few Q6K
+    /// tensors, so this is the dominant one rather than the file name suffix.
+    pub quant: String,
+    /// `<arch>.context_length`, the trained context window in tokens
+    pub context_length: Option<usize>,
+    /// `<arch>.block_count`, the number of transformer layers
+    pub layer_count: Option<usize>,
+    /// `<arch>.embedding_length`
+    pub embedding_length: Option<usize>,
+    pub tensor_count: usize,
+}
+
+impl ModelInfo {
+    pub fn from_gguf(content: &gguf_file::Content) -> Self {
+        let string = |key: &str| {
+            content
+                .metadata
+                .get(key)
+                .and_then(|v| v.to_string().ok())
+                .cloned()
+        };
+        let architecture = string("general.architecture");
+        let number = |key: &str| {
+            let arch = architecture.as_deref()?;
+            let value = content.metadata.get(&format!("{arch}.{key}"))?;
+            value.to_u64().ok().map(|v| v as usize)
+        };
+
+        let mut dtype_counts: std::collections::HashMap<String, usize> = Default::default();
+        for info in content.tensor_infos.values() {
+            *dtype_counts
+                .entry(format!("{:?}", info.ggml_dtype))
+                .or_default() += 1;
+        }
+        let quant = dtype_counts
+            .into_iter()
+            .max_by(|(a, na), (b, nb)| na.cmp(nb).then_with(|| b.cmp(a)))
+            .map(|(dtype, _)| dtype)
+            .unwrap_or_default();
+
+        Self {
+            name: string("general.name"),
+            quant,
+            context_length: number("context_length"),
+            layer_count: number("block_count"),
+            embedding_length: number("embedding_length"),
+            tensor_count: content.tensor_infos.len(),
+            architecture,
+        }
+    }
+}
+
 pub struct Qwen2Model {
     model: Qwen2,
+    info: ModelInfo,
     device: Device,
     tokenizer: Tokenizer,
-    logits_processor: LogitsProcessor,
+    sampler: Box<dyn Sampler>,
     repeat_penalty: f32,
     repeat_last_n: usize,
     eos_token: u32,
     split_prompt: bool,
+    skip_special_tokens: bool,
+    add_special_tokens: bool,
+    stop: Vec<String>,
+    continuation: Option<Continuation>,
+}
+
+/// Speaker of one turn of a conversation passed to [Qwen2Model::generate_chat].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    /// The role name used in the ChatML template.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+/// `messages` in the Qwen2.5 ChatML template, followed by an open assistant turn for the reply.
+/// No default system prompt is added.
+fn chat_prompt(messages: &[(Role, String)]) -> String {
+    let mut prompt = String::new();
+    for (role, content) in messages {
+        prompt.push_str(&format!(
+            "<|im_start|>{}\n{content}<|im_end|>\n",
+            role.as_str()
+        ));
+    }
+    prompt.push_str("<|im_start|>assistant\n");
+    prompt
+}
+
+/// `prompt` as the only, user, turn of a conversation.
+fn user_turn(prompt: &str) -> [(Role, String); 1] {
+    [(Role::User, prompt.to_string())]
+}
+
+/// Token ids of [chat_prompt], see [ModelArgs::add_special_tokens].
+fn encode_prompt(
+    tokenizer: &Tokenizer,
+    messages: &[(Role, String)],
+    add_special_tokens: bool,
+) -> Result<Vec<u32>> {
+    let tokens = tokenizer
+        .encode(chat_prompt(messages), add_special_tokens)
+        .map_err(anyhow::Error::msg)?;
+    Ok(tokens.get_ids().to_vec())
+}
+
+/// Where the last generation stopped, for [Qwen2Model::continue_generation]. Text that may
+/// still change, the start of a character split across tokens or of a stop sequence, stays
+/// buffered here until the generation ends.
+struct Continuation {
+    tos: TokenOutputStream,
+    stop: StopSequences,
+    /// The constraint of [Qwen2Model::generate_constrained], kept when continuing.
+    json: Option<JsonPrefix>,
+    all_tokens: Vec<u32>,
+    /// Last sampled token, not yet fed to the model.
+    next_token: u32,
+    /// Position `next_token` will take in the KV cache.
+    pos: usize,
+}
+
+impl Continuation {
+    /// Decodes `token` and passes on the text that can't change anymore.
+    fn emit<F: FnMut(String) -> Result<()>>(&mut self, token: u32, callback: &mut F) -> Result<()> {
+        if let Some(t) = self.tos.next_token(token)? {
+            if let Some(t) = self.stop.push(&t) {
+                callback(t)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Passes on all buffered text, a split character lossily.
+    fn flush<F: FnMut(String) -> Result<()>>(&mut self, callback: &mut F) -> Result<()> {
+        if let Some(rest) = self.tos.decode_rest()? {
+            if let Some(rest) = self.stop.push(&rest) {
+                callback(rest)?;
+            }
+        }
+        if let Some(held) = self.stop.finish() {
+            callback(held)?;
+        }
+        Ok(())
+    }
 }
 
 impl Qwen2Model {
@@ -201,12 +662,19 @@ impl Qwen2Model {
         let device = device(args.cpu)?;
         let model_path = args.model().await?;
         let mut file = std::fs::File::open(&model_path)?;
-        let model = {
-            let model = gguf_file::Content::read(&mut file).map_err(|e| e.with_path(model_path))?;
-            Qwen2::from_gguf(model, &mut file, &device)?
-        };
+        let content = gguf_file::Content::read(&mut file).map_err(|e| e.with_path(model_path))?;
 
-        let tokenizer = args.tokenizer().await?;
+        // Some GGUF repos don't ship a tokenizer.json; the GGUF carries its own vocabulary.
+        let tokenizer = match args.tokenizer().await {
+            Ok(tokenizer) => tokenizer,
+            Err(err) if args.tokenizer.is_none() => {
+                tracing::warn!("Could not fetch tokenizer ({err}), rebuilding it from the GGUF");
+                tokenizer_from_gguf(&content)?
+            }
+            Err(err) => return Err(err),
+        };
+        let info = ModelInfo::from_gguf(&content);
+        let model = Qwen2::from_gguf(content, &mut file, &device)?;
         let logits_processor = {
             let temperature = args.temperature;
             let sampling = if temperature <= 0. {
@@ -226,50 +694,135 @@ impl Qwen2Model {
 
         Ok(Self {
             model,
+            info,
             device,
             tokenizer,
-            logits_processor,
+            sampler: Box::new(logits_processor),
             repeat_penalty: args.repeat_penalty,
             repeat_last_n: args.repeat_last_n,
             eos_token,
             split_prompt: args.split_prompt,
+            skip_special_tokens: args.skip_special_tokens,
+            add_special_tokens: args.add_special_tokens,
+            stop: args.stop.clone(),
+            continuation: None,
         })
     }
 
+    /// Architecture, quantization and size of the loaded GGUF.
+    pub fn model_info(&self) -> ModelInfo {
+        self.info.clone()
+    }
+
+    /// Replaces the sampler used for every following generation. The repeat penalty is still
+    /// applied to the logits before they reach it.
+    pub fn set_sampler(&mut self, sampler: impl Sampler + 'static) {
+        self.sampler = Box::new(sampler);
+    }
+
     pub fn estimate_prompt_tokens(&self, prompt: &str) -> Result<usize> {
-        let prompt_str = format!("<|im_start|>user\n{prompt}<|im_end|>\n<|im_start|>assistant\n");
-        let tokens = self
-            .tokenizer
-            .encode(prompt_str.as_str(), true)
-            .map_err(anyhow::Error::msg)?;
-        Ok(tokens.get_ids().len())
+        Ok(encode_prompt(&self.tokenizer, &user_turn(prompt), self.add_special_tokens)?.len())
     }
 
+    /// Replies to `prompt` as a single user turn, see [Qwen2Model::generate_chat].
     pub fn generate<F: FnMut(String) -> Result<()>>(
         &mut self,
         prompt: &str,
         sample_len: usize,
-        mut callback: F,
+        callback: F,
     ) -> Result<GenerationStats> {
-        tracing::info!("Generating with sample_len={sample_len}");
-        let mut tos = TokenOutputStream::new(self.tokenizer.clone());
-        let prompt_str = format!("<|im_start|>user\n{prompt}<|im_end|>\n<|im_start|>assistant\n");
+        self.generate_chat(&user_turn(prompt), sample_len, callback)
+    }
 
-        tracing::info!("Encoding prompt {prompt_str}");
+    /// Generates the assistant's reply to a whole conversation, e.g. a system prompt followed
+    /// by alternating user and assistant turns.
+    ///
+    /// If `sample_len` runs out before the reply ends, text that may still change is held back:
+    /// the start of a character split across tokens and anything that could begin a stop
+    /// sequence. [Qwen2Model::continue_generation] or [Qwen2Model::finish_generation] passes it
+    /// on.
+    pub fn generate_chat<F: FnMut(String) -> Result<()>>(
+        &mut self,
+        messages: &[(Role, String)],
+        sample_len: usize,
+        callback: F,
+    ) -> Result<GenerationStats> {
+        self.generate_inner(messages, sample_len, callback, None, None)
+    }
 
-        let tokens = self
-            .tokenizer
-            .encode(prompt_str.as_str(), true)
-            .map_err(anyhow::Error::msg)?;
+    /// Like [Qwen2Model::generate], but only samples tokens that keep the reply valid under
+    /// `grammar`, and stops as soon as the reply is complete. Each step asks the sampler a few
+    /// times with rejected tokens masked out, then falls back to the most likely valid token.
+    /// Tokens that decode to a partial UTF-8 sequence are never picked, so non-ASCII text in
+    /// strings is only produced through tokens carrying whole characters.
+    pub fn generate_constrained<F: FnMut(String) -> Result<()>>(
+        &mut self,
+        prompt: &str,
+        sample_len: usize,
+        grammar: Grammar,
+        callback: F,
+    ) -> Result<GenerationStats> {
+        self.generate_constrained_chat(&user_turn(prompt), sample_len, grammar, callback)
+    }
 
-        let tokens = tokens.get_ids();
+    /// [Qwen2Model::generate_constrained] for a whole conversation, as in
+    /// [Qwen2Model::generate_chat].
+    pub fn generate_constrained_chat<F: FnMut(String) -> Result<()>>(
+        &mut self,
+        messages: &[(Role, String)],
+        sample_len: usize,
+        grammar: Grammar,
+        callback: F,
+    ) -> Result<GenerationStats> {
+        self.generate_inner(
+            messages,
+            sample_len,
+            callback,
+            None,
+            Some(grammar.validator()),
+        )
+    }
+
+    /// Same as [Qwen2Model::generate], but also records the per-token timing trace.
+    pub fn generate_with_trace<F: FnMut(String) -> Result<()>>(
+        &mut self,
+        prompt: &str,
+        sample_len: usize,
+        callback: F,
+    ) -> Result<TracedGenerationStats> {
+        let mut token_times = Vec::with_capacity(sample_len);
+        let stats = self.generate_inner(
+            &user_turn(prompt),
+            sample_len,
+            callback,
+            Some(&mut token_times),
+            None,
+        )?;
+        Ok(TracedGenerationStats { stats, token_times })
+    }
+
+    fn generate_inner<F: FnMut(String) -> Result<()>>(
+        &mut self,
+        messages: &[(Role, String)],
+        sample_len: usize,
+        mut callback: F,
+        token_times: Option<&mut Vec<std::time::Duration>>,
+        mut json: Option<JsonPrefix>,
+    ) -> Result<GenerationStats> {
+        tracing::info!("Generating with sample_len={sample_len}");
+        // The KV cache is about to be overwritten.
+        self.continuation = None;
+        let tos = TokenOutputStream::new(self.tokenizer.clone())
+            .with_skip_special_tokens(self.skip_special_tokens);
+        tracing::info!("Encoding prompt {}", chat_prompt(messages));
+
+        let tokens = encode_prompt(&self.tokenizer, messages, self.add_special_tokens)?;
+        let tokens = tokens.as_slice();
 
         let to_sample = sample_len.saturating_sub(1);
 
         tracing::info!("Prompt encoded to {} tokens", tokens.len());
 
-        let mut all_tokens = vec![];
-
         let start_prompt_processing = std::time::Instant::now();
 
         tracing::info!(
@@ -277,21 +830,20 @@ impl Qwen2Model {
             start_prompt_processing.elapsed()
         );
 
-        let mut next_token = if !self.split_prompt {
+        let logits = if !self.split_prompt {
             let input = Tensor::new(tokens, &self.device)?.unsqueeze(0)?;
-            let logits = self.model.forward(&input, 0)?;
-            let logits = logits.squeeze(0)?;
-            self.logits_processor.sample(&logits)?
+            self.model.forward(&input, 0)?
         } else {
-            let mut next_token = 0;
+            // Only the last position's logits are sampled, so the sampler and the `json`
+            // constraint see exactly one token for the prompt either way.
+            let mut logits = None;
             for (pos, token) in tokens.iter().enumerate() {
                 let input = Tensor::new(&[*token], &self.device)?.unsqueeze(0)?;
-                let logits = self.model.forward(&input, pos)?;
-                let logits = logits.squeeze(0)?;
-                next_token = self.logits_processor.sample(&logits)?;
+                logits = Some(self.model.forward(&input, pos)?);
             }
-            next_token
+            logits.ok_or_else(|| anyhow::anyhow!("The prompt encoded to no tokens"))?
         };
+        let next_token = self.sample_next(&logits.squeeze(0)?, json.as_mut())?;
 
         tracing::info!(
             "Time {:?}: Finished prompt processing",
@@ -300,53 +852,759 @@ impl Qwen2Model {
 
         let prompt_dt = start_prompt_processing.elapsed();
 
-        all_tokens.push(next_token);
+        let mut state = Continuation {
+            tos,
+            stop: StopSequences::new(&self.stop),
+            json,
+            all_tokens: vec![next_token],
+            next_token,
+            pos: tokens.len(),
+        };
+        tracing::info!("Time {:?}: Sending first token after prompt", prompt_dt);
+        state.emit(next_token, &mut callback)?;
 
-        if let Some(t) = tos.next_token(next_token)? {
-            tracing::info!("Time {:?}: Sending first token after prompt", prompt_dt);
-            callback(t)?;
+        let start_post_prompt = std::time::Instant::now();
+        let sampled = self.sample_tokens(&mut state, to_sample, &mut callback, token_times)?;
+        let dt = start_post_prompt.elapsed();
+        self.continuation = Some(state);
+
+        Ok(GenerationStats {
+            prompt_tokens: tokens.len(),
+            prompt_processing_time: prompt_dt,
+            generated_tokens: sampled,
+            generation_time: dt,
+        })
+    }
+
+    /// Generates up to `additional_len` more tokens after the last [Qwen2Model::generate] (or
+    /// any other generation call), reusing its KV cache instead of re-feeding the context. The
+    /// tokens are the same as if the first call had been given the combined length.
+    ///
+    /// Fails if nothing was generated yet or the last generation ended: with the end-of-sequence
+    /// token, at a stop sequence or with its JSON complete. A `generate_constrained` reply stays
+    /// under its grammar. Text the last call held back is passed on here, so a character or a
+    /// stop sequence split across the two calls comes out as if generated in one. The returned
+    /// stats have no prompt tokens.
+    pub fn continue_generation<F: FnMut(String) -> Result<()>>(
+        &mut self,
+        additional_len: usize,
+        mut callback: F,
+    ) -> Result<GenerationStats> {
+        let Some(mut state) = self.continuation.take() else {
+            anyhow::bail!("No previous generation to continue");
+        };
+        if state.next_token == self.eos_token {
+            anyhow::bail!("The previous generation ended with the end-of-sequence token");
         }
+        if state.stop.is_stopped() {
+            anyhow::bail!("The previous generation ended at a stop sequence");
+        }
+        if state.json.as_ref().is_some_and(JsonPrefix::is_complete) {
+            anyhow::bail!("The previous generation completed its JSON");
+        }
+        let start = std::time::Instant::now();
+        let sampled = self.sample_tokens(&mut state, additional_len, &mut callback, None);
+        let dt = start.elapsed();
+        self.continuation = Some(state);
+        Ok(GenerationStats {
+            prompt_tokens: 0,
+            prompt_processing_time: std::time::Duration::ZERO,
+            generated_tokens: sampled?,
+            generation_time: dt,
+        })
+    }
 
-        let eos_token = self.eos_token;
+    /// Ends the last generation, passing on the text it held back when `sample_len` ran out, a
+    /// split character lossily. It can't be continued afterwards.
+    pub fn finish_generation<F: FnMut(String) -> Result<()>>(
+        &mut self,
+        mut callback: F,
+    ) -> Result<()> {
+        match self.continuation.take() {
+            Some(mut state) if !self.has_ended(&state) => state.flush(&mut callback),
+            _ => Ok(()),
+        }
+    }
 
-        let start_post_prompt = std::time::Instant::now();
+    /// Whether `state` ended with the end-of-sequence token, at a stop sequence or with its JSON
+    /// complete; its buffered text was passed on then.
+    fn has_ended(&self, state: &Continuation) -> bool {
+        state.next_token == self.eos_token
+            || state.stop.is_stopped()
+            || state.json.as_ref().is_some_and(JsonPrefix::is_complete)
+    }
 
+    /// Feeds `state.next_token` back into the model and samples up to `count` tokens, stopping
+    /// when the generation ends (see [Qwen2Model::has_ended]) and only then flushing the
+    /// buffered text. Returns how many were sampled.
+    fn sample_tokens<F: FnMut(String) -> Result<()>>(
+        &mut self,
+        state: &mut Continuation,
+        count: usize,
+        callback: &mut F,
+        mut token_times: Option<&mut Vec<std::time::Duration>>,
+    ) -> Result<usize> {
         let mut sampled = 0;
-        for _index in 0..to_sample {
-            let input = Tensor::new(&[next_token], &self.device)?.unsqueeze(0)?;
-            let logits = self.model.forward(&input, tokens.len() + sampled)?;
+        for _index in 0..count {
+            if self.has_ended(state) {
+                break;
+            }
+            let token_start = token_times.is_some().then(std::time::Instant::now);
+            let input = Tensor::new(&[state.next_token], &self.device)?.unsqueeze(0)?;
+            let logits = self.model.forward(&input, state.pos)?;
             let logits = logits.squeeze(0)?;
             let logits = if self.repeat_penalty == 1. {
                 logits
             } else {
-                let start_at = all_tokens.len().saturating_sub(self.repeat_last_n);
+                let start_at = state.all_tokens.len().saturating_sub(self.repeat_last_n);
                 candle_transformers::utils::apply_repeat_penalty(
                     &logits,
                     self.repeat_penalty,
-                    &all_tokens[start_at..],
+                    &state.all_tokens[start_at..],
                 )?
             };
-            next_token = self.logits_processor.sample(&logits)?;
-            all_tokens.push(next_token);
-            if let Some(t) = tos.next_token(next_token)? {
-                callback(t)?;
+            state.pos += 1;
+            state.next_token = self.sample_next(&logits, state.json.as_mut())?;
+            if let (Some(times), Some(start)) = (token_times.as_deref_mut(), token_start) {
+                times.push(start.elapsed());
             }
+            state.all_tokens.push(state.next_token);
+            state.emit(state.next_token, callback)?;
             sampled += 1;
-            if next_token == eos_token {
-                break;
+        }
+
+        if self.has_ended(state) {
+            state.flush(callback)?;
+        }
+        Ok(sampled)
+    }
+
+    /// Samples the next token. With a `json` constraint, tokens that would break it are masked
+    /// out and the accepted token is fed into it.
+    fn sample_next(&mut self, logits: &Tensor, json: Option<&mut JsonPrefix>) -> Result<u32> {
+        const RESAMPLES: usize = 16;
+
+        let Some(json) = json else {
+            return Ok(self.sampler.sample(logits)?);
+        };
+        let mut masked: Option<Vec<f32>> = None;
+        for _ in 0..RESAMPLES {
+            let token = match &masked {
+                None => self.sampler.sample(logits)?,
+                Some(values) => self
+                    .sampler
+                    .sample(&Tensor::new(values.as_slice(), logits.device())?)?,
             };
+            if self.accept_json_token(json, token) {
+                return Ok(token);
+            }
+            let values = match masked.as_mut() {
+                Some(values) => values,
+                None => masked.insert(logits.to_dtype(candle::DType::F32)?.to_vec1()?),
+            };
+            values[token as usize] = f32::NEG_INFINITY;
+        }
+
+        let values = match masked {
+            Some(values) => values,
+            None => logits.to_dtype(candle::DType::F32)?.to_vec1()?,
+        };
+        let mut by_logit: Vec<u32> = (0..values.len() as u32).collect();
+        by_logit.sort_by(|a, b| values[*b as usize].total_cmp(&values[*a as usize]));
+        by_logit
+            .into_iter()
+            .find(|&token| self.accept_json_token(json, token))
+            .ok_or_else(|| anyhow::anyhow!("No token can continue the constrained output"))
+    }
+
+    fn accept_json_token(&self, json: &mut JsonPrefix, token: u32) -> bool {
+        if token == self.eos_token {
+            return json.is_complete();
+        }
+        let Ok(text) = self.tokenizer.decode(&[token], true) else {
+            return false;
+        };
+        // Empty for other special tokens; U+FFFD for a partial UTF-8 sequence.
+        if text.is_empty() || text.contains('\u{FFFD}') {
+            return false;
+        }
+        let mut next = json.clone();
+        if next.push_str(&text) {
+            *json = next;
+            true
+        } else {
+            false
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_shared_defaults() {
+        assert_eq!(defaults::SAMPLE_LEN, 1000);
+        assert_eq!(defaults::TEMPERATURE, 0.0);
+        assert_eq!(defaults::CLI_TEMPERATURE, 0.8);
+        assert_eq!(defaults::SEED, 299792458);
+        assert_eq!(defaults::REPEAT_PENALTY, 1.1);
+        assert_eq!(defaults::REPEAT_LAST_N, 64);
+
+        let args = ModelArgs::default();
+        assert_eq!(args.sample_len, defaults::SAMPLE_LEN);
+        assert_eq!(args.temperature, defaults::TEMPERATURE);
+        assert_eq!(args.seed, defaults::SEED);
+        assert_eq!(args.repeat_penalty, defaults::REPEAT_PENALTY);
+        assert_eq!(args.repeat_last_n, defaults::REPEAT_LAST_N);
+    }
+
+    #[test]
+    fn test_model_args_builder_defaults() {
+        let args = ModelArgs::builder()
+            .which(Which::W25_3b)
+            .temperature(0.7)
+            .top_k(Some(40))
+            .build();
+        assert_eq!(args.which, Which::W25_3b);
+        assert_eq!(args.temperature, 0.7);
+        assert_eq!(args.top_k, Some(40));
+
+        let defaults = ModelArgs::default();
+        assert_eq!(args.sample_len, defaults.sample_len);
+        assert_eq!(args.seed, defaults.seed);
+        assert_eq!(args.top_p, None);
+        assert_eq!(args.repeat_penalty, defaults.repeat_penalty);
+        assert_eq!(args.repeat_last_n, defaults.repeat_last_n);
+        assert_eq!(args.quant, Quant::Q4KM);
+        assert!(args.model.is_none() && args.tokenizer.is_none());
+        assert!(!args.cpu && !args.tracing && !args.split_prompt);
+        assert!(args.skip_special_tokens);
+        assert!(args.stop.is_empty());
+    }
+
+    #[test]
+    fn test_tokenizer_repo_is_not_gguf_repo() {
+        let which = Which::W25_0_5b;
+        assert_eq!(which.tokenizer_repo(), "Qwen/Qwen2.5-0.5B-Instruct");
+        assert_eq!(which.gguf_repo(), "Qwen/Qwen2.5-0.5B-Instruct-GGUF");
+        assert_ne!(which.tokenizer_repo(), which.gguf_repo());
+    }
 
-        if let Some(rest) = tos.decode_rest().map_err(candle::Error::msg)? {
-            callback(rest)?;
+    /// Word-level tokenizer with `<|im_end|>` registered as a special token.
+    fn tiny_tokenizer() -> Tokenizer {
+        let json = r#"{
+            "version": "1.0",
+            "added_tokens": [
+                {"id": 2, "content": "<|im_end|>", "single_word": false, "lstrip": false,
+                 "rstrip": false, "normalized": false, "special": true}
+            ],
+            "pre_tokenizer": {"type": "Whitespace"},
+            "model": {
+                "type": "WordLevel",
+                "vocab": {"hello": 0, "world": 1, "<|im_end|>": 2},
+                "unk_token": "<|im_end|>"
+            }
+        }"#;
+        Tokenizer::from_bytes(json).unwrap()
+    }
+
+    fn stream_all(tos: &mut TokenOutputStream, tokens: &[u32]) -> String {
+        let mut out = String::new();
+        for &token in tokens {
+            if let Some(text) = tos.next_token(token).unwrap() {
+                out.push_str(&text);
+            }
+        }
+        if let Some(rest) = tos.decode_rest().unwrap() {
+            out.push_str(&rest);
         }
+        out
+    }
 
-        let dt = start_post_prompt.elapsed();
-        Ok(GenerationStats {
-            prompt_tokens: tokens.len(),
-            prompt_processing_time: prompt_dt,
-            generated_tokens: sampled,
-            generation_time: dt,
-        })
+    #[test]
+    fn test_skip_special_tokens() {
+        let tokens = [0, 1, 2];
+
+        let mut skipped = TokenOutputStream::new(tiny_tokenizer());
+        let skipped = stream_all(&mut skipped, &tokens);
+        assert_eq!(skipped, "hello world");
+
+        let mut kept = TokenOutputStream::new(tiny_tokenizer()).with_skip_special_tokens(false);
+        let kept = stream_all(&mut kept, &tokens);
+        assert!(kept.starts_with("hello world"));
+        assert!(kept.ends_with("<|im_end|>"));
+    }
+
+    /// Byte-level BPE tokenizer where `é` (bytes `C3 A9`) is split across tokens 2 and 3.
+    fn byte_level_tokenizer() -> Tokenizer {
+        let json = r#"{
+            "version": "1.0",
+            "added_tokens": [
+                {"id": 4, "content": "<|im_end|>", "single_word": false, "lstrip": false,
+                 "rstrip": false, "normalized": false, "special": true}
+            ],
+            "pre_tokenizer": {"type": "ByteLevel", "add_prefix_space": false,
+                              "trim_offsets": true, "use_regex": true},
+            "decoder": {"type": "ByteLevel", "add_prefix_space": false,
+                        "trim_offsets": true, "use_regex": true},
+            "model": {
+                "type": "BPE",
+                "vocab": {"h": 0, "i": 1, "Ã": 2, "©": 3, "<|im_end|>": 4},
+                "merges": []
+            }
+        }"#;
+        Tokenizer::from_bytes(json).unwrap()
+    }
+
+    /// Chat tokenizer whose post-processor prepends `<|im_start|>` like a BOS token.
+    fn bos_tokenizer() -> Tokenizer {
+        let json = r#"{
+            "version": "1.0",
+            "added_tokens": [
+                {"id": 0, "content": "<|im_start|>", "single_word": false, "lstrip": false,
+                 "rstrip": false, "normalized": false, "special": true},
+                {"id": 1, "content": "<|im_end|>", "single_word": false, "lstrip": false,
+                 "rstrip": false, "normalized": false, "special": true}
+            ],
+            "pre_tokenizer": {"type": "Whitespace"},
+            "post_processor": {
+                "type": "TemplateProcessing",
+                "single": [{"SpecialToken": {"id": "<|im_start|>", "type_id": 0}},
+                           {"Sequence": {"id": "A", "type_id": 0}}],
+                "pair": [{"SpecialToken": {"id": "<|im_start|>", "type_id": 0}},
+                         {"Sequence": {"id": "A", "type_id": 0}},
+                         {"Sequence": {"id": "B", "type_id": 1}}],
+                "special_tokens": {
+                    "<|im_start|>": {"id": "<|im_start|>", "ids": [0], "tokens": ["<|im_start|>"]}
+                }
+            },
+            "model": {
+                "type": "WordLevel",
+                "vocab": {"<|im_start|>": 0, "<|im_end|>": 1, "user": 2, "assistant": 3,
+                          "hi": 4, "<unk>": 5},
+                "unk_token": "<unk>"
+            }
+        }"#;
+        Tokenizer::from_bytes(json).unwrap()
+    }
+
+    #[test]
+    fn test_encode_prompt_special_tokens() {
+        let tokenizer = bos_tokenizer();
+        let starts = |ids: &[u32]| ids.iter().filter(|&&id| id == 0).count();
+
+        // The template alone opens the user and assistant turns.
+        let plain = encode_prompt(&tokenizer, &user_turn("hi"), false).unwrap();
+        assert_eq!(plain, vec![0, 2, 4, 1, 0, 3]);
+        assert_eq!(starts(&plain), 2);
+
+        let added = encode_prompt(&tokenizer, &user_turn("hi"), true).unwrap();
+        assert_eq!(added.len(), plain.len() + 1);
+        assert_eq!(starts(&added), 3);
+
+        assert!(!ModelArgs::default().add_special_tokens);
+    }
+
+    #[test]
+    fn test_chat_prompt_turns() {
+        assert_eq!(
+            chat_prompt(&user_turn("hi")),
+            "<|im_start|>user\nhi<|im_end|>\n<|im_start|>assistant\n"
+        );
+
+        let messages = [
+            (Role::System, "Be brief.".to_string()),
+            (Role::User, "hi".to_string()),
+            (Role::Assistant, "Hello!".to_string()),
+            (Role::User, "bye".to_string()),
+        ];
+        assert_eq!(
+            chat_prompt(&messages),
+            "<|im_start|>system\nBe brief.<|im_end|>\n\
+             <|im_start|>user\nhi<|im_end|>\n\
+             <|im_start|>assistant\nHello!<|im_end|>\n\
+             <|im_start|>user\nbye<|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+
+        // Earlier turns are encoded too, each opening with `<|im_start|>`.
+        let tokenizer = bos_tokenizer();
+        let ids = encode_prompt(&tokenizer, &messages[1..3], false).unwrap();
+        assert_eq!(ids.iter().filter(|&&id| id == 0).count(), 3);
+    }
+
+    fn stream_steps(tos: &mut TokenOutputStream, tokens: &[u32]) -> Vec<Option<String>> {
+        tokens
+            .iter()
+            .map(|&token| tos.next_token(token).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_token_output_stream_empty_decode() {
+        // A skipped special token decodes to nothing and must not emit an empty string.
+        let mut tos = TokenOutputStream::new(tiny_tokenizer());
+        assert_eq!(
+            stream_steps(&mut tos, &[0, 2, 1]),
+            vec![Some("hello".to_string()), None, Some(" world".to_string())]
+        );
+        assert_eq!(tos.decode_rest().unwrap(), None);
+    }
+
+    #[test]
+    fn test_token_output_stream_split_multibyte_char() {
+        let mut tos = TokenOutputStream::new(byte_level_tokenizer());
+        assert_eq!(
+            stream_steps(&mut tos, &[0, 2, 3, 1]),
+            vec![
+                Some("h".to_string()),
+                None,
+                Some("é".to_string()),
+                Some("i".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_next_token_utf8_buffers_partial_codepoint() {
+        let mut tos = TokenOutputStream::new(byte_level_tokenizer());
+        let steps: Vec<_> = [0, 2, 3, 1, 4]
+            .iter()
+            .map(|&token| tos.next_token_utf8(token).unwrap())
+            .collect();
+        // "Ã" is the lead byte 0xc3 and "©" the continuation 0xa9 of "é".
+        assert_eq!(
+            steps,
+            vec![
+                Some("h".to_string()),
+                None,
+                Some("é".to_string()),
+                Some("i".to_string()),
+                None
+            ]
+        );
+        tos.finish_utf8().unwrap();
+
+        // A continuation byte with no lead byte can never become valid.
+        let mut tos = TokenOutputStream::new(byte_level_tokenizer());
+        assert!(tos.next_token_utf8(3).is_err());
+
+        let mut tos = TokenOutputStream::new(byte_level_tokenizer());
+        assert_eq!(tos.next_token_utf8(2).unwrap(), None);
+        assert!(tos.finish_utf8().is_err());
+        assert_eq!(byte_level_byte('Ġ'), Some(b' '));
+    }
+
+    #[test]
+    fn test_token_output_stream_decode_rest_after_eos() {
+        let mut tos = TokenOutputStream::new(byte_level_tokenizer());
+        assert_eq!(
+            stream_steps(&mut tos, &[0, 1, 4]),
+            vec![Some("h".to_string()), Some("i".to_string()), None]
+        );
+        // Everything was already emitted.
+        assert_eq!(tos.decode_rest().unwrap(), None);
+
+        // Stopping mid-character flushes what is left, lossily.
+        let mut tos = TokenOutputStream::new(byte_level_tokenizer());
+        assert_eq!(
+            stream_steps(&mut tos, &[0, 2, 4]),
+            vec![Some("h".to_string()), None, None]
+        );
+        assert_eq!(tos.decode_rest().unwrap(), Some("\u{FFFD}".to_string()));
+    }
+
+    #[test]
+    fn test_continuation_buffers_across_calls() {
+        // "h", "i" and the first byte of "é" in one call, the rest of "é" in the next.
+        let calls: [&[u32]; 2] = [&[0, 1, 2], &[3]];
+        // The text passed on by the end of each call, then after the final flush.
+        let run = |stop: &str| {
+            let mut state = Continuation {
+                tos: TokenOutputStream::new(byte_level_tokenizer()),
+                stop: StopSequences::new(&[stop.to_string()]),
+                json: None,
+                all_tokens: Vec::new(),
+                next_token: 0,
+                pos: 0,
+            };
+            let text = std::cell::RefCell::new(String::new());
+            let mut callback = |t: String| -> Result<()> {
+                text.borrow_mut().push_str(&t);
+                Ok(())
+            };
+            let mut texts = Vec::new();
+            for tokens in calls {
+                for &token in tokens {
+                    state.emit(token, &mut callback).unwrap();
+                }
+                texts.push(text.borrow().clone());
+            }
+            state.flush(&mut callback).unwrap();
+            texts.push(text.borrow().clone());
+            texts
+        };
+
+        // Neither the split "é" nor the "i" that may start a stop sequence is passed on at the
+        // boundary, so the stop sequence spanning it is still found.
+        assert_eq!(run("ié"), ["h", "h", "h"]);
+        assert_eq!(run("ix"), ["h", "hié", "hié"]);
+    }
+
+    #[test]
+    fn test_generation_stats_to_json() {
+        let stats = GenerationStats {
+            prompt_tokens: 20,
+            prompt_processing_time: Duration::from_millis(500),
+            generated_tokens: 50,
+            generation_time: Duration::from_secs(2),
+        };
+
+        let json = stats.to_json();
+        assert_eq!(json["prompt_tokens"], 20);
+        assert_eq!(json["prompt_processing_ms"], 500.0);
+        assert_eq!(json["prompt_tokens_per_sec"], 40.0);
+        assert_eq!(json["generated_tokens"], 50);
+        assert_eq!(json["generation_ms"], 2000.0);
+        assert_eq!(json["generated_tokens_per_sec"], 25.0);
+    }
+
+    #[test]
+    fn test_model_info_from_gguf() {
+        use candle::quantized::{gguf_file::Value, GgmlDType, QTensor};
+
+        let weights = Tensor::zeros((2, 32), candle::DType::F32, &Device::Cpu).unwrap();
+        let q4 = QTensor::quantize(&weights, GgmlDType::Q4_0).unwrap();
+        let q8 = QTensor::quantize(&weights, GgmlDType::Q8_0).unwrap();
+        let metadata = [
+            ("general.architecture", Value::String("qwen2".to_string())),
+            ("general.name", Value::String("fixture".to_string())),
+            ("qwen2.context_length", Value::U32(32768)),
+            ("qwen2.block_count", Value::U32(24)),
+            ("qwen2.embedding_length", Value::U32(896)),
+        ];
+        let metadata: Vec<_> = metadata.iter().map(|(k, v)| (*k, v)).collect();
+        let mut buf = std::io::Cursor::new(Vec::new());
+        gguf_file::write(
+            &mut buf,
+            &metadata,
+            &[("a.weight", &q4), ("b.weight", &q4), ("output.weight", &q8)],
+        )
+        .unwrap();
+        buf.set_position(0);
+        let content = gguf_file::Content::read(&mut buf).unwrap();
+
+        let info = ModelInfo::from_gguf(&content);
+        assert_eq!(
+            info,
+            ModelInfo {
+                architecture: Some("qwen2".to_string()),
+                name: Some("fixture".to_string()),
+                quant: "Q4_0".to_string(),
+                context_length: Some(32768),
+                layer_count: Some(24),
+                embedding_length: Some(896),
+                tensor_count: 3,
+            }
+        );
+    }
+
+    struct FirstToken;
+
+    impl Sampler for FirstToken {
+        fn sample(&mut self, _logits: &Tensor) -> candle::Result<u32> {
+            Ok(0)
+        }
+    }
+
+    /// Greedy sampler that records the tokens it picks.
+    #[derive(Clone, Default)]
+    struct Recording(std::sync::Arc<std::sync::Mutex<Vec<u32>>>);
+
+    impl Sampler for Recording {
+        fn sample(&mut self, logits: &Tensor) -> candle::Result<u32> {
+            let token = logits.argmax(0)?.to_scalar::<u32>()?;
+            self.0.lock().unwrap().push(token);
+            Ok(token)
+        }
+    }
+
+    #[test]
+    fn test_logits_processor_sampler() {
+        let logits = Tensor::new(&[0.1f32, 2.0, -1.0, 0.5], &Device::Cpu).unwrap();
+        let mut argmax: Box<dyn Sampler> =
+            Box::new(LogitsProcessor::from_sampling(0, Sampling::ArgMax));
+        assert_eq!(argmax.sample(&logits).unwrap(), 1);
+        let mut first: Box<dyn Sampler> = Box::new(FirstToken);
+        assert_eq!(first.sample(&logits).unwrap(), 0);
+    }
+
+    // Downloads the 0.5b GGUF and tokenizer from the Hugging Face Hub.
+    // Run with: cargo test -p candle-qwen2-5-core -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_custom_sampler() {
+        let args = ModelArgs {
+            cpu: true,
+            ..Default::default()
+        };
+        let mut model = Qwen2Model::new(&args).await.unwrap();
+        model.set_sampler(FirstToken);
+
+        let mut text = String::new();
+        let stats = model
+            .generate("Say anything.", 6, |t| {
+                text.push_str(&t);
+                Ok(())
+            })
+            .unwrap();
+
+        // Every generated token is id 0, so the text is that token repeated.
+        let token_zero = model.tokenizer.decode(&[0], false).unwrap();
+        assert_eq!(text, token_zero.repeat(stats.generated_tokens + 1));
+    }
+
+    // Downloads the 0.5b GGUF and tokenizer from the Hugging Face Hub.
+    // Run with: cargo test -p candle-qwen2-5-core -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_generate_constrained_json() {
+        let args = ModelArgs {
+            cpu: true,
+            ..Default::default()
+        };
+        let mut model = Qwen2Model::new(&args).await.unwrap();
+
+        let mut text = String::new();
+        model
+            .generate_constrained(
+                "Describe a cat as JSON with name and age fields.",
+                200,
+                Grammar::JsonObject,
+                |t| {
+                    text.push_str(&t);
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert!(value.is_object());
+    }
+
+    // Downloads the 0.5b GGUF and tokenizer from the Hugging Face Hub.
+    // Run with: cargo test -p candle-qwen2-5-core -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_generate_constrained_json_split_prompt() {
+        let args = ModelArgs {
+            cpu: true,
+            split_prompt: true,
+            ..Default::default()
+        };
+        let mut model = Qwen2Model::new(&args).await.unwrap();
+        let prompt = "Describe a cat as JSON with name and age fields.";
+        let generate = |model: &mut Qwen2Model| {
+            let recording = Recording::default();
+            model.set_sampler(recording.clone());
+            let mut text = String::new();
+            model
+                .generate_constrained(prompt, 200, Grammar::JsonObject, |t| {
+                    text.push_str(&t);
+                    Ok(())
+                })
+                .unwrap();
+            let sampled = recording.0.lock().unwrap().len();
+            (text, sampled)
+        };
+
+        let split = generate(&mut model);
+        model.split_prompt = false;
+        let single = generate(&mut model);
+
+        let value: serde_json::Value = serde_json::from_str(&split.0).unwrap();
+        assert!(value.is_object());
+        assert_eq!(split, single);
+    }
+
+    // Downloads the 0.5b GGUF and tokenizer from the Hugging Face Hub.
+    // Run with: cargo test -p candle-qwen2-5-core -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_continue_generation() {
+        let args = ModelArgs {
+            cpu: true,
+            ..Default::default()
+        };
+        let mut model = Qwen2Model::new(&args).await.unwrap();
+        let prompt = "Count from one to twenty.";
+
+        let split = Recording::default();
+        model.set_sampler(split.clone());
+        assert!(model.continue_generation(5, |_| Ok(())).is_err());
+        let mut split_text = String::new();
+        let mut on_split = |t: String| -> Result<()> {
+            split_text.push_str(&t);
+            Ok(())
+        };
+        model.generate(prompt, 5, &mut on_split).unwrap();
+        let stats = model.continue_generation(5, &mut on_split).unwrap();
+        assert_eq!(stats.generated_tokens, 5);
+        model.finish_generation(&mut on_split).unwrap();
+        assert!(model.continue_generation(5, |_| Ok(())).is_err());
+
+        let single = Recording::default();
+        model.set_sampler(single.clone());
+        let mut single_text = String::new();
+        let mut on_single = |t: String| -> Result<()> {
+            single_text.push_str(&t);
+            Ok(())
+        };
+        model.generate(prompt, 10, &mut on_single).unwrap();
+        model.finish_generation(&mut on_single).unwrap();
+
+        let split = split.0.lock().unwrap();
+        assert_eq!(split.len(), 10);
+        assert_eq!(*split, *single.0.lock().unwrap());
+        assert_eq!(split_text, single_text);
+    }
+
+    // Downloads the 0.5b GGUF and tokenizer from the Hugging Face Hub.
+    // Run with: cargo test -p candle-qwen2-5-core -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_generate_with_trace() {
+        let args = ModelArgs {
+            cpu: true,
+            ..Default::default()
+        };
+        let mut model = Qwen2Model::new(&args).await.unwrap();
+
+        let traced = model
+            .generate_with_trace("Count from one to ten.", 5, |_| Ok(()))
+            .unwrap();
+
+        assert_eq!(traced.token_times.len(), traced.stats.generated_tokens);
+    }
+
+    // Downloads the 0.5b GGUF and tokenizer.json from the Hugging Face Hub.
+    // Run with: cargo test -p candle-qwen2-5-core -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_tokenizer_from_gguf_matches_hub() {
+        let args = ModelArgs::default();
+        let model_path = args.model().await.unwrap();
+        let mut file = std::fs::File::open(&model_path).unwrap();
+        let content = gguf_file::Content::read(&mut file).unwrap();
+
+        let from_gguf = tokenizer_from_gguf(&content).unwrap();
+        let from_hub = args.tokenizer().await.unwrap();
+
+        let prompt = "<|im_start|>user\nWhat's 2 + 2? Answer in   one word.<|im_end|>\n";
+        let ids = |t: &Tokenizer| t.encode(prompt, true).unwrap().get_ids().to_vec();
+        assert_eq!(ids(&from_gguf), ids(&from_hub));
+        assert!(from_gguf.get_vocab(true).contains_key("<|im_end|>"));
     }
 }
@@ -0,0 +1,107 @@
+//! Cutting generated text at caller-chosen stop strings, see [crate::ModelArgs::stop].
+
+/// Streams text through until one of the stop strings appears, then stops for good. Text that
+/// could still be the start of a stop string is held back until later tokens settle it, so a
+/// stop string split across tokens never leaks out.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StopSequences {
+    stops: Vec<String>,
+    pending: String,
+    stopped: bool,
+}
+
+impl StopSequences {
+    /// Empty stop strings are ignored.
+    pub(crate) fn new(stops: &[String]) -> Self {
+        Self {
+            stops: stops.iter().filter(|s| !s.is_empty()).cloned().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Whether a stop string was seen; everything from it on is dropped.
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Adds newly decoded text and returns what can be emitted now.
+    pub(crate) fn push(&mut self, text: &str) -> Option<String> {
+        if self.stopped {
+            return None;
+        }
+        self.pending.push_str(text);
+        let first_stop = self
+            .stops
+            .iter()
+            .filter_map(|stop| self.pending.find(stop.as_str()))
+            .min();
+        let emit = match first_stop {
+            Some(at) => {
+                self.stopped = true;
+                self.pending.truncate(at);
+                at
+            }
+            None => self.pending.len() - self.held_back(),
+        };
+        let rest = self.pending.split_off(emit);
+        let text = std::mem::replace(&mut self.pending, rest);
+        (!text.is_empty()).then_some(text)
+    }
+
+    /// Releases text held back as a possible stop string that never completed.
+    pub(crate) fn finish(&mut self) -> Option<String> {
+        let text = std::mem::take(&mut self.pending);
+        (!text.is_empty()).then_some(text)
+    }
+
+    /// Length of the longest end of `pending` that some stop string starts with.
+    fn held_back(&self) -> usize {
+        self.pending
+            .char_indices()
+            .map(|(i, _)| &self.pending[i..])
+            .find(|tail| self.stops.iter().any(|stop| stop.starts_with(tail)))
+            .map_or(0, str::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(stops: &[&str], pieces: &[&str]) -> (Vec<Option<String>>, Option<String>) {
+        let stops: Vec<String> = stops.iter().map(|s| s.to_string()).collect();
+        let mut seq = StopSequences::new(&stops);
+        let out = pieces.iter().map(|piece| seq.push(piece)).collect();
+        (out, seq.finish())
+    }
+
+    fn some(text: &str) -> Option<String> {
+        Some(text.to_string())
+    }
+
+    #[test]
+    fn test_stop_within_one_piece() {
+        let (out, rest) = run(&["\n\n"], &["Hello", " world\n\nmore", "ignored"]);
+        assert_eq!(out, vec![some("Hello"), some(" world"), None]);
+        assert_eq!(rest, None);
+    }
+
+    #[test]
+    fn test_stop_spanning_pieces() {
+        let (out, rest) = run(&["```"], &["code", "`", "`", "`after"]);
+        assert_eq!(out, vec![some("code"), None, None, None]);
+        assert_eq!(rest, None);
+
+        // A false start is released once it can no longer match.
+        let (out, rest) = run(&["```"], &["a`", "`b", "c`"]);
+        assert_eq!(out, vec![some("a"), some("``b"), some("c")]);
+        assert_eq!(rest, some("`"));
+    }
+
+    #[test]
+    fn test_no_stops_passes_through() {
+        let (out, rest) = run(&[""], &["a", "b"]);
+        assert_eq!(out, vec![some("a"), some("b")]);
+        assert_eq!(rest, None);
+    }
+}
@@ -0,0 +1,220 @@
+//! Converting a [NamedGraph] to and from graph-delta [Chunk]s.
+use crate::{Clusters, NamedGraph, sanitize_id};
+use graph_delta::parser::Chunk;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences as _};
+use petgraph::{Directed, EdgeType};
+use std::collections::HashMap;
+use std::fmt::Display;
+
+impl<E: Display, Ty: EdgeType> NamedGraph<E, Ty> {
+    /// The graph as graph-delta chunks, one statement per line so the line ranges encode the
+    /// subgraph nesting: subgraphs (named and labelled like [NamedGraph::to_dot]) with their
+    /// nodes, then the remaining nodes, then every edge. Node and edge attributes become the
+    /// chunk attributes, and an edge's weight its `label` unless it displays as empty.
+    pub fn to_chunks(&self) -> Vec<Chunk> {
+        let mut names = self.node_names();
+        names.sort();
+        let clusters = self.clusters(&names, |i, subgraph_name| self.cluster_id(i, subgraph_name));
+
+        let mut chunks = Vec::new();
+        let mut line = 1;
+        for subgraph_name in &clusters.roots {
+            self.push_subgraph_chunks(&mut chunks, &mut line, subgraph_name, &clusters);
+        }
+        for name in &clusters.loose {
+            chunks.push(self.node_chunk(name, line));
+            line += 1;
+        }
+
+        let mut edges: Vec<(&str, &str, String)> = self
+            .graph
+            .edge_references()
+            .map(|e| {
+                (
+                    self.graph[e.source()].as_str(),
+                    self.graph[e.target()].as_str(),
+                    e.weight().to_string(),
+                )
+            })
+            .collect();
+        edges.sort();
+        for (s, t, label) in edges {
+            let mut attrs = HashMap::new();
+            if !label.is_empty() {
+                attrs.insert("label".to_string(), label);
+            }
+            attrs.extend(self.edge_attrs(s, t).unwrap_or_default().iter().cloned());
+            chunks.push(Chunk {
+                kind: "edge".to_string(),
+                id: Some(sanitize_id(s)),
+                attrs,
+                range: (line, line),
+                extra: Some(sanitize_id(t)),
+                span: None,
+            });
+            line += 1;
+        }
+        chunks
+    }
+
+    fn node_chunk(&self, name: &str, line: usize) -> Chunk {
+        Chunk {
+            kind: "node".to_string(),
+            id: Some(sanitize_id(name)),
+            attrs: self.node_attrs(name).cloned().unwrap_or_default(),
+            range: (line, line),
+            extra: None,
+            span: None,
+        }
+    }
+
+    /// Pushes the subgraph chunk, its nodes and its nested subgraphs, then closes its range.
+    fn push_subgraph_chunks(
+        &self,
+        chunks: &mut Vec<Chunk>,
+        line: &mut usize,
+        subgraph_name: &str,
+        clusters: &Clusters,
+    ) {
+        let pos = chunks.len();
+        let start = *line;
+        chunks.push(Chunk {
+            kind: "subgraph".to_string(),
+            id: Some(clusters.ids[subgraph_name].clone()),
+            attrs: HashMap::from([("label".to_string(), subgraph_name.to_string())]),
+            range: (start, start),
+            extra: None,
+            span: None,
+        });
+        *line += 1;
+        for name in clusters.nodes.get(subgraph_name).into_iter().flatten() {
+            chunks.push(self.node_chunk(name, *line));
+            *line += 1;
+        }
+        for child in clusters.children.get(subgraph_name).into_iter().flatten() {
+            self.push_subgraph_chunks(chunks, line, child, clusters);
+        }
+        chunks[pos].range = (start, *line);
+        *line += 1;
+    }
+}
+
+impl NamedGraph<String, Directed> {
+    /// Rebuilds a graph from chunks such as [NamedGraph::to_chunks] produces, with each edge's
+    /// `label` as its weight (empty without one) and its other attributes as edge attributes.
+    ///
+    /// Nodes join the innermost subgraph whose line range contains theirs. A subgraph is named
+    /// by its `label` attribute, else by its id without the `cluster_` prefix. Chunks of other
+    /// kinds are ignored.
+    pub fn from_chunks(chunks: &[Chunk]) -> Self {
+        let mut ng = Self::new_directed();
+        let subgraph_name = |chunk: &Chunk| match chunk.attrs.get("label") {
+            Some(label) => label.clone(),
+            None => {
+                let id = unquote(chunk.id.as_deref().unwrap_or_default());
+                id.strip_prefix("cluster_")
+                    .map(str::to_string)
+                    .unwrap_or(id)
+            }
+        };
+        // Innermost subgraph around chunk `i`, other than itself.
+        let parent = |i: usize| {
+            let r = chunks[i].range;
+            chunks
+                .iter()
+                .enumerate()
+                .filter(|&(j, c)| {
+                    j != i && c.kind == "subgraph" && c.range.0 <= r.0 && r.1 <= c.range.1
+                })
+                .min_by_key(|(_, c)| c.range.1 - c.range.0)
+                .map(|(_, c)| subgraph_name(c))
+        };
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            match (
+                chunk.kind.as_str(),
+                chunk.id.as_deref(),
+                chunk.extra.as_deref(),
+            ) {
+                ("node", Some(id), _) => {
+                    let name = unquote(id);
+                    ng.ensure_node(name.as_str());
+                    for (key, value) in &chunk.attrs {
+                        ng.set_node_attr(&name, key, value);
+                    }
+                    if let Some(subgraph) = parent(i) {
+                        ng.set_node_subgraph(&name, subgraph);
+                    }
+                }
+                ("edge", Some(from), Some(to)) => {
+                    let (from, to) = (unquote(from), unquote(to));
+                    let mut attrs: Vec<_> = chunk.attrs.iter().collect();
+                    attrs.sort();
+                    let label = chunk.attrs.get("label").cloned().unwrap_or_default();
+                    ng.add_edge_by_name(&from, &to, label);
+                    for (key, value) in attrs.into_iter().filter(|(key, _)| *key != "label") {
+                        ng.set_edge_attr(&from, &to, key, value);
+                    }
+                }
+                ("subgraph", _, _) => {
+                    if let Some(outer) = parent(i) {
+                        // Only fails for a cycle, which nested ranges cannot form.
+                        let _ = ng.set_subgraph_parent(&subgraph_name(chunk), &outer);
+                    }
+                }
+                _ => {}
+            }
+        }
+        ng
+    }
+}
+
+/// A chunk id as a plain name: surrounding quotes removed and `\"` unescaped.
+fn unquote(id: &str) -> String {
+    match id.strip_prefix('"').and_then(|id| id.strip_suffix('"')) {
+        Some(inner) => inner.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => id.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_round_trip() {
+        let mut ng = NamedGraph::<String, Directed>::new_directed();
+        ng.add_edge_by_name("api", "db", "reads".to_string());
+        ng.add_edge_by_name("web ui", "api", String::new());
+        ng.set_node_attr("api", "shape", "box");
+        ng.set_edge_attr("api", "db", "style", "dashed");
+        ng.set_node_subgraph("api", "Backend");
+        ng.set_node_subgraph("db", "Storage");
+        ng.set_subgraph_parent("Storage", "Backend").unwrap();
+
+        let chunks = ng.to_chunks();
+        let kinds: Vec<&str> = chunks.iter().map(|c| c.kind.as_str()).collect();
+        assert_eq!(
+            kinds,
+            [
+                "subgraph", "node", "subgraph", "node", "node", "edge", "edge"
+            ]
+        );
+        let dot = graph_delta::parser::chunks_to_dot_nested(&chunks, None);
+        assert!(dot.contains("\"web ui\" -> api;"), "{dot}");
+
+        let back = NamedGraph::<String, Directed>::from_chunks(&chunks);
+        let mut edges = back.edges_with_names();
+        edges.sort();
+        let mut expected = ng.edges_with_names();
+        expected.sort();
+        assert_eq!(edges, expected);
+        assert_eq!(back.node_attrs("api"), ng.node_attrs("api"));
+        assert_eq!(back.edge_attrs("api", "db"), ng.edge_attrs("api", "db"));
+        assert_eq!(back.subgraph_members("Backend"), ["api"]);
+        assert_eq!(back.subgraph_members("Storage"), ["db"]);
+        assert_eq!(back.subgraph_parent("Storage"), Some("Backend"));
+        assert!(back.subgraph_members("web ui").is_empty());
+        assert_eq!(back.to_dot(), ng.to_dot());
+    }
+}
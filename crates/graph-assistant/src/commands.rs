@@ -0,0 +1,153 @@
+//! Applying graph-delta [DotCommand]s to a [NamedGraph] instead of a chunk list.
+use crate::NamedGraph;
+use graph_delta::commands::DotCommand;
+use graph_delta::parser::parse_attribute_string;
+use petgraph::EdgeType;
+
+impl<E: Default, Ty: EdgeType> NamedGraph<E, Ty> {
+    /// Applies a node or edge command through the named API, with the same errors as
+    /// [graph_delta::commands::apply_command] for duplicates and missing elements.
+    ///
+    /// New edges get `E::default()` as their weight. Node and edge attributes are stored with
+    /// [NamedGraph::set_node_attr] and [NamedGraph::set_edge_attr], and a `parent` becomes the
+    /// node's subgraph. Edges belong to no subgraph here, so the `parent` of
+    /// [DotCommand::CreateEdge] is ignored. Deleting a node also deletes its edges.
+    /// Subgraph and graph-attribute commands have no `NamedGraph` equivalent and are rejected.
+    pub fn apply_command(&mut self, command: &DotCommand) -> Result<(), String> {
+        match command {
+            DotCommand::CreateNode { id, attrs, parent } => {
+                if self.get_node_index(id).is_some() {
+                    return Err(format!("Node '{}' already exists", id));
+                }
+                self.ensure_node(id.as_str());
+                self.set_node_attrs(id, attrs.as_deref());
+                if let Some(parent) = parent {
+                    self.set_node_subgraph(id, parent.as_str());
+                }
+                Ok(())
+            }
+            DotCommand::UpdateNode { id, attrs } => match self.get_node_index(id) {
+                Some(_) => {
+                    self.set_node_attrs(id, attrs.as_deref());
+                    Ok(())
+                }
+                None => Err(format!("Node '{}' not found", id)),
+            },
+            DotCommand::DeleteNode { id } => match self.remove_node_by_name(id) {
+                Some(_) => Ok(()),
+                None => Err(format!("Node '{}' not found", id)),
+            },
+            DotCommand::CreateEdge {
+                from, to, attrs, ..
+            } => {
+                if self.has_edge(from, to) {
+                    return Err(format!("Edge '{}' -> '{}' already exists", from, to));
+                }
+                self.add_edge_by_name(from, to, E::default());
+                self.set_edge_attrs(from, to, attrs.as_deref());
+                Ok(())
+            }
+            DotCommand::UpdateEdge { from, to, attrs } => {
+                if !self.has_edge(from, to) {
+                    self.add_edge_by_name(from, to, E::default());
+                }
+                self.set_edge_attrs(from, to, attrs.as_deref());
+                Ok(())
+            }
+            DotCommand::DeleteEdge { from, to } => match self.remove_edge_by_names(from, to) {
+                Some(_) => Ok(()),
+                None => Err(format!("Edge '{}' -> '{}' not found", from, to)),
+            },
+            other => Err(format!(
+                "Command is not supported on a NamedGraph: {}",
+                other
+            )),
+        }
+    }
+
+    /// Sets each attribute of a DOT attribute string on the node.
+    fn set_node_attrs(&mut self, id: &str, attrs: Option<&str>) {
+        for (key, value) in attrs.map(parse_attribute_string).unwrap_or_default() {
+            self.set_node_attr(id, &key, &value);
+        }
+    }
+
+    /// Sets each attribute of a DOT attribute string on the edge, in key order.
+    fn set_edge_attrs(&mut self, a: &str, b: &str, attrs: Option<&str>) {
+        let mut attrs: Vec<_> = attrs
+            .map(parse_attribute_string)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        attrs.sort();
+        for (key, value) in attrs {
+            self.set_edge_attr(a, b, key, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use petgraph::Directed;
+
+    #[test]
+    fn test_apply_node_and_edge_commands() {
+        let mut ng = NamedGraph::<String, Directed>::new_directed();
+
+        ng.apply_command(&DotCommand::CreateNode {
+            id: "A".to_string(),
+            attrs: Some(r#"label="Start""#.to_string()),
+            parent: Some("Inputs".to_string()),
+        })
+        .unwrap();
+        assert!(ng.get_node_index("A").is_some());
+        assert!(ng.to_dot().contains("label = \"Inputs\";"));
+        assert_eq!(
+            ng.node_attrs("A").unwrap().get("label").map(String::as_str),
+            Some("Start")
+        );
+        assert!(
+            ng.apply_command(&DotCommand::CreateNode {
+                id: "A".to_string(),
+                attrs: None,
+                parent: None,
+            })
+            .is_err()
+        );
+
+        ng.apply_command(&DotCommand::CreateEdge {
+            from: "A".to_string(),
+            to: "B".to_string(),
+            attrs: Some("style=dashed, color=red".to_string()),
+            parent: Some("Inputs".to_string()),
+        })
+        .unwrap();
+        assert_eq!(ng.neighbors_by_name("A").unwrap(), vec!["B".to_string()]);
+        assert_eq!(
+            ng.edge_attrs("A", "B").unwrap(),
+            &[
+                ("color".to_string(), "red".to_string()),
+                ("style".to_string(), "dashed".to_string()),
+            ]
+        );
+
+        ng.apply_command(&DotCommand::DeleteNode {
+            id: "B".to_string(),
+        })
+        .unwrap();
+        assert!(ng.get_node_index("B").is_none());
+        assert_eq!(ng.graph().edge_count(), 0);
+        assert!(
+            ng.apply_command(&DotCommand::DeleteNode {
+                id: "B".to_string()
+            })
+            .is_err()
+        );
+
+        let unsupported = DotCommand::DeleteAttr {
+            key: "rankdir".to_string(),
+        };
+        assert!(ng.apply_command(&unsupported).is_err());
+    }
+}
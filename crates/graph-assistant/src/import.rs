@@ -0,0 +1,94 @@
+//! Building a [NamedGraph] from DOT source.
+use crate::NamedGraph;
+use petgraph::dot::dot_parser::{DotAttrList, DotNodeWeight, ParseFromDot};
+use petgraph::stable_graph::StableGraph;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences as _};
+use petgraph::{Directed, EdgeType};
+use std::collections::HashMap;
+
+/// Parses `dot` and rebuilds it as a `NamedGraph<E, Ty>`, naming nodes by their (unquoted) id and
+/// mapping each edge's attribute list through `map_edge`.
+///
+/// petgraph's DOT parser always produces a directed graph, so for undirected `Ty` the edges keep
+/// the source/target order they were written in.
+pub(crate) fn parse_dot<E, Ty, F>(dot: &str, mut map_edge: F) -> Result<NamedGraph<E, Ty>, String>
+where
+    Ty: EdgeType,
+    F: FnMut(&DotAttrList) -> E,
+{
+    let parsed: StableGraph<DotNodeWeight, DotAttrList> =
+        ParseFromDot::try_from(dot).map_err(|e| format!("Failed to parse DOT: {}", e))?;
+
+    let mut graph = StableGraph::<String, E, Ty>::default();
+    let mut map = HashMap::new();
+    for ni in parsed.node_indices() {
+        let name = parsed[ni].id.to_string().trim_matches('"').to_string();
+        map.insert(ni, graph.add_node(name));
+    }
+    for e in parsed.edge_references() {
+        graph.add_edge(map[&e.source()], map[&e.target()], map_edge(e.weight()));
+    }
+
+    Ok(NamedGraph::from_owned_graph(graph))
+}
+
+/// Every attribute of a parsed edge, unquoted the same way `graph-delta` does, via
+/// `parse_attribute_string`.
+fn edge_attrs(attrs: &DotAttrList) -> HashMap<String, String> {
+    let attr_str = attrs
+        .elems
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join(", ");
+    graph_delta::parser::parse_attribute_string(&attr_str)
+}
+
+impl<Ty: EdgeType> NamedGraph<HashMap<String, String>, Ty> {
+    /// Imports DOT keeping every edge attribute (not just the label) in a map weight.
+    pub fn from_dot_attributed(dot: &str) -> Result<Self, String> {
+        parse_dot(dot, edge_attrs)
+    }
+}
+
+impl NamedGraph<String, Directed> {
+    /// Imports DOT with each edge's `label` as its weight, or an empty string for edges
+    /// without one.
+    pub fn from_dot(dot: &str) -> Result<Self, String> {
+        parse_dot(dot, |attrs| {
+            edge_attrs(attrs).remove("label").unwrap_or_default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_dot_attributed_keeps_all_edge_attrs() {
+        let dot = r#"digraph { A -> B [color=red, style="dashed"]; }"#;
+        let ng = NamedGraph::<HashMap<String, String>, Directed>::from_dot_attributed(dot).unwrap();
+
+        let edges = ng.edges_with_names();
+        assert_eq!(edges.len(), 1);
+        let (from, to, attrs) = &edges[0];
+        assert_eq!((from.as_str(), to.as_str()), ("A", "B"));
+        assert_eq!(attrs.get("color"), Some(&"red".to_string()));
+        assert_eq!(attrs.get("style"), Some(&"dashed".to_string()));
+    }
+
+    #[test]
+    fn from_dot_uses_edge_labels() {
+        let dot = r#"digraph { A -> B [label="x"]; B -> C; }"#;
+        let ng = NamedGraph::<String, Directed>::from_dot(dot).unwrap();
+        assert_eq!(ng.edge_count(), 2);
+        assert_eq!(ng.edge_weight_by_names("A", "B"), Some(&"x".to_string()));
+        assert_eq!(ng.edge_weight_by_names("B", "C"), Some(&String::new()));
+
+        let err = NamedGraph::<String, Directed>::from_dot("digraph { A -> ")
+            .err()
+            .unwrap();
+        assert!(err.starts_with("Failed to parse DOT"), "{err}");
+    }
+}
@@ -1,11 +1,24 @@
 //! A graph library with named nodes built on petgraph's StableGraph.
+mod chunks;
+mod commands;
+mod import;
+mod sanitize;
+#[cfg(feature = "serde")]
+mod serialize;
+
+use petgraph::algo::astar;
 use petgraph::graph::NodeIndex;
 use petgraph::stable_graph::StableGraph;
-use petgraph::visit::{EdgeRef, IntoEdgeReferences as _};
-use petgraph::{Directed, EdgeType, Graph, Undirected};
-use std::collections::HashMap;
+use petgraph::unionfind::UnionFind;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences as _, NodeIndexable as _};
+use petgraph::{Directed, EdgeType, Graph, Incoming, Outgoing, Undirected};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Display;
 
+use sanitize::escape_quotes;
+pub use sanitize::sanitize_id;
+
 /// Convert any StableGraph<N, E, Ty> into a StableGraph<String, NewE, Ty>.
 /// The caller provides:
 ///   - `extract_name`: map &N -> String (how to get a node name)
@@ -39,11 +52,165 @@ where
     out
 }
 
+/// Like [convert_nodes_and_map_edges], but collapses parallel edges: when the output already
+/// has an edge between the same two nodes (either way round for undirected graphs), `merge`
+/// folds the new weight into it instead of adding another edge.
+pub fn convert_nodes_dedup_edges<N, E, Ty, F, G, M, NewE>(
+    g: StableGraph<N, E, Ty>,
+    mut extract_name: F,
+    mut map_edge: G,
+    mut merge: M,
+) -> StableGraph<String, NewE, Ty>
+where
+    F: FnMut(&N) -> String,
+    G: FnMut(&E) -> NewE,
+    M: FnMut(&mut NewE, &E),
+    Ty: EdgeType,
+{
+    let mut out = StableGraph::with_capacity(g.node_count(), g.edge_count());
+    let mut map: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+
+    for ni in g.node_indices() {
+        let name = extract_name(g.node_weight(ni).unwrap());
+        let new_ni = out.add_node(name);
+        map.insert(ni, new_ni);
+    }
+
+    for e in g.edge_references() {
+        let (s, t) = (map[&e.source()], map[&e.target()]);
+        match out.find_edge(s, t) {
+            Some(existing) => merge(&mut out[existing], e.weight()),
+            None => {
+                out.add_edge(s, t, map_edge(e.weight()));
+            }
+        }
+    }
+
+    out
+}
+
+/// Leaves HTML-like `<...>` values bare and writes everything else as a DOT id with
+/// [sanitize_id].
+fn dot_attr_value(value: &str) -> String {
+    let trimmed = value.trim();
+    if trimmed.starts_with('<') && trimmed.ends_with('>') {
+        value.to_string()
+    } else {
+        sanitize_id(value)
+    }
+}
+
+/// Escapes text for a quoted Mermaid node label or an `|edge label|`, using Mermaid's `#...;`
+/// entity codes for the characters that would end or break them.
+fn mermaid_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("#quot;"),
+            '|' => out.push_str("#124;"),
+            '#' => out.push_str("#35;"),
+            '<' => out.push_str("#lt;"),
+            '>' => out.push_str("#gt;"),
+            '\n' => out.push_str("<br>"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Mermaid node declaration, `n0["name"]`, indented `depth` levels.
+fn mermaid_node(name: &str, ids: &HashMap<&str, String>, depth: usize) -> String {
+    format!(
+        "{}{}[\"{}\"]\n",
+        "    ".repeat(depth),
+        ids[name],
+        mermaid_text(name)
+    )
+}
+
+/// A `subgraph s0 ["name"] ... end` block with its nodes and nested subgraphs.
+fn write_mermaid_subgraph(
+    out: &mut String,
+    subgraph_name: &str,
+    depth: usize,
+    clusters: &Clusters,
+    ids: &HashMap<&str, String>,
+) {
+    let indent = "    ".repeat(depth);
+    out.push_str(&format!(
+        "{}subgraph {} [\"{}\"]\n",
+        indent,
+        clusters.ids[subgraph_name],
+        mermaid_text(subgraph_name)
+    ));
+    for name in clusters.nodes.get(subgraph_name).into_iter().flatten() {
+        out.push_str(&mermaid_node(name, ids, depth + 1));
+    }
+    for child in clusters.children.get(subgraph_name).into_iter().flatten() {
+        write_mermaid_subgraph(out, child, depth + 1, clusters, ids);
+    }
+    out.push_str(&format!("{}end\n", indent));
+}
+
+/// A subgraph label from [NamedGraph::set_node_subgraph], as listed by
+/// [NamedGraph::subgraph_report].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubgraphInfo {
+    pub label: String,
+    /// Nodes still in the graph assigned to this label. A subgraph with no members is left out
+    /// of [NamedGraph::to_dot].
+    pub members: usize,
+    /// Other labels that only differ from this one by case or surrounding whitespace, which
+    /// usually means one logical group got split in two.
+    pub similar_labels: Vec<String>,
+}
+
+/// Errors from the checked [NamedGraph] methods.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    /// A node with this name already exists.
+    DuplicateNode(String),
+    /// Nesting subgraph `child` inside `parent` would make a subgraph its own ancestor.
+    SubgraphCycle { child: String, parent: String },
+}
+
+impl Display for GraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphError::DuplicateNode(name) => write!(f, "Node '{}' already exists", name),
+            GraphError::SubgraphCycle { child, parent } => write!(
+                f,
+                "Nesting subgraph '{}' in '{}' would create a cycle",
+                child, parent
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+/// The subgraphs [NamedGraph::to_dot] and [NamedGraph::to_mermaid] draw, each list in output
+/// order.
+#[derive(Default)]
+struct Clusters<'a> {
+    ids: HashMap<&'a str, String>,
+    roots: Vec<&'a str>,
+    children: HashMap<&'a str, Vec<&'a str>>,
+    nodes: HashMap<&'a str, Vec<&'a str>>,
+    /// Nodes outside every subgraph.
+    loose: Vec<&'a str>,
+}
+
 /// NamedGraph owns String node weights (so all mutation APIs are easy).
 pub struct NamedGraph<E, Ty: EdgeType = Undirected> {
     graph: StableGraph<String, E, Ty>,
     name_map: HashMap<String, NodeIndex>,
     node_to_subgraph: HashMap<String, String>, // node name -> subgraph name
+    subgraph_labels: HashSet<String>,          // every subgraph name ever assigned
+    subgraph_parent: HashMap<String, String>,  // subgraph name -> enclosing subgraph name
+    node_attrs: HashMap<String, HashMap<String, String>>, // node name -> extra DOT attrs
+    edge_attrs: HashMap<(String, String), Vec<(String, String)>>, // (source, target) -> extra DOT attrs
+    stable_cluster_ids: bool,
 }
 
 impl<E> NamedGraph<E, Undirected> {
@@ -52,6 +219,11 @@ impl<E> NamedGraph<E, Undirected> {
             graph: Graph::new_undirected().into(),
             name_map: HashMap::new(),
             node_to_subgraph: HashMap::new(),
+            subgraph_labels: HashSet::new(),
+            subgraph_parent: HashMap::new(),
+            node_attrs: HashMap::new(),
+            edge_attrs: HashMap::new(),
+            stable_cluster_ids: false,
         }
     }
 }
@@ -62,10 +234,87 @@ impl<E> NamedGraph<E, Directed> {
             graph: StableGraph::new(),
             name_map: HashMap::new(),
             node_to_subgraph: HashMap::new(),
+            subgraph_labels: HashSet::new(),
+            subgraph_parent: HashMap::new(),
+            node_attrs: HashMap::new(),
+            edge_attrs: HashMap::new(),
+            stable_cluster_ids: false,
+        }
+    }
+
+    /// Groups of nodes that can all reach each other (Tarjan's algorithm), sorted like
+    /// [NamedGraph::connected_components]. Nodes on no cycle form singleton groups.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<String>> {
+        let components = petgraph::algo::tarjan_scc(&self.graph)
+            .into_iter()
+            .map(|component| {
+                component
+                    .into_iter()
+                    .map(|idx| self.graph[idx].clone())
+                    .collect()
+            })
+            .collect();
+        sorted_groups(components)
+    }
+
+    /// A copy with every edge flipped, e.g. to follow reachability backwards. Node names,
+    /// weights, subgraphs and node and edge attributes are kept. Only defined for directed
+    /// graphs, where reversing means something.
+    pub fn reverse(&self) -> NamedGraph<E, Directed>
+    where
+        E: Clone,
+    {
+        let mut out = NamedGraph::new_directed();
+        for name in self.graph.node_weights() {
+            out.ensure_node(name.as_str());
+        }
+        for (s, t, weight) in self.edges_with_names() {
+            out.add_edge_by_name(&t, &s, weight);
         }
+        out.node_to_subgraph = self.node_to_subgraph.clone();
+        out.subgraph_labels = self.subgraph_labels.clone();
+        out.subgraph_parent = self.subgraph_parent.clone();
+        out.node_attrs = self.node_attrs.clone();
+        out.edge_attrs = self
+            .edge_attrs
+            .iter()
+            .map(|((s, t), attrs)| ((t.clone(), s.clone()), attrs.clone()))
+            .collect();
+        out.stable_cluster_ids = self.stable_cluster_ids;
+        out
+    }
+
+    /// Old name of [NamedGraph::reverse].
+    #[deprecated(note = "renamed to reverse")]
+    pub fn reversed(&self) -> NamedGraph<E, Directed>
+    where
+        E: Clone,
+    {
+        self.reverse()
+    }
+
+    /// Number of edges pointing into `name`, or `None` if there is no such node.
+    pub fn in_degree_by_name(&self, name: &str) -> Option<usize> {
+        let idx = self.get_node_index(name)?;
+        Some(self.graph.neighbors_directed(idx, Incoming).count())
+    }
+
+    /// Number of edges leaving `name`, or `None` if there is no such node.
+    pub fn out_degree_by_name(&self, name: &str) -> Option<usize> {
+        let idx = self.get_node_index(name)?;
+        Some(self.graph.neighbors_directed(idx, Outgoing).count())
     }
 }
 
+/// Sorts each group, then the groups by their first name.
+fn sorted_groups(mut groups: Vec<Vec<String>>) -> Vec<Vec<String>> {
+    for group in &mut groups {
+        group.sort();
+    }
+    groups.sort();
+    groups
+}
+
 impl<E, Ty> NamedGraph<E, Ty>
 where
     Ty: EdgeType,
@@ -81,6 +330,11 @@ where
             graph,
             name_map,
             node_to_subgraph: HashMap::new(),
+            subgraph_labels: HashSet::new(),
+            subgraph_parent: HashMap::new(),
+            node_attrs: HashMap::new(),
+            edge_attrs: HashMap::new(),
+            stable_cluster_ids: false,
         }
     }
 
@@ -106,36 +360,189 @@ where
         idx
     }
 
+    /// Like [NamedGraph::ensure_node], but fails instead of returning the existing node, so
+    /// imports can report name collisions.
+    pub fn add_node_strict(&mut self, name: impl Into<String>) -> Result<NodeIndex, GraphError> {
+        let name = name.into();
+        if self.name_map.contains_key(&name) {
+            return Err(GraphError::DuplicateNode(name));
+        }
+        Ok(self.ensure_node(name))
+    }
+
     pub fn add_edge_by_name(&mut self, a: &str, b: &str, weight: E) -> petgraph::graph::EdgeIndex {
         let ia = self.ensure_node(a.to_string());
         let ib = self.ensure_node(b.to_string());
         self.graph.add_edge(ia, ib, weight)
     }
 
+    /// Adds every `(a, b, weight)` exactly like [NamedGraph::add_edge_by_name] in a loop, but
+    /// takes the names by value and keeps the nodes it has resolved in a local cache, so each
+    /// name is looked up in the graph (and copied) only once per call.
+    pub fn add_edges_by_name<I>(&mut self, edges: I)
+    where
+        I: IntoIterator<Item = (String, String, E)>,
+    {
+        let mut resolved: HashMap<String, NodeIndex> = HashMap::new();
+        for (a, b, weight) in edges {
+            let ia = self.ensure_node_cached(&mut resolved, a);
+            let ib = self.ensure_node_cached(&mut resolved, b);
+            self.graph.add_edge(ia, ib, weight);
+        }
+    }
+
+    /// [NamedGraph::ensure_node] through `cache`, for [NamedGraph::add_edges_by_name].
+    fn ensure_node_cached(
+        &mut self,
+        cache: &mut HashMap<String, NodeIndex>,
+        name: String,
+    ) -> NodeIndex {
+        match cache.entry(name) {
+            Entry::Occupied(entry) => *entry.get(),
+            Entry::Vacant(entry) => {
+                let idx = self.ensure_node(entry.key().clone());
+                *entry.insert(idx)
+            }
+        }
+    }
+
+    /// Like [NamedGraph::add_edge_by_name], but if `a -> b` already exists its weight is folded
+    /// with `combine` instead of adding a parallel edge, e.g. `|w, n| *w += n` to count pairs.
+    pub fn add_or_accumulate_edge(
+        &mut self,
+        a: &str,
+        b: &str,
+        weight: E,
+        combine: impl Fn(&mut E, E),
+    ) -> petgraph::graph::EdgeIndex {
+        let ia = self.ensure_node(a.to_string());
+        let ib = self.ensure_node(b.to_string());
+        match self.graph.find_edge(ia, ib) {
+            Some(ei) => {
+                combine(&mut self.graph[ei], weight);
+                ei
+            }
+            None => self.graph.add_edge(ia, ib, weight),
+        }
+    }
+
     pub fn remove_node_by_name(&mut self, name: &str) -> Option<String> {
         let idx = self.name_map.remove(name)?;
+        self.node_to_subgraph.remove(name);
+        self.node_attrs.remove(name);
+        self.edge_attrs.retain(|(s, t), _| s != name && t != name);
         self.graph.remove_node(idx)
     }
 
+    /// Removes one edge `a -> b`. Its DOT attributes are shared by every parallel edge, so they
+    /// are only dropped with the last one.
     pub fn remove_edge_by_names(&mut self, a: &str, b: &str) -> Option<E> {
         let ia = self.get_node_index(a)?;
         let ib = self.get_node_index(b)?;
-        if let Some(ei) = self.graph.find_edge(ia, ib) {
-            self.graph.remove_edge(ei)
-        } else {
-            None
+        let ei = self.graph.find_edge(ia, ib)?;
+        let weight = self.graph.remove_edge(ei);
+        if self.graph.find_edge(ia, ib).is_none() {
+            self.edge_attrs.remove(&self.edge_key(a, b));
+        }
+        weight
+    }
+
+    /// Weight of the edge `a -> b`; for undirected graphs `b -> a` is found as well.
+    pub fn edge_weight_by_names(&self, a: &str, b: &str) -> Option<&E> {
+        let ei = self
+            .graph
+            .find_edge(self.get_node_index(a)?, self.get_node_index(b)?)?;
+        self.graph.edge_weight(ei)
+    }
+
+    /// Weights of every parallel edge `a -> b`, in no particular order; for undirected graphs
+    /// edges stored as `b -> a` are included. Empty if either node is missing.
+    pub fn edges_between(&self, a: &str, b: &str) -> Vec<&E> {
+        match (self.get_node_index(a), self.get_node_index(b)) {
+            (Some(ia), Some(ib)) => self
+                .graph
+                .edges_connecting(ia, ib)
+                .map(|e| e.weight())
+                .collect(),
+            _ => Vec::new(),
         }
     }
 
+    /// Mutable weight of the edge found as in [NamedGraph::edge_weight_by_names].
+    pub fn edge_weight_by_names_mut(&mut self, a: &str, b: &str) -> Option<&mut E> {
+        let ei = self
+            .graph
+            .find_edge(self.get_node_index(a)?, self.get_node_index(b)?)?;
+        self.graph.edge_weight_mut(ei)
+    }
+
+    /// Replaces the weight of an existing edge, found as in [NamedGraph::edge_weight_by_names],
+    /// and returns the old one. Returns `None` and changes nothing if there is no such edge.
+    pub fn update_edge_by_name(&mut self, a: &str, b: &str, weight: E) -> Option<E> {
+        let old = self.edge_weight_by_names_mut(a, b)?;
+        Some(std::mem::replace(old, weight))
+    }
+
+    /// Removes every node, edge, subgraph assignment and node or edge attribute, keeping the
+    /// allocated capacity so the graph can be rebuilt without reallocating.
+    pub fn clear(&mut self) {
+        self.graph.clear();
+        self.name_map.clear();
+        self.node_to_subgraph.clear();
+        self.subgraph_labels.clear();
+        self.subgraph_parent.clear();
+        self.node_attrs.clear();
+        self.edge_attrs.clear();
+    }
+
+    /// Removes every edge (and its attributes) but keeps the nodes and their subgraphs.
+    pub fn clear_edges(&mut self) {
+        self.graph.clear_edges();
+        self.edge_attrs.clear();
+    }
+
     pub fn neighbors_by_name(&self, name: &str) -> Option<Vec<String>> {
+        Some(
+            self.neighbors_iter_by_name(name)?
+                .map(String::from)
+                .collect(),
+        )
+    }
+
+    /// Like [NamedGraph::neighbors_by_name], but borrows the names instead of cloning them.
+    pub fn neighbors_iter_by_name<'a>(
+        &'a self,
+        name: &str,
+    ) -> Option<impl Iterator<Item = &'a str> + use<'a, E, Ty>> {
         let idx = self.get_node_index(name)?;
-        let mut res = Vec::new();
-        for n in self.graph.neighbors(idx) {
-            if let Some(w) = self.graph.node_weight(n) {
-                res.push(w.clone());
-            }
-        }
-        Some(res)
+        Some(
+            self.graph
+                .neighbors(idx)
+                .filter_map(|n| self.graph.node_weight(n).map(String::as_str)),
+        )
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.graph.node_count()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.graph.edge_count()
+    }
+
+    pub fn contains_node(&self, name: &str) -> bool {
+        self.name_map.contains_key(name)
+    }
+
+    /// Whether there is an edge `a -> b`; for undirected graphs `b -> a` counts as well.
+    pub fn has_edge(&self, a: &str, b: &str) -> bool {
+        self.edge_weight_by_names(a, b).is_some()
+    }
+
+    /// Number of edges touching `name` in either direction, or `None` if there is no such node.
+    pub fn degree_by_name(&self, name: &str) -> Option<usize> {
+        let idx = self.get_node_index(name)?;
+        Some(self.graph.neighbors_undirected(idx).count())
     }
 
     pub fn node_names(&self) -> Vec<String> {
@@ -166,6 +573,20 @@ where
         };
         if let Some(w) = self.graph.node_weight_mut(idx) {
             *w = new_name.clone();
+            self.edge_attrs = std::mem::take(&mut self.edge_attrs)
+                .into_iter()
+                .map(|((s, t), attrs)| {
+                    let s = if s == old_name { new_name.clone() } else { s };
+                    let t = if t == old_name { new_name.clone() } else { t };
+                    (self.edge_key(&s, &t), attrs)
+                })
+                .collect();
+            if let Some(subgraph) = self.node_to_subgraph.remove(old_name) {
+                self.node_to_subgraph.insert(new_name.clone(), subgraph);
+            }
+            if let Some(attrs) = self.node_attrs.remove(old_name) {
+                self.node_attrs.insert(new_name.clone(), attrs);
+            }
             self.name_map.insert(new_name, idx);
             true
         } else {
@@ -173,17 +594,815 @@ where
         }
     }
 
+    /// Collapses `remove` into `keep`: every edge touching `remove` is moved over to `keep`
+    /// with its weight and attributes, then `remove` is deleted. Edges between the two become a
+    /// single self-loop on `keep`, and none at all if `keep` already had one. Other parallel
+    /// edges are kept, so `A -> C` and `B -> C` leave two `A -> C` edges. When edges end up
+    /// between the same nodes, their attributes are merged: those of an edge `keep` already had
+    /// win, then those of the moved edges in order of their endpoint names. `keep` takes over
+    /// the subgraph of `remove` if it had none. Returns false if either node is missing or they
+    /// are the same node.
+    pub fn contract_nodes(&mut self, keep: &str, remove: &str) -> bool {
+        let (Some(ik), Some(ir)) = (self.get_node_index(keep), self.get_node_index(remove)) else {
+            return false;
+        };
+        if ik == ir {
+            return false;
+        }
+
+        let touching: Vec<_> = self
+            .graph
+            .edge_references()
+            .filter(|e| e.source() == ir || e.target() == ir)
+            .map(|e| e.id())
+            .collect();
+        let mut has_self_loop = self.graph.find_edge(ik, ik).is_some();
+        for ei in touching {
+            let (s, t) = self.graph.edge_endpoints(ei).unwrap();
+            let weight = self.graph.remove_edge(ei).unwrap();
+            let s = if s == ir { ik } else { s };
+            let t = if t == ir { ik } else { t };
+            if s == ik && t == ik {
+                if has_self_loop {
+                    continue;
+                }
+                has_self_loop = true;
+            }
+            self.graph.add_edge(s, t, weight);
+        }
+
+        let mut moved: Vec<_> = self
+            .edge_attrs
+            .keys()
+            .filter(|(s, t)| s == remove || t == remove)
+            .cloned()
+            .collect();
+        moved.sort();
+        for old_key in moved {
+            let attrs = self.edge_attrs.remove(&old_key).unwrap();
+            let rename = |n: &str| if n == remove { keep } else { n }.to_string();
+            let key = self.edge_key(&rename(&old_key.0), &rename(&old_key.1));
+            let merged = self.edge_attrs.entry(key).or_default();
+            for (k, v) in attrs {
+                if !merged.iter().any(|(existing, _)| *existing == k) {
+                    merged.push((k, v));
+                }
+            }
+        }
+        if let Some(subgraph) = self.node_to_subgraph.get(remove).cloned() {
+            self.node_to_subgraph
+                .entry(keep.to_string())
+                .or_insert(subgraph);
+        }
+        self.remove_node_by_name(remove);
+        true
+    }
+
     pub fn set_node_subgraph(&mut self, node_name: &str, subgraph_name: impl Into<String>) {
         if self.name_map.contains_key(node_name) {
+            let subgraph_name = subgraph_name.into();
+            self.subgraph_labels.insert(subgraph_name.clone());
             self.node_to_subgraph
-                .insert(node_name.to_string(), subgraph_name.into());
+                .insert(node_name.to_string(), subgraph_name);
+        }
+    }
+
+    /// Nests subgraph `child` inside `parent`, so [NamedGraph::to_dot] draws its cluster within
+    /// the parent's, replacing any previous parent. Fails without changing anything if `parent`
+    /// is `child` or nested inside it.
+    pub fn set_subgraph_parent(&mut self, child: &str, parent: &str) -> Result<(), GraphError> {
+        let mut ancestor = Some(parent);
+        while let Some(name) = ancestor {
+            if name == child {
+                return Err(GraphError::SubgraphCycle {
+                    child: child.to_string(),
+                    parent: parent.to_string(),
+                });
+            }
+            ancestor = self.subgraph_parent.get(name).map(String::as_str);
+        }
+        self.subgraph_parent
+            .insert(child.to_string(), parent.to_string());
+        Ok(())
+    }
+
+    /// The subgraph `child` is nested in, if any.
+    pub fn subgraph_parent(&self, child: &str) -> Option<&str> {
+        self.subgraph_parent.get(child).map(String::as_str)
+    }
+
+    /// Every subgraph label ever assigned, sorted, with its current member count.
+    pub fn subgraph_report(&self) -> Vec<SubgraphInfo> {
+        let mut members: HashMap<&str, usize> = self
+            .subgraph_labels
+            .iter()
+            .map(|label| (label.as_str(), 0))
+            .collect();
+        for label in self.node_to_subgraph.values() {
+            *members.entry(label.as_str()).or_insert(0) += 1;
+        }
+
+        let normalize = |label: &str| label.trim().to_lowercase();
+        let mut labels: Vec<&str> = members.keys().copied().collect();
+        labels.sort();
+        labels
+            .iter()
+            .map(|&label| SubgraphInfo {
+                label: label.to_string(),
+                members: members[label],
+                similar_labels: labels
+                    .iter()
+                    .filter(|&&other| other != label && normalize(other) == normalize(label))
+                    .map(|other| other.to_string())
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Sorted names of the nodes assigned to `subgraph`, e.g. to pass to
+    /// [NamedGraph::induced_subgraph].
+    pub fn subgraph_members(&self, subgraph: &str) -> Vec<String> {
+        let mut members: Vec<String> = self
+            .node_to_subgraph
+            .iter()
+            .filter(|(node, label)| *label == subgraph && self.name_map.contains_key(*node))
+            .map(|(node, _)| node.clone())
+            .collect();
+        members.sort();
+        members
+    }
+
+    /// Name clusters in [NamedGraph::to_dot] with
+    /// [graph_delta::parser::stable_cluster_id] of the subgraph name instead of `cluster_<index>`,
+    /// so ids don't shift when subgraphs are added and match graph-delta's stable reconstruction.
+    pub fn set_stable_cluster_ids(&mut self, enabled: bool) {
+        self.stable_cluster_ids = enabled;
+    }
+
+    /// Sets an extra DOT attribute (`shape`, `color`, ...) on a node, emitted by
+    /// [NamedGraph::to_dot] in key order. Does nothing if there is no such node.
+    pub fn set_node_attr(&mut self, name: &str, key: &str, value: &str) {
+        if self.name_map.contains_key(name) {
+            self.node_attrs
+                .entry(name.to_string())
+                .or_default()
+                .insert(key.to_string(), value.to_string());
+        }
+    }
+
+    /// Extra DOT attributes set on a node.
+    pub fn node_attrs(&self, name: &str) -> Option<&HashMap<String, String>> {
+        self.node_attrs.get(name)
+    }
+
+    /// `"name"` with its extra attributes, if any, as a DOT node statement without the `;`.
+    fn dot_node(&self, name: &str) -> String {
+        let quoted = format!("\"{}\"", escape_quotes(name));
+        let Some(attrs) = self.node_attrs.get(name).filter(|attrs| !attrs.is_empty()) else {
+            return quoted;
+        };
+        let mut attrs: Vec<_> = attrs.iter().collect();
+        attrs.sort();
+        let attr_list: Vec<String> = attrs
+            .into_iter()
+            .map(|(k, v)| format!("{}={}", k, dot_attr_value(v)))
+            .collect();
+        format!("{} [{}]", quoted, attr_list.join(","))
+    }
+
+    /// Key for `edge_attrs`; undirected edges are stored with their endpoints sorted so either
+    /// order finds them.
+    fn edge_key(&self, a: &str, b: &str) -> (String, String) {
+        if !self.graph.is_directed() && b < a {
+            (b.to_string(), a.to_string())
+        } else {
+            (a.to_string(), b.to_string())
+        }
+    }
+
+    /// Sets an extra DOT attribute (`color`, `style`, ...) on the edge `a -> b`, emitted by
+    /// [NamedGraph::to_dot] after the label. Returns false if there is no such edge.
+    pub fn set_edge_attr(
+        &mut self,
+        a: &str,
+        b: &str,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> bool {
+        let (Some(ia), Some(ib)) = (self.get_node_index(a), self.get_node_index(b)) else {
+            return false;
+        };
+        if self.graph.find_edge(ia, ib).is_none() {
+            return false;
+        }
+        let (key, value) = (key.into(), value.into());
+        let attrs = self.edge_attrs.entry(self.edge_key(a, b)).or_default();
+        match attrs.iter_mut().find(|(k, _)| *k == key) {
+            Some(existing) => existing.1 = value,
+            None => attrs.push((key, value)),
+        }
+        true
+    }
+
+    /// Extra DOT attributes set on the edge `a -> b`, in the order they were first set.
+    pub fn edge_attrs(&self, a: &str, b: &str) -> Option<&[(String, String)]> {
+        self.edge_attrs
+            .get(&self.edge_key(a, b))
+            .map(|attrs| attrs.as_slice())
+    }
+
+    /// Rebuilds this graph as a directed one, keeping names, subgraphs and edge attributes.
+    /// Undirected edges become a pair of opposite directed edges (self-loops stay single), so
+    /// every node keeps the same neighbors; directed graphs are copied as they are.
+    pub fn into_directed(self) -> NamedGraph<E, Directed>
+    where
+        E: Clone,
+    {
+        let mirror = !self.graph.is_directed();
+        self.rebuild(mirror)
+    }
+
+    /// Rebuilds this graph as an undirected one, keeping names, subgraphs and edge attributes.
+    /// Each directed edge becomes one undirected edge, so `A -> B` plus `B -> A` turn into two
+    /// parallel `A -- B` edges; their attributes are merged, with the later edge winning.
+    pub fn into_undirected(self) -> NamedGraph<E, Undirected>
+    where
+        E: Clone,
+    {
+        self.rebuild(false)
+    }
+
+    fn rebuild<NewTy: EdgeType>(self, mirror: bool) -> NamedGraph<E, NewTy>
+    where
+        E: Clone,
+    {
+        let mut graph =
+            StableGraph::with_capacity(self.graph.node_count(), self.graph.edge_count());
+        let mut name_map = HashMap::new();
+        for name in self.graph.node_weights() {
+            name_map.insert(name.clone(), graph.add_node(name.clone()));
+        }
+        for e in self.graph.edge_references() {
+            let s = name_map[&self.graph[e.source()]];
+            let t = name_map[&self.graph[e.target()]];
+            graph.add_edge(s, t, e.weight().clone());
+            if mirror && s != t {
+                graph.add_edge(t, s, e.weight().clone());
+            }
+        }
+
+        let mut out = NamedGraph {
+            graph,
+            name_map,
+            node_to_subgraph: self.node_to_subgraph,
+            subgraph_labels: self.subgraph_labels,
+            subgraph_parent: self.subgraph_parent,
+            node_attrs: self.node_attrs,
+            edge_attrs: HashMap::new(),
+            stable_cluster_ids: self.stable_cluster_ids,
+        };
+        for ((s, t), attrs) in self.edge_attrs {
+            if mirror {
+                out.edge_attrs.insert(out.edge_key(&t, &s), attrs.clone());
+            }
+            out.edge_attrs.insert(out.edge_key(&s, &t), attrs);
+        }
+        out
+    }
+
+    /// Adds every node and edge of `other` to this graph. Nodes are matched by name, so a name
+    /// already present here is reused rather than duplicated, and its subgraph and node
+    /// attributes here win over those from `other`, as do subgraph parents. Edges are always
+    /// added, so an edge present in both graphs ends up as two parallel edges.
+    pub fn merge(&mut self, other: &NamedGraph<E, Ty>)
+    where
+        E: Clone,
+    {
+        for name in other.graph.node_weights() {
+            self.ensure_node(name.as_str());
+        }
+        for e in other.graph.edge_references() {
+            let s = &other.graph[e.source()];
+            let t = &other.graph[e.target()];
+            self.add_edge_by_name(s, t, e.weight().clone());
+        }
+        self.subgraph_labels
+            .extend(other.subgraph_labels.iter().cloned());
+        for (node, subgraph) in &other.node_to_subgraph {
+            if other.name_map.contains_key(node) {
+                self.node_to_subgraph
+                    .entry(node.clone())
+                    .or_insert_with(|| subgraph.clone());
+            }
+        }
+        for (child, parent) in &other.subgraph_parent {
+            if !self.subgraph_parent.contains_key(child) {
+                // Skipped if the parents already set here would make it a cycle.
+                let _ = self.set_subgraph_parent(child, parent);
+            }
+        }
+        for (node, attrs) in &other.node_attrs {
+            self.node_attrs
+                .entry(node.clone())
+                .or_insert_with(|| attrs.clone());
+        }
+        for ((s, t), attrs) in &other.edge_attrs {
+            let key = self.edge_key(s, t);
+            self.edge_attrs.entry(key).or_insert_with(|| attrs.clone());
+        }
+    }
+
+    /// Nodes within `radius` hops of `center` (following edge direction in directed graphs) with
+    /// every edge between them, keeping subgraphs and edge attributes. `None` if `center` is not
+    /// in the graph.
+    pub fn ego_network(&self, center: &str, radius: usize) -> Option<NamedGraph<E, Ty>>
+    where
+        E: Clone,
+    {
+        let dist = self.hop_distances(self.get_node_index(center)?, radius);
+        Some(self.induced(|idx| dist.contains_key(&idx)))
+    }
+
+    /// Only the named nodes, with every edge between two of them and their subgraphs and
+    /// attributes. Names not in the graph are ignored.
+    pub fn induced_subgraph(&self, node_names: &[&str]) -> NamedGraph<E, Ty>
+    where
+        E: Clone,
+    {
+        let wanted: HashSet<&str> = node_names.iter().copied().collect();
+        self.induced(|idx| wanted.contains(self.graph[idx].as_str()))
+    }
+
+    /// Names of the nodes reachable from `name` in at most `k` hops, following edge direction
+    /// like [NamedGraph::ego_network], sorted and without `name` itself.
+    pub fn neighbors_within(&self, name: &str, k: usize) -> Option<Vec<String>> {
+        let start = self.get_node_index(name)?;
+        let mut names: Vec<String> = self
+            .hop_distances(start, k)
+            .into_keys()
+            .filter(|&idx| idx != start)
+            .map(|idx| self.graph[idx].clone())
+            .collect();
+        names.sort();
+        Some(names)
+    }
+
+    /// Node names along a path from `from` to `to` with the fewest hops (following edge direction
+    /// for directed graphs), both ends included. `None` if either node is missing or `to` cannot
+    /// be reached.
+    pub fn shortest_path_by_name(&self, from: &str, to: &str) -> Option<Vec<String>> {
+        let (start, goal) = (self.get_node_index(from)?, self.get_node_index(to)?);
+        let mut prev: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+        while let Some(v) = queue.pop_front() {
+            if v == goal {
+                break;
+            }
+            for w in self.graph.neighbors(v) {
+                if w != start && !prev.contains_key(&w) {
+                    prev.insert(w, v);
+                    queue.push_back(w);
+                }
+            }
+        }
+
+        let mut path = vec![goal];
+        while let Some(&v) = path.last().filter(|&&v| v != start) {
+            path.push(*prev.get(&v)?);
+        }
+        Some(
+            path.iter()
+                .rev()
+                .map(|&idx| self.graph[idx].clone())
+                .collect(),
+        )
+    }
+
+    /// Lowest-cost path from `from` to `to` and its total weight, like
+    /// [NamedGraph::shortest_path_by_name] but summing edge weights, which must not be
+    /// negative. Runs Dijkstra through petgraph's `astar` with a zero heuristic, which also
+    /// returns the path.
+    pub fn shortest_weighted_path_by_name(&self, from: &str, to: &str) -> Option<(Vec<String>, f64)>
+    where
+        E: Into<f64> + Copy,
+    {
+        let (start, goal) = (self.get_node_index(from)?, self.get_node_index(to)?);
+        let (cost, path) = astar(
+            &self.graph,
+            start,
+            |idx| idx == goal,
+            |e| (*e.weight()).into(),
+            |_| 0.0,
+        )?;
+        let names = path
+            .into_iter()
+            .map(|idx| self.graph[idx].clone())
+            .collect();
+        Some((names, cost))
+    }
+
+    /// BFS distances from `start` to every node at most `max_hops` away, `start` included.
+    fn hop_distances(&self, start: NodeIndex, max_hops: usize) -> HashMap<NodeIndex, usize> {
+        let mut dist: HashMap<NodeIndex, usize> = HashMap::from([(start, 0)]);
+        let mut queue = VecDeque::from([start]);
+        while let Some(v) = queue.pop_front() {
+            let d = dist[&v];
+            if d == max_hops {
+                continue;
+            }
+            for w in self.graph.neighbors(v) {
+                if let Entry::Vacant(entry) = dist.entry(w) {
+                    entry.insert(d + 1);
+                    queue.push_back(w);
+                }
+            }
+        }
+        dist
+    }
+
+    /// Copy of the nodes `keep` selects and the edges between them, with their subgraphs and
+    /// edge attributes. Node order is preserved.
+    fn induced(&self, keep: impl Fn(NodeIndex) -> bool) -> NamedGraph<E, Ty>
+    where
+        E: Clone,
+    {
+        let mut graph = StableGraph::default();
+        let mut name_map = HashMap::new();
+        for idx in self.graph.node_indices().filter(|&idx| keep(idx)) {
+            let name = self.graph[idx].clone();
+            name_map.insert(name.clone(), graph.add_node(name));
+        }
+        for e in self.graph.edge_references() {
+            if let (Some(&s), Some(&t)) = (
+                name_map.get(&self.graph[e.source()]),
+                name_map.get(&self.graph[e.target()]),
+            ) {
+                graph.add_edge(s, t, e.weight().clone());
+            }
+        }
+
+        let kept = |name: &String| name_map.contains_key(name);
+        let node_to_subgraph: HashMap<String, String> = self
+            .node_to_subgraph
+            .iter()
+            .filter(|(node, _)| kept(node))
+            .map(|(node, subgraph)| (node.clone(), subgraph.clone()))
+            .collect();
+        NamedGraph {
+            subgraph_labels: node_to_subgraph.values().cloned().collect(),
+            node_to_subgraph,
+            subgraph_parent: self.subgraph_parent.clone(),
+            node_attrs: self
+                .node_attrs
+                .iter()
+                .filter(|(node, _)| kept(node))
+                .map(|(node, attrs)| (node.clone(), attrs.clone()))
+                .collect(),
+            edge_attrs: self
+                .edge_attrs
+                .iter()
+                .filter(|((s, t), _)| kept(s) && kept(t))
+                .map(|(key, attrs)| (key.clone(), attrs.clone()))
+                .collect(),
+            stable_cluster_ids: self.stable_cluster_ids,
+            graph,
+            name_map,
+        }
+    }
+
+    /// Node names grouped per connected component, ignoring edge direction (weak connectivity
+    /// for directed graphs). Each group is sorted and the groups are sorted by their first name.
+    pub fn connected_components(&self) -> Vec<Vec<String>> {
+        let mut sets = UnionFind::<usize>::new(self.graph.node_bound());
+        for e in self.graph.edge_references() {
+            sets.union(e.source().index(), e.target().index());
+        }
+        let mut groups: HashMap<usize, Vec<String>> = HashMap::new();
+        for idx in self.graph.node_indices() {
+            groups
+                .entry(sets.find(idx.index()))
+                .or_default()
+                .push(self.graph[idx].clone());
+        }
+        sorted_groups(groups.into_values().collect())
+    }
+
+    /// Edges over the maximum possible for this node count (`n(n-1)` directed, half that
+    /// undirected). Graphs with fewer than two nodes have density 0.
+    pub fn density(&self) -> f64 {
+        let n = self.graph.node_count() as f64;
+        if n < 2.0 {
+            return 0.0;
+        }
+        let possible = n * (n - 1.0);
+        let m = self.graph.edge_count() as f64;
+        if self.graph.is_directed() {
+            m / possible
+        } else {
+            2.0 * m / possible
+        }
+    }
+
+    /// Longest shortest path in hops, found by a BFS from every node (following edge direction
+    /// for directed graphs). `None` if the graph is empty or some node cannot reach another.
+    pub fn diameter(&self) -> Option<usize> {
+        let n = self.graph.node_count();
+        if n == 0 {
+            return None;
+        }
+        let mut diameter = 0;
+        for start in self.graph.node_indices() {
+            let mut dist: HashMap<NodeIndex, usize> = HashMap::from([(start, 0)]);
+            let mut queue = VecDeque::from([start]);
+            while let Some(v) = queue.pop_front() {
+                let d = dist[&v];
+                for w in self.graph.neighbors(v) {
+                    if let Entry::Vacant(entry) = dist.entry(w) {
+                        entry.insert(d + 1);
+                        queue.push_back(w);
+                    }
+                }
+            }
+            if dist.len() < n {
+                return None;
+            }
+            diameter = diameter.max(dist.values().copied().max().unwrap_or(0));
+        }
+        Some(diameter)
+    }
+
+    /// Compact text adjacency list, one `A -> B, C` line per node (`--` for undirected graphs),
+    /// with nodes and neighbors sorted. Handy for logs and snapshot tests.
+    pub fn to_adjacency_list(&self) -> String {
+        let edge_op = if self.graph.is_directed() { "->" } else { "--" };
+
+        let mut names = self.node_names();
+        names.sort();
+
+        let mut out = String::new();
+        for name in &names {
+            let mut neighbors = self.neighbors_by_name(name).unwrap_or_default();
+            neighbors.sort();
+            neighbors.dedup();
+            if neighbors.is_empty() {
+                out.push_str(&format!("{} {}\n", name, edge_op));
+            } else {
+                out.push_str(&format!("{} {} {}\n", name, edge_op, neighbors.join(", ")));
+            }
+        }
+        out
+    }
+
+    /// Sorted node names and a 0/1 matrix indexed by them, `m[i][j] == 1` when there is an edge
+    /// from `names[i]` to `names[j]`. Undirected graphs give a symmetric matrix.
+    pub fn adjacency_matrix(&self) -> (Vec<String>, Vec<Vec<u8>>) {
+        let (names, weighted) = self.adjacency_matrix_weighted(|_| 1.0);
+        let matrix = weighted
+            .into_iter()
+            .map(|row| row.into_iter().map(|w| u8::from(w != 0.0)).collect())
+            .collect();
+        (names, matrix)
+    }
+
+    /// Like [NamedGraph::adjacency_matrix], with each entry the sum of `f` over the edges
+    /// between the two nodes (0 where there are none).
+    pub fn adjacency_matrix_weighted(&self, f: impl Fn(&E) -> f64) -> (Vec<String>, Vec<Vec<f64>>) {
+        let mut names = self.node_names();
+        names.sort();
+        let pos: HashMap<&str, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), i))
+            .collect();
+
+        let mut matrix = vec![vec![0.0; names.len()]; names.len()];
+        for e in self.graph.edge_references() {
+            let i = pos[self.graph[e.source()].as_str()];
+            let j = pos[self.graph[e.target()].as_str()];
+            let w = f(e.weight());
+            matrix[i][j] += w;
+            if !self.graph.is_directed() && i != j {
+                matrix[j][i] += w;
+            }
+        }
+        (names, matrix)
+    }
+
+    /// Mermaid flowchart (`flowchart TD` when directed, `graph LR` when undirected) for
+    /// embedding in Markdown. Nodes are declared as `n0["name"]`, numbered in name order, and
+    /// edges use the weight's `Display` as their label (`n0 -->|label| n1`), omitted when empty.
+    /// Subgraphs become `subgraph s0 ["name"] ... end` blocks, nested like in
+    /// [NamedGraph::to_dot].
+    pub fn to_mermaid(&self) -> String
+    where
+        E: Display,
+    {
+        let (header, edge_op) = if self.graph.is_directed() {
+            ("flowchart TD", "-->")
+        } else {
+            ("graph LR", "---")
+        };
+
+        let mut names = self.node_names();
+        names.sort();
+        let ids: HashMap<&str, String> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), format!("n{}", i)))
+            .collect();
+
+        let mut out = format!("{}\n", header);
+        let clusters = self.clusters(&names, |i, _| format!("s{}", i));
+        for subgraph_name in &clusters.roots {
+            write_mermaid_subgraph(&mut out, subgraph_name, 1, &clusters, &ids);
+        }
+        for name in &clusters.loose {
+            out.push_str(&mermaid_node(name, &ids, 1));
+        }
+
+        let mut edges: Vec<(&str, &str, String)> = self
+            .graph
+            .edge_references()
+            .map(|e| {
+                (
+                    self.graph[e.source()].as_str(),
+                    self.graph[e.target()].as_str(),
+                    e.weight().to_string(),
+                )
+            })
+            .collect();
+        edges.sort();
+        for (s, t, label) in edges {
+            let (s, t) = (&ids[s], &ids[t]);
+            if label.is_empty() {
+                out.push_str(&format!("    {} {} {}\n", s, edge_op, t));
+            } else {
+                out.push_str(&format!(
+                    "    {} {}|{}| {}\n",
+                    s,
+                    edge_op,
+                    mermaid_text(&label),
+                    t
+                ));
+            }
+        }
+        out
+    }
+
+    pub fn to_dot(&self) -> String
+    where
+        E: Clone + Display,
+        (String, String, E): Ord,
+    {
+        let mut names = self.node_names();
+        names.sort();
+        self.write_dot(&names, &self.sorted_edges())
+    }
+
+    /// Every edge, sorted and independent of insertion order.
+    fn sorted_edges(&self) -> Vec<(String, String, E)>
+    where
+        E: Clone,
+        (String, String, E): Ord,
+    {
+        let mut edges = self.edges_with_names();
+        if !self.graph.is_directed() {
+            // An undirected edge reads the same either way round, so write the smaller name
+            // first to keep the output independent of insertion order.
+            for (s, t, _) in &mut edges {
+                if t < s {
+                    std::mem::swap(s, t);
+                }
+            }
+        }
+        edges.sort();
+        edges
+    }
+
+    /// Like [NamedGraph::to_dot], but keeps the node order of a previous render so small edits
+    /// don't make Graphviz reshuffle the whole layout: nodes in `prev_order` come first in that
+    /// order, new nodes follow sorted, and edges are ordered by their endpoints' positions.
+    /// Returns the DOT and the node order to pass in next time.
+    pub fn to_dot_stable(&self, prev_order: &[String]) -> (String, Vec<String>)
+    where
+        E: Clone + Display,
+    {
+        let mut seen = HashSet::new();
+        let mut order: Vec<String> = prev_order
+            .iter()
+            .filter(|name| self.name_map.contains_key(*name) && seen.insert(name.as_str()))
+            .cloned()
+            .collect();
+        let mut added: Vec<String> = self
+            .node_names()
+            .into_iter()
+            .filter(|name| !seen.contains(name.as_str()))
+            .collect();
+        added.sort();
+        order.extend(added);
+
+        let position: HashMap<&str, usize> = order
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), i))
+            .collect();
+        let mut edges = self.edges_with_names();
+        // Stable, so parallel edges keep their insertion order.
+        edges.sort_by_key(|(s, t, _)| (position[s.as_str()], position[t.as_str()]));
+        let dot = self.write_dot(&order, &edges);
+        (dot, order)
+    }
+
+    /// Groups the nodes in `order` by subgraph and arranges the subgraphs into a tree, naming
+    /// the i-th subgraph (sorted by name) with `cluster_id`.
+    fn clusters<'a>(
+        &'a self,
+        order: &'a [String],
+        cluster_id: impl Fn(usize, &str) -> String,
+    ) -> Clusters<'a> {
+        let mut clusters = Clusters::default();
+        for node_name in order {
+            match self.node_to_subgraph.get(node_name) {
+                Some(subgraph_name) => clusters
+                    .nodes
+                    .entry(subgraph_name.as_str())
+                    .or_default()
+                    .push(node_name),
+                None => clusters.loose.push(node_name),
+            }
+        }
+
+        // Parents are drawn even without nodes of their own, so they can hold their children.
+        let mut drawn: HashSet<&str> = clusters.nodes.keys().copied().collect();
+        for &subgraph_name in clusters.nodes.keys() {
+            let mut current = subgraph_name;
+            while let Some(parent) = self.subgraph_parent(current) {
+                if !drawn.insert(parent) {
+                    break;
+                }
+                current = parent;
+            }
+        }
+        let mut subgraph_keys: Vec<&str> = drawn.into_iter().collect();
+        subgraph_keys.sort();
+
+        for (i, &subgraph_name) in subgraph_keys.iter().enumerate() {
+            clusters
+                .ids
+                .insert(subgraph_name, cluster_id(i, subgraph_name));
+            match self.subgraph_parent(subgraph_name) {
+                Some(parent) => clusters
+                    .children
+                    .entry(parent)
+                    .or_default()
+                    .push(subgraph_name),
+                None => clusters.roots.push(subgraph_name),
+            }
+        }
+        clusters
+    }
+
+    /// DOT id of the `index`-th subgraph, see [NamedGraph::set_stable_cluster_ids].
+    fn cluster_id(&self, index: usize, subgraph_name: &str) -> String {
+        if self.stable_cluster_ids {
+            graph_delta::parser::stable_cluster_id(subgraph_name)
+        } else {
+            format!("cluster_{}", index)
+        }
+    }
+
+    /// Writes the cluster of `subgraph_name` at nesting `depth`, with its nodes followed by the
+    /// clusters of its child subgraphs.
+    fn write_cluster(
+        &self,
+        out: &mut String,
+        subgraph_name: &str,
+        depth: usize,
+        clusters: &Clusters,
+    ) {
+        let indent = "    ".repeat(depth);
+        out.push_str(&format!(
+            "{}subgraph {} {{\n",
+            indent, clusters.ids[subgraph_name]
+        ));
+        out.push_str(&format!(
+            "{}    label = \"{}\";\n",
+            indent,
+            escape_quotes(subgraph_name)
+        ));
+        for node_name in clusters.nodes.get(subgraph_name).into_iter().flatten() {
+            out.push_str(&format!("{}    {};\n", indent, self.dot_node(node_name)));
         }
+        for child in clusters.children.get(subgraph_name).into_iter().flatten() {
+            self.write_cluster(out, child, depth + 1, clusters);
+        }
+        out.push_str(&format!("{}}}\n", indent));
     }
 
-    pub fn to_dot(&self) -> String
+    /// DOT with nodes in `order` (every node exactly once) and `edges` in the given order.
+    fn write_dot(&self, order: &[String], edges: &[(String, String, E)]) -> String
     where
-        E: Clone + Display,
-        (String, String, E): Ord,
+        E: Display,
     {
         let mut dot_output = String::new();
         let graph_type = if self.graph.is_directed() {
@@ -195,56 +1414,34 @@ where
 
         dot_output.push_str(&format!("{} G {{\n", graph_type));
 
-        let mut subgraph_nodes: HashMap<String, Vec<String>> = HashMap::new();
-        let mut root_nodes: Vec<String> = Vec::new();
-
-        for node_name in self.graph.node_weights().cloned() {
-            if let Some(subgraph_name) = self.node_to_subgraph.get(&node_name) {
-                subgraph_nodes
-                    .entry(subgraph_name.clone())
-                    .or_default()
-                    .push(node_name);
-            } else {
-                root_nodes.push(node_name);
-            }
-        }
-        root_nodes.sort();
-
-        let mut subgraph_keys: Vec<_> = subgraph_nodes.keys().cloned().collect();
-        subgraph_keys.sort();
-
-        for (i, subgraph_name) in subgraph_keys.iter().enumerate() {
-            dot_output.push_str(&format!("    subgraph cluster_{} {{\n", i));
-            dot_output.push_str(&format!("        label = \"{}\";\n", subgraph_name));
-            if let Some(nodes) = subgraph_nodes.get(subgraph_name) {
-                let mut sorted_nodes = nodes.clone();
-                sorted_nodes.sort();
-                for node_name in &sorted_nodes {
-                    dot_output.push_str(&format!("        \"{}\";\n", node_name));
-                }
-            }
-            dot_output.push_str("    }\n");
+        let clusters = self.clusters(order, |i, subgraph_name| self.cluster_id(i, subgraph_name));
+        for subgraph_name in &clusters.roots {
+            self.write_cluster(&mut dot_output, subgraph_name, 1, &clusters);
         }
 
-        for node_name in &root_nodes {
-            dot_output.push_str(&format!("    \"{}\";\n", node_name));
+        for node_name in &clusters.loose {
+            dot_output.push_str(&format!("    {};\n", self.dot_node(node_name)));
         }
 
-        let mut sorted_edges = self.edges_with_names();
-        sorted_edges.sort();
-
-        for (s, t, w) in &sorted_edges {
+        for (s, t, w) in edges {
             let edge_label = w.to_string();
             let label_attr =
                 if edge_label.trim().starts_with('<') && edge_label.trim().ends_with('>') {
                     format!("label={}", edge_label)
                 } else {
-                    format!("label=\"{}\"", edge_label)
+                    format!("label=\"{}\"", escape_quotes(&edge_label))
                 };
+            let mut attr_list = vec![label_attr];
+            for (k, v) in self.edge_attrs(s, t).unwrap_or_default() {
+                attr_list.push(format!("{}={}", k, dot_attr_value(v)));
+            }
 
             dot_output.push_str(&format!(
                 "    \"{}\" {} \"{}\" [{}];\n",
-                s, edge_op, t, label_attr
+                escape_quotes(s),
+                edge_op,
+                escape_quotes(t),
+                attr_list.join(",")
             ));
         }
 
@@ -253,6 +1450,26 @@ where
     }
 }
 
+/// Structural equality regardless of insertion order: the same node names, the same edges
+/// (parallel edges counted, either way round for undirected graphs) and the same subgraph
+/// assignments. Attributes and subgraph parents are not compared.
+impl<E, Ty> PartialEq for NamedGraph<E, Ty>
+where
+    E: PartialEq + Clone + Ord,
+    Ty: EdgeType,
+{
+    fn eq(&self, other: &Self) -> bool {
+        let sorted_names = |g: &Self| {
+            let mut names = g.node_names();
+            names.sort();
+            names
+        };
+        self.node_to_subgraph == other.node_to_subgraph
+            && sorted_names(self) == sorted_names(other)
+            && self.sorted_edges() == other.sorted_edges()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -279,6 +1496,26 @@ mod tests {
         assert!(ng.get_node_index("B").is_none());
     }
 
+    #[test]
+    fn convert_dedup_parallel_edges() {
+        let mut multi: StableGraph<&str, u32> = StableGraph::new();
+        let a = multi.add_node("a");
+        let b = multi.add_node("b");
+        multi.add_edge(a, b, 1);
+        multi.add_edge(a, b, 2);
+        multi.add_edge(b, a, 4);
+
+        let simple =
+            convert_nodes_dedup_edges(multi.clone(), |n| n.to_string(), |w| *w, |acc, w| *acc += w);
+        let ng = NamedGraph::from_owned_graph(simple);
+        assert_eq!(ng.edge_count(), 2);
+        assert_eq!(ng.edge_weight_by_names("a", "b"), Some(&3));
+        assert_eq!(ng.edge_weight_by_names("b", "a"), Some(&4));
+
+        let plain = convert_nodes_and_map_edges(multi, |n| n.to_string(), |w| *w);
+        assert_eq!(plain.edge_count(), 3);
+    }
+
     #[test]
     fn parse_digraph_and_modify() {
         let dot = r#"digraph { "Alice" -> "Bob"; }"#;
@@ -363,6 +1600,65 @@ mod tests {
         assert_eq!(dot_output, expected_dot);
     }
 
+    #[test]
+    fn nested_subgraphs_to_dot() {
+        let mut ng = NamedGraph::<i32, Directed>::new_directed();
+        ng.add_edge_by_name("a", "b", 1);
+        ng.add_edge_by_name("b", "c", 2);
+        ng.add_edge_by_name("c", "d", 3);
+        ng.set_node_subgraph("a", "Inner");
+        ng.set_node_subgraph("b", "Outer");
+        ng.set_node_subgraph("c", "Deepest");
+        ng.set_subgraph_parent("Inner", "Outer").unwrap();
+        ng.set_subgraph_parent("Deepest", "Inner").unwrap();
+        // Drawn to hold "Top", even though no node is assigned to it directly.
+        ng.set_subgraph_parent("Outer", "Top").unwrap();
+        assert_eq!(ng.subgraph_parent("Inner"), Some("Outer"));
+
+        let expected_dot = r#"digraph G {
+    subgraph cluster_3 {
+        label = "Top";
+        subgraph cluster_2 {
+            label = "Outer";
+            "b";
+            subgraph cluster_1 {
+                label = "Inner";
+                "a";
+                subgraph cluster_0 {
+                    label = "Deepest";
+                    "c";
+                }
+            }
+        }
+    }
+    "d";
+    "a" -> "b" [label="1"];
+    "b" -> "c" [label="2"];
+    "c" -> "d" [label="3"];
+}
+"#;
+        assert_eq!(ng.to_dot(), expected_dot);
+    }
+
+    #[test]
+    fn subgraph_parent_cycles_are_rejected() {
+        let mut ng = NamedGraph::<i32>::new_undirected();
+        ng.set_subgraph_parent("B", "A").unwrap();
+        ng.set_subgraph_parent("C", "B").unwrap();
+
+        let cycle = GraphError::SubgraphCycle {
+            child: "A".to_string(),
+            parent: "C".to_string(),
+        };
+        assert_eq!(ng.set_subgraph_parent("A", "C"), Err(cycle));
+        assert!(ng.set_subgraph_parent("A", "A").is_err());
+        assert_eq!(ng.subgraph_parent("A"), None);
+
+        // Moving a subgraph elsewhere in the tree is fine.
+        ng.set_subgraph_parent("C", "A").unwrap();
+        assert_eq!(ng.subgraph_parent("C"), Some("A"));
+    }
+
     #[test]
     fn parse_with_edge_label() {
         let dot = r#"digraph { A -> B [label = "MyLabel"]; }"#;
@@ -420,6 +1716,243 @@ mod tests {
         assert!(dot_output.contains("[label=<simple text>]"));
     }
 
+    #[test]
+    fn edge_attrs_in_dot() {
+        let mut ng = NamedGraph::<String, Directed>::new_directed();
+        ng.add_edge_by_name("A", "B", "uses".to_string());
+        assert!(ng.set_edge_attr("A", "B", "color", "red"));
+        assert!(!ng.set_edge_attr("B", "A", "color", "red"));
+
+        let dot_output = ng.to_dot();
+        assert!(dot_output.contains(r#""A" -> "B" [label="uses",color=red];"#));
+
+        ng.set_edge_attr("A", "B", "style", "dashed bold");
+        assert!(
+            ng.to_dot()
+                .contains(r#"[label="uses",color=red,style="dashed bold"]"#)
+        );
+
+        assert!(ng.rename_node("A", "C"));
+        assert_eq!(ng.edge_attrs("C", "B").map(|a| a.len()), Some(2));
+    }
+
+    #[test]
+    fn edge_attrs_outlive_parallel_edges() {
+        let mut ng = NamedGraph::<String, Directed>::new_directed();
+        ng.add_edge_by_name("A", "B", "first".to_string());
+        ng.add_edge_by_name("A", "B", "second".to_string());
+        assert!(ng.set_edge_attr("A", "B", "color", "red"));
+
+        assert!(ng.remove_edge_by_names("A", "B").is_some());
+        assert_eq!(ng.edge_attrs("A", "B").map(|a| a.len()), Some(1));
+        assert!(ng.remove_edge_by_names("A", "B").is_some());
+        assert_eq!(ng.edge_attrs("A", "B"), None);
+    }
+
+    #[test]
+    fn undirected_to_dot_orders_endpoints() {
+        let mut ng = NamedGraph::<u32>::new_undirected();
+        ng.add_edge_by_name("B", "A", 1);
+        ng.add_edge_by_name("C", "A", 2);
+        let dot_output = ng.to_dot();
+        assert!(dot_output.contains(r#"    "A" -- "B" [label="1"];"#));
+        assert!(dot_output.find(r#""A" -- "B""#) < dot_output.find(r#""A" -- "C""#));
+
+        let mut directed = NamedGraph::<u32, Directed>::new_directed();
+        directed.add_edge_by_name("B", "A", 1);
+        assert!(directed.to_dot().contains(r#"    "B" -> "A" [label="1"];"#));
+    }
+
+    #[test]
+    fn node_attrs_in_dot() {
+        let mut ng = NamedGraph::<String, Directed>::new_directed();
+        ng.add_edge_by_name("A", "B", "uses".to_string());
+        ng.ensure_node("C");
+        ng.set_node_subgraph("C", "Outputs");
+        ng.set_node_attr("A", "shape", "box");
+        ng.set_node_attr("A", "color", "light blue");
+        ng.set_node_attr("C", "shape", "circle");
+        ng.set_node_attr("missing", "shape", "box");
+
+        let dot_output = ng.to_dot();
+        assert!(dot_output.contains(r#"    "A" [color="light blue",shape=box];"#));
+        assert!(dot_output.contains("    \"B\";\n"));
+        assert!(dot_output.contains(r#"        "C" [shape=circle];"#));
+        assert!(ng.node_attrs("missing").is_none());
+
+        assert!(ng.rename_node("A", "D"));
+        assert_eq!(
+            ng.node_attrs("D").unwrap().get("shape").map(String::as_str),
+            Some("box")
+        );
+        ng.remove_node_by_name("D");
+        assert!(ng.node_attrs("D").is_none());
+    }
+
+    #[test]
+    fn dot_attr_values_are_valid_ids() {
+        assert_eq!(dot_attr_value("box"), "box");
+        assert_eq!(dot_attr_value("1abc"), r#""1abc""#);
+        assert_eq!(dot_attr_value("a.b"), r#""a.b""#);
+        assert_eq!(dot_attr_value("node"), r#""node""#);
+        assert_eq!(dot_attr_value("Edge"), r#""Edge""#);
+        assert_eq!(dot_attr_value(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(dot_attr_value(r"C:\dir\"), r#""C:\\dir\\""#);
+        assert_eq!(dot_attr_value("<<b>bold</b>>"), "<<b>bold</b>>");
+
+        let mut ng = NamedGraph::<String, Directed>::new_directed();
+        ng.ensure_node("A");
+        ng.set_node_attr("A", "group", "node");
+        ng.set_node_attr("A", "tooltip", r"C:\dir\");
+        let dot = ng.to_dot();
+        assert!(dot.contains(r#""A" [group="node",tooltip="C:\\dir\\"];"#));
+        assert!(graph_delta::parser::parse_dot_to_chunks(&dot).is_ok());
+    }
+
+    #[test]
+    fn density_and_diameter() {
+        let mut path = NamedGraph::<i32>::new_undirected();
+        for (a, b) in [("A", "B"), ("B", "C"), ("C", "D"), ("D", "E")] {
+            path.add_edge_by_name(a, b, 1);
+        }
+        assert_eq!(path.diameter(), Some(4));
+        assert!((path.density() - 0.4).abs() < 1e-9);
+
+        let mut disconnected = NamedGraph::<i32>::new_undirected();
+        disconnected.add_edge_by_name("A", "B", 1);
+        disconnected.add_edge_by_name("C", "D", 1);
+        assert_eq!(disconnected.diameter(), None);
+
+        let mut directed = NamedGraph::<i32, Directed>::new_directed();
+        directed.add_edge_by_name("A", "B", 1);
+        assert_eq!(directed.diameter(), None);
+        assert!((directed.density() - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn accumulate_cooccurrence_edges() {
+        let documents = [
+            vec!["rust", "graph"],
+            vec!["graph", "rust"],
+            vec!["rust", "llm"],
+        ];
+        let mut ng = NamedGraph::<u32>::new_undirected();
+        for doc in &documents {
+            ng.add_or_accumulate_edge(doc[0], doc[1], 1, |w, n| *w += n);
+        }
+
+        assert_eq!(ng.graph().edge_count(), 2);
+        let mut edges = ng.edges_with_names();
+        edges.sort();
+        assert_eq!(
+            edges,
+            vec![
+                ("rust".to_string(), "graph".to_string(), 2),
+                ("rust".to_string(), "llm".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn stable_cluster_ids_match_graph_delta() {
+        use graph_delta::parser::{self, DotOptions};
+
+        let mut ng = NamedGraph::<String, Directed>::new_directed();
+        ng.ensure_node("A");
+        ng.ensure_node("B");
+        ng.set_node_subgraph("A", "Backend");
+        ng.set_node_subgraph("B", "Frontend");
+        ng.set_stable_cluster_ids(true);
+
+        let dot = ng.to_dot();
+        let backend = parser::stable_cluster_id("Backend");
+        assert!(dot.contains(&format!("subgraph {} {{", backend)));
+
+        let chunks = parser::parse_dot_to_chunks(&dot).unwrap();
+        let options = DotOptions {
+            stable_cluster_ids: true,
+            ..Default::default()
+        };
+        let rebuilt = parser::chunks_to_dot_with_options(&chunks, Some("G"), &options);
+        assert!(rebuilt.contains(&format!("subgraph {} {{", backend)));
+        assert!(rebuilt.contains(&format!(
+            "subgraph {} {{",
+            parser::stable_cluster_id("Frontend")
+        )));
+    }
+
+    #[test]
+    fn adjacency_list() {
+        let mut ng = NamedGraph::<i32, Directed>::new_directed();
+        ng.add_edge_by_name("A", "C", 1);
+        ng.add_edge_by_name("A", "B", 1);
+        ng.add_edge_by_name("B", "C", 1);
+        ng.ensure_node("D");
+
+        assert_eq!(ng.to_adjacency_list(), "A -> B, C\nB -> C\nC ->\nD ->\n");
+
+        let mut ung = NamedGraph::<i32>::new_undirected();
+        ung.add_edge_by_name("A", "B", 1);
+        assert_eq!(ung.to_adjacency_list(), "A -- B\nB -- A\n");
+    }
+
+    #[test]
+    fn eq_ignores_insertion_order() {
+        let mut left = NamedGraph::<u32>::new_undirected();
+        left.add_edge_by_name("a", "b", 1);
+        left.add_edge_by_name("b", "c", 2);
+        left.set_node_subgraph("a", "S");
+
+        let mut right = NamedGraph::<u32>::new_undirected();
+        right.add_edge_by_name("c", "b", 2);
+        right.add_edge_by_name("b", "a", 1);
+        right.set_node_subgraph("a", "S");
+        assert!(left == right);
+
+        right.update_edge_by_name("b", "c", 3);
+        assert!(left != right);
+        right.update_edge_by_name("b", "c", 2);
+        right.set_node_subgraph("b", "S");
+        assert!(left != right);
+
+        // Direction matters for directed graphs.
+        let mut ab = NamedGraph::<u32, Directed>::new_directed();
+        ab.add_edge_by_name("a", "b", 1);
+        let mut ba = NamedGraph::<u32, Directed>::new_directed();
+        ba.add_edge_by_name("b", "a", 1);
+        assert!(ab != ba);
+        assert!(ab == ba.reverse());
+    }
+
+    #[test]
+    fn adjacency_matrix() {
+        let mut ng = NamedGraph::<i32>::new_undirected();
+        ng.add_edge_by_name("C", "A", 3);
+        ng.add_edge_by_name("A", "B", 1);
+        ng.add_edge_by_name("B", "C", 2);
+
+        let (names, matrix) = ng.adjacency_matrix();
+        assert_eq!(names, vec!["A", "B", "C"]);
+        assert_eq!(matrix, vec![vec![0, 1, 1], vec![1, 0, 1], vec![1, 1, 0]]);
+
+        let (_, weighted) = ng.adjacency_matrix_weighted(|w| *w as f64);
+        assert_eq!(
+            weighted,
+            vec![
+                vec![0.0, 1.0, 3.0],
+                vec![1.0, 0.0, 2.0],
+                vec![3.0, 2.0, 0.0]
+            ]
+        );
+        let symmetric =
+            |i: usize, j: usize| matrix[i][j] == matrix[j][i] && weighted[i][j] == weighted[j][i];
+        assert!((0..3).all(|i| (0..3).all(|j| symmetric(i, j))));
+
+        let mut dg = NamedGraph::<i32, Directed>::new_directed();
+        dg.add_edge_by_name("A", "B", 1);
+        assert_eq!(dg.adjacency_matrix().1, vec![vec![0, 1], vec![0, 0]]);
+    }
+
     // parse tests/fixtures/record.dot
     #[test]
     fn parse_record_node() {
@@ -437,4 +1970,721 @@ mod tests {
         let dot_output = Dot::with_config(&ng.graph, &[]).to_string();
         println!("DOT Output:\n{}", dot_output);
     }
+
+    #[test]
+    fn convert_directedness() {
+        let mut ng = NamedGraph::<i32>::new_undirected();
+        ng.add_edge_by_name("A", "B", 1);
+        ng.add_edge_by_name("C", "B", 2);
+        ng.set_node_subgraph("A", "Left");
+        assert!(ng.set_edge_attr("B", "C", "color", "red"));
+
+        let directed = ng.into_directed();
+        assert!(directed.graph().is_directed());
+        assert_eq!(directed.graph().edge_count(), 4);
+        let mut edges = directed.edges_with_names();
+        edges.sort();
+        assert_eq!(
+            edges,
+            vec![
+                ("A".to_string(), "B".to_string(), 1),
+                ("B".to_string(), "A".to_string(), 1),
+                ("B".to_string(), "C".to_string(), 2),
+                ("C".to_string(), "B".to_string(), 2),
+            ]
+        );
+        let red = vec![("color".to_string(), "red".to_string())];
+        assert_eq!(directed.edge_attrs("B", "C"), Some(red.as_slice()));
+        assert_eq!(directed.edge_attrs("C", "B"), Some(red.as_slice()));
+        assert!(directed.to_dot().contains("label = \"Left\";"));
+
+        let mut dg = NamedGraph::<i32, Directed>::new_directed();
+        dg.add_edge_by_name("A", "B", 1);
+        dg.add_edge_by_name("B", "A", 2);
+        dg.add_edge_by_name("B", "C", 3);
+        assert!(dg.set_edge_attr("B", "C", "style", "dashed"));
+
+        let undirected = dg.into_undirected();
+        assert!(!undirected.graph().is_directed());
+        assert_eq!(undirected.graph().edge_count(), 3);
+        let mut neighbors = undirected.neighbors_by_name("C").unwrap();
+        neighbors.sort();
+        assert_eq!(neighbors, vec!["B".to_string()]);
+        let dashed = vec![("style".to_string(), "dashed".to_string())];
+        assert_eq!(undirected.edge_attrs("C", "B"), Some(dashed.as_slice()));
+    }
+
+    #[test]
+    fn subgraph_report_counts_members() {
+        let mut ng = NamedGraph::<i32>::new_undirected();
+        ng.add_edge_by_name("A", "B", 1);
+        ng.add_edge_by_name("B", "C", 1);
+        ng.add_edge_by_name("C", "D", 1);
+        ng.set_node_subgraph("A", "Left");
+        ng.set_node_subgraph("B", "Left");
+        ng.set_node_subgraph("C", "Right");
+        ng.set_node_subgraph("D", "left ");
+
+        ng.remove_node_by_name("A");
+
+        let report = ng.subgraph_report();
+        let labels: Vec<_> = report.iter().map(|s| s.label.as_str()).collect();
+        assert_eq!(labels, vec!["Left", "Right", "left "]);
+        assert_eq!(report[0].members, 1);
+        assert_eq!(report[0].similar_labels, vec!["left ".to_string()]);
+        assert_eq!(report[1].members, 1);
+        assert!(report[1].similar_labels.is_empty());
+        assert_eq!(report[2].similar_labels, vec!["Left".to_string()]);
+
+        // A label whose last member is removed stays known with no members.
+        ng.remove_node_by_name("B");
+        let report = ng.subgraph_report();
+        assert_eq!(report[0].label, "Left");
+        assert_eq!(report[0].members, 0);
+        assert!(!ng.to_dot().contains("label = \"Left\";"));
+    }
+
+    #[test]
+    fn ego_network_radius_one() {
+        let mut ng = NamedGraph::<i32>::new_undirected();
+        for spoke in ["A", "B", "C"] {
+            ng.add_edge_by_name("Hub", spoke, 1);
+        }
+        ng.add_edge_by_name("A", "B", 2);
+        ng.add_edge_by_name("C", "Far", 3);
+        ng.add_edge_by_name("Far", "Farther", 4);
+        ng.set_node_subgraph("A", "Left");
+        assert!(ng.set_edge_attr("A", "B", "color", "red"));
+
+        let ego = ng.ego_network("Hub", 1).unwrap();
+        let mut names = ego.node_names();
+        names.sort();
+        assert_eq!(names, vec!["A", "B", "C", "Hub"]);
+        // Edges between neighbors are kept too, with their attributes.
+        assert_eq!(ego.graph().edge_count(), 4);
+        assert!(ego.edge_attrs("B", "A").is_some());
+        assert!(ego.to_dot().contains("label = \"Left\";"));
+
+        assert_eq!(ng.ego_network("Hub", 2).unwrap().graph().node_count(), 5);
+        assert_eq!(ng.ego_network("Hub", 0).unwrap().node_names(), vec!["Hub"]);
+        assert!(ng.ego_network("Nobody", 1).is_none());
+    }
+
+    #[test]
+    fn clear_keeps_capacity() {
+        let mut ng = NamedGraph::<i32, Directed>::new_directed();
+        for i in 0..100 {
+            ng.add_edge_by_name(&format!("n{i}"), &format!("n{}", i + 1), i);
+        }
+        ng.set_node_subgraph("n0", "Start");
+        assert!(ng.set_edge_attr("n0", "n1", "color", "red"));
+
+        ng.clear_edges();
+        assert_eq!(ng.graph().node_count(), 101);
+        assert_eq!(ng.graph().edge_count(), 0);
+        assert!(ng.edge_attrs("n0", "n1").is_none());
+        assert!(ng.to_dot().contains("label = \"Start\";"));
+
+        let capacity = ng.graph().capacity();
+        ng.clear();
+        assert_eq!(ng.graph().node_count(), 0);
+        assert_eq!(ng.graph().edge_count(), 0);
+        assert!(ng.get_node_index("n0").is_none());
+        assert!(ng.subgraph_report().is_empty());
+        assert_eq!(ng.graph().capacity(), capacity);
+
+        ng.add_edge_by_name("x", "y", 1);
+        assert_eq!(ng.graph().capacity(), capacity);
+        assert_eq!(ng.neighbors_by_name("x").unwrap(), vec!["y".to_string()]);
+    }
+
+    #[test]
+    fn edge_weight_lookup_and_update() {
+        let mut directed = NamedGraph::<u32, Directed>::new_directed();
+        directed.add_edge_by_name("a", "b", 1);
+        assert_eq!(directed.edge_weight_by_names("a", "b"), Some(&1));
+        assert_eq!(directed.edge_weight_by_names("b", "a"), None);
+        assert_eq!(directed.update_edge_by_name("b", "a", 5), None);
+
+        let old = directed.edge_weight_by_names("a", "b").copied().unwrap();
+        assert_eq!(directed.update_edge_by_name("a", "b", old + 1), Some(1));
+        assert_eq!(directed.edge_weight_by_names("a", "b"), Some(&2));
+        assert_eq!(directed.graph().edge_count(), 1);
+
+        let mut undirected = NamedGraph::<u32>::new_undirected();
+        undirected.add_edge_by_name("a", "b", 1);
+        assert_eq!(undirected.edge_weight_by_names("b", "a"), Some(&1));
+        assert_eq!(undirected.update_edge_by_name("b", "a", 3), Some(1));
+        assert_eq!(undirected.edge_weight_by_names("a", "b"), Some(&3));
+        assert_eq!(undirected.edge_weight_by_names("a", "missing"), None);
+    }
+
+    #[test]
+    fn edge_weight_by_names_mut_in_place() {
+        let mut directed = NamedGraph::<u32, Directed>::new_directed();
+        directed.add_edge_by_name("a", "b", 1);
+        *directed.edge_weight_by_names_mut("a", "b").unwrap() += 10;
+        assert_eq!(directed.edge_weight_by_names("a", "b"), Some(&11));
+        assert!(directed.edge_weight_by_names_mut("b", "a").is_none());
+        assert!(directed.edge_weight_by_names_mut("a", "missing").is_none());
+
+        let mut undirected = NamedGraph::<u32>::new_undirected();
+        undirected.add_edge_by_name("a", "b", 1);
+        *undirected.edge_weight_by_names_mut("b", "a").unwrap() += 10;
+        assert_eq!(undirected.edge_weight_by_names("a", "b"), Some(&11));
+        assert!(
+            undirected
+                .edge_weight_by_names_mut("missing", "b")
+                .is_none()
+        );
+        undirected.ensure_node("c");
+        assert!(undirected.edge_weight_by_names_mut("a", "c").is_none());
+    }
+
+    #[test]
+    fn counts_and_degrees() {
+        let mut g = NamedGraph::<u32, Directed>::new_directed();
+        g.add_edge_by_name("hub", "a", 1);
+        g.add_edge_by_name("hub", "b", 1);
+        g.add_edge_by_name("a", "hub", 1);
+        g.ensure_node("lonely");
+        assert_eq!(g.node_count(), 4);
+        assert_eq!(g.edge_count(), 3);
+
+        assert_eq!(g.out_degree_by_name("hub"), Some(2));
+        assert_eq!(g.in_degree_by_name("hub"), Some(1));
+        assert_eq!(g.degree_by_name("hub"), Some(3));
+        assert_eq!(g.in_degree_by_name("b"), Some(1));
+        assert_eq!(g.out_degree_by_name("b"), Some(0));
+        assert_eq!(g.degree_by_name("lonely"), Some(0));
+        assert_eq!(g.degree_by_name("missing"), None);
+        assert_eq!(g.in_degree_by_name("missing"), None);
+
+        assert!(g.contains_node("lonely"));
+        assert!(!g.contains_node("missing"));
+        assert!(g.has_edge("hub", "b"));
+        assert!(!g.has_edge("b", "hub"));
+        assert!(!g.has_edge("hub", "missing"));
+
+        g.remove_node_by_name("a");
+        assert_eq!(g.node_count(), 3);
+        assert_eq!(g.edge_count(), 1);
+        assert_eq!(g.degree_by_name("hub"), Some(1));
+
+        let mut u = NamedGraph::<u32>::new_undirected();
+        u.add_edge_by_name("a", "b", 1);
+        assert!(u.has_edge("a", "b"));
+        assert!(u.has_edge("b", "a"));
+    }
+
+    #[test]
+    fn edges_between_parallel_edges() {
+        let mut g = NamedGraph::<u32, Directed>::new_directed();
+        g.add_edge_by_name("A", "B", 1);
+        g.add_edge_by_name("A", "B", 2);
+        g.add_edge_by_name("B", "A", 3);
+        let mut weights = g.edges_between("A", "B");
+        weights.sort();
+        assert_eq!(weights, [&1, &2]);
+        assert_eq!(g.edges_between("B", "A"), [&3]);
+        assert!(g.edges_between("A", "missing").is_empty());
+
+        let mut u = NamedGraph::<u32>::new_undirected();
+        u.add_edge_by_name("A", "B", 1);
+        u.add_edge_by_name("B", "A", 2);
+        assert_eq!(u.edges_between("A", "B").len(), 2);
+    }
+
+    #[test]
+    fn add_edges_by_name_matches_loop() {
+        let edges = [
+            ("a", "b", 1),
+            ("a", "c", 2),
+            ("b", "a", 3),
+            ("a", "a", 4),
+            ("c", "d", 5),
+        ];
+        let owned = edges
+            .iter()
+            .map(|&(a, b, w)| (a.to_string(), b.to_string(), w));
+
+        let mut bulk = NamedGraph::<u32, Directed>::new_directed();
+        bulk.ensure_node("c");
+        bulk.add_edges_by_name(owned);
+        let mut looped = NamedGraph::<u32, Directed>::new_directed();
+        looped.ensure_node("c");
+        for (a, b, w) in edges {
+            looped.add_edge_by_name(a, b, w);
+        }
+
+        assert_eq!(bulk.node_names(), looped.node_names());
+        assert_eq!(bulk.edges_with_names(), looped.edges_with_names());
+        assert_eq!(bulk.node_count(), 4);
+    }
+
+    #[test]
+    fn reverse_flips_edges() {
+        let mut ng = NamedGraph::<u32, Directed>::new_directed();
+        ng.add_edge_by_name("a", "b", 1);
+        ng.add_edge_by_name("b", "c", 2);
+        ng.add_edge_by_name("c", "c", 3);
+        ng.ensure_node("lonely");
+        ng.set_node_subgraph("a", "Start");
+        ng.set_edge_attr("a", "b", "color", "red");
+
+        let rev = ng.reverse();
+        assert_eq!(rev.edge_weight_by_names("b", "a"), Some(&1));
+        assert_eq!(rev.edge_weight_by_names("a", "b"), None);
+        assert_eq!(rev.out_degree_by_name("c"), Some(2));
+        assert_eq!(rev.node_count(), 4);
+        assert_eq!(rev.subgraph_members("Start"), ["a"]);
+        assert_eq!(
+            rev.edge_attrs("b", "a"),
+            Some(&[("color".to_string(), "red".to_string())][..])
+        );
+
+        let mut original = ng.edges_with_names();
+        let mut twice = rev.reverse().edges_with_names();
+        original.sort();
+        twice.sort();
+        assert_eq!(twice, original);
+
+        #[allow(deprecated)]
+        let old_name = ng.reversed();
+        assert_eq!(old_name.edge_weight_by_names("b", "a"), Some(&1));
+    }
+
+    #[test]
+    fn merge_overlapping_graphs() {
+        let mut left = NamedGraph::<u32, Directed>::new_directed();
+        left.add_edge_by_name("a", "b", 1);
+        left.add_edge_by_name("b", "c", 2);
+        left.set_node_subgraph("b", "left");
+
+        let mut right = NamedGraph::<u32, Directed>::new_directed();
+        right.add_edge_by_name("b", "c", 3);
+        right.add_edge_by_name("c", "d", 4);
+        right.set_node_subgraph("b", "right");
+        right.set_node_subgraph("d", "right");
+
+        left.merge(&right);
+        let mut names = left.node_names();
+        names.sort();
+        assert_eq!(names, ["a", "b", "c", "d"]);
+        assert_eq!(left.graph().node_count(), 4);
+        assert_eq!(left.graph().edge_count(), 4);
+        assert_eq!(left.edge_weight_by_names("c", "d"), Some(&4));
+
+        let report = left.subgraph_report();
+        let members: Vec<_> = report
+            .iter()
+            .map(|s| (s.label.as_str(), s.members))
+            .collect();
+        assert_eq!(members, [("left", 1), ("right", 1)]);
+    }
+
+    #[test]
+    fn merge_shared_node_is_not_duplicated() {
+        let mut ng = NamedGraph::<u32>::new_undirected();
+        ng.add_edge_by_name("shared", "a", 1);
+        let mut other = NamedGraph::<u32>::new_undirected();
+        other.add_edge_by_name("shared", "b", 2);
+
+        ng.merge(&other);
+        assert_eq!(ng.node_count(), 3);
+        assert_eq!(ng.degree_by_name("shared"), Some(2));
+        assert_eq!(ng.edge_weight_by_names("b", "shared"), Some(&2));
+        assert_eq!(other.node_count(), 2);
+    }
+
+    #[test]
+    fn mermaid_flowchart() {
+        let mut ng = NamedGraph::<String, Directed>::new_directed();
+        ng.add_edge_by_name("Start", "Check \"x\"", "go".to_string());
+        ng.add_edge_by_name("Check \"x\"", "End", "a|b".to_string());
+        ng.add_edge_by_name("Start", "End", String::new());
+
+        assert_eq!(
+            ng.to_mermaid(),
+            "flowchart TD\n\
+             \x20   n0[\"Check #quot;x#quot;\"]\n\
+             \x20   n1[\"End\"]\n\
+             \x20   n2[\"Start\"]\n\
+             \x20   n0 -->|a#124;b| n1\n\
+             \x20   n2 -->|go| n0\n\
+             \x20   n2 --> n1\n"
+        );
+
+        let mut undirected = NamedGraph::<u32>::new_undirected();
+        undirected.add_edge_by_name("a", "b", 1);
+        let mermaid = undirected.to_mermaid();
+        assert!(mermaid.starts_with("graph LR\n"));
+        assert!(mermaid.contains("n0 ---|1| n1"));
+    }
+
+    #[test]
+    fn mermaid_subgraphs() {
+        let mut ng = NamedGraph::<String, Directed>::new_directed();
+        ng.add_edge_by_name("api", "db", "reads".to_string());
+        ng.ensure_node("ui");
+        ng.set_node_subgraph("api", "Backend");
+        ng.set_node_subgraph("db", "Storage");
+        ng.set_subgraph_parent("Storage", "Backend").unwrap();
+
+        assert_eq!(
+            ng.to_mermaid(),
+            "flowchart TD\n\
+             \x20   subgraph s0 [\"Backend\"]\n\
+             \x20       n0[\"api\"]\n\
+             \x20       subgraph s1 [\"Storage\"]\n\
+             \x20           n1[\"db\"]\n\
+             \x20       end\n\
+             \x20   end\n\
+             \x20   n2[\"ui\"]\n\
+             \x20   n0 -->|reads| n1\n"
+        );
+    }
+
+    #[test]
+    fn connected_components_of_two_triangles() {
+        let mut ng = NamedGraph::<u32, Directed>::new_directed();
+        assert!(ng.connected_components().is_empty());
+        assert!(ng.strongly_connected_components().is_empty());
+
+        for (a, b) in [
+            ("b", "c"),
+            ("c", "a"),
+            ("a", "b"),
+            ("x", "y"),
+            ("y", "z"),
+            ("x", "z"),
+        ] {
+            ng.add_edge_by_name(a, b, 1);
+        }
+        ng.ensure_node("lonely");
+
+        let abc = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let xyz = vec!["x".to_string(), "y".to_string(), "z".to_string()];
+        assert_eq!(
+            ng.connected_components(),
+            vec![abc.clone(), vec!["lonely".to_string()], xyz]
+        );
+        // x -> y -> z with the shortcut x -> z has no cycle.
+        assert_eq!(
+            ng.strongly_connected_components(),
+            vec![
+                abc,
+                vec!["lonely".to_string()],
+                vec!["x".to_string()],
+                vec!["y".to_string()],
+                vec!["z".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn rename_and_remove_keep_subgraphs_in_sync() {
+        let mut ng = NamedGraph::<String, Directed>::new_directed();
+        ng.add_edge_by_name("A", "B", "uses".to_string());
+        ng.set_node_subgraph("A", "Backend");
+        ng.set_node_subgraph("B", "Frontend");
+
+        assert!(ng.rename_node("A", "A2"));
+        let dot = ng.to_dot();
+        let backend = dot.find("label = \"Backend\";").unwrap();
+        let a2 = dot.find("\"A2\";").unwrap();
+        assert!(backend < a2 && a2 < dot[backend..].find('}').unwrap() + backend);
+
+        ng.remove_node_by_name("B");
+        let members: Vec<_> = ng
+            .subgraph_report()
+            .iter()
+            .map(|s| (s.label.clone(), s.members))
+            .collect();
+        assert_eq!(
+            members,
+            [("Backend".to_string(), 1), ("Frontend".to_string(), 0)]
+        );
+        assert!(!ng.to_dot().contains("Frontend"));
+    }
+
+    #[test]
+    fn contract_nodes_rewires_edges() {
+        let mut ng = NamedGraph::<u32, Directed>::new_directed();
+        ng.add_edge_by_name("x", "B", 1);
+        ng.add_edge_by_name("B", "y", 2);
+        ng.add_edge_by_name("A", "y", 3);
+        ng.set_node_subgraph("B", "Dupes");
+        ng.set_edge_attr("B", "y", "color", "red");
+
+        assert!(ng.contract_nodes("A", "B"));
+        assert!(ng.get_node_index("B").is_none());
+        assert_eq!(ng.node_count(), 3);
+        assert_eq!(ng.edge_weight_by_names("x", "A"), Some(&1));
+        // Parallel edges are kept.
+        assert_eq!(ng.out_degree_by_name("A"), Some(2));
+        assert_eq!(
+            ng.edge_attrs("A", "y"),
+            Some(&[("color".to_string(), "red".to_string())][..])
+        );
+        assert_eq!(ng.subgraph_report()[0].label, "Dupes");
+        assert_eq!(ng.subgraph_report()[0].members, 1);
+
+        assert!(!ng.contract_nodes("A", "missing"));
+        assert!(!ng.contract_nodes("missing", "A"));
+        assert!(!ng.contract_nodes("A", "A"));
+    }
+
+    #[test]
+    fn contract_nodes_merges_edge_attrs() {
+        let mut ng = NamedGraph::<u32, Directed>::new_directed();
+        ng.add_edge_by_name("A", "y", 1);
+        ng.add_edge_by_name("B", "y", 2);
+        ng.add_edge_by_name("C", "y", 3);
+        ng.set_edge_attr("A", "y", "color", "red");
+        ng.set_edge_attr("B", "y", "color", "blue");
+        ng.set_edge_attr("B", "y", "style", "dashed");
+        ng.set_edge_attr("C", "y", "color", "green");
+        ng.set_edge_attr("C", "y", "penwidth", "2");
+        assert!(ng.contract_nodes("A", "B"));
+        assert!(ng.contract_nodes("A", "C"));
+        assert_eq!(
+            ng.edge_attrs("A", "y").unwrap(),
+            &[
+                ("color".to_string(), "red".to_string()),
+                ("style".to_string(), "dashed".to_string()),
+                ("penwidth".to_string(), "2".to_string()),
+            ]
+        );
+
+        // Moved edges that meet fill in each other in name order, whatever the map order.
+        for _ in 0..8 {
+            let mut ng = NamedGraph::<u32, Directed>::new_directed();
+            ng.add_edge_by_name("A", "B", 1);
+            ng.add_edge_by_name("B", "A", 2);
+            ng.set_edge_attr("A", "B", "color", "red");
+            ng.set_edge_attr("B", "A", "color", "blue");
+            ng.set_edge_attr("B", "A", "style", "dashed");
+            assert!(ng.contract_nodes("A", "B"));
+            assert_eq!(
+                ng.edge_attrs("A", "A").unwrap(),
+                &[
+                    ("color".to_string(), "red".to_string()),
+                    ("style".to_string(), "dashed".to_string()),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn contract_nodes_makes_one_self_loop() {
+        let mut directed = NamedGraph::<u32, Directed>::new_directed();
+        directed.add_edge_by_name("A", "B", 1);
+        directed.add_edge_by_name("B", "A", 2);
+        directed.add_edge_by_name("B", "B", 3);
+        assert!(directed.contract_nodes("A", "B"));
+        assert_eq!(directed.node_count(), 1);
+        assert_eq!(directed.edge_count(), 1);
+        assert!(directed.edge_weight_by_names("A", "A").is_some());
+
+        let mut undirected = NamedGraph::<u32>::new_undirected();
+        undirected.add_edge_by_name("A", "A", 1);
+        undirected.add_edge_by_name("A", "B", 2);
+        undirected.add_edge_by_name("B", "C", 3);
+        assert!(undirected.contract_nodes("A", "B"));
+        assert_eq!(undirected.edge_count(), 2);
+        assert_eq!(undirected.edge_weight_by_names("A", "A"), Some(&1));
+        assert_eq!(undirected.edge_weight_by_names("C", "A"), Some(&3));
+    }
+
+    #[test]
+    fn induced_subgraph_of_cluster() {
+        let mut ng = NamedGraph::<u32, Directed>::new_directed();
+        ng.add_edge_by_name("api", "db", 1);
+        ng.add_edge_by_name("db", "cache", 2);
+        ng.add_edge_by_name("api", "ui", 3);
+        for node in ["api", "db", "cache"] {
+            ng.set_node_subgraph(node, "Backend");
+        }
+        ng.set_node_subgraph("ui", "Frontend");
+
+        let members = ng.subgraph_members("Backend");
+        assert_eq!(members, ["api", "cache", "db"]);
+        assert!(ng.subgraph_members("Nope").is_empty());
+
+        let names: Vec<&str> = members.iter().map(String::as_str).collect();
+        let backend = ng.induced_subgraph(&names);
+        assert_eq!(backend.node_count(), 3);
+        assert_eq!(backend.edge_count(), 2);
+        // The edge to the excluded "ui" node is dropped.
+        assert!(backend.get_node_index("ui").is_none());
+        assert_eq!(backend.edge_weight_by_names("db", "cache"), Some(&2));
+        assert_eq!(backend.subgraph_members("Backend"), members);
+        assert_eq!(ng.induced_subgraph(&["api", "missing"]).node_count(), 1);
+    }
+
+    #[test]
+    fn neighbors_iter_matches_neighbors_by_name() {
+        let mut ng = NamedGraph::<u32, Directed>::new_directed();
+        ng.add_edge_by_name("a", "b", 1);
+        ng.add_edge_by_name("a", "c", 1);
+        ng.add_edge_by_name("c", "a", 1);
+
+        let mut borrowed: Vec<&str> = ng.neighbors_iter_by_name("a").unwrap().collect();
+        borrowed.sort();
+        assert_eq!(borrowed, ["b", "c"]);
+        let mut owned = ng.neighbors_by_name("a").unwrap();
+        owned.sort();
+        assert_eq!(owned, borrowed);
+        assert_eq!(ng.neighbors_iter_by_name("b").unwrap().count(), 0);
+        assert!(ng.neighbors_iter_by_name("missing").is_none());
+    }
+
+    #[test]
+    fn neighbors_within_k_hops() {
+        let mut ng = NamedGraph::<u32>::new_undirected();
+        for (a, b) in [("a", "b"), ("b", "c"), ("c", "d"), ("d", "e")] {
+            ng.add_edge_by_name(a, b, 1);
+        }
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            ng.neighbors_within("c", 2),
+            Some(names(&["a", "b", "d", "e"]))
+        );
+        assert_eq!(ng.neighbors_within("a", 2), Some(names(&["b", "c"])));
+        assert_eq!(ng.neighbors_within("a", 0), Some(vec![]));
+        assert_eq!(ng.neighbors_within("missing", 2), None);
+    }
+
+    #[test]
+    fn shortest_paths_by_name() {
+        let mut ng = NamedGraph::<u32, Directed>::new_directed();
+        for (a, b, w) in [("a", "b", 1), ("b", "c", 1), ("a", "c", 5), ("c", "d", 1)] {
+            ng.add_edge_by_name(a, b, w);
+        }
+        ng.ensure_node("lonely");
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            ng.shortest_path_by_name("a", "d"),
+            Some(names(&["a", "c", "d"]))
+        );
+        assert_eq!(ng.shortest_path_by_name("b", "b"), Some(names(&["b"])));
+        assert_eq!(ng.shortest_path_by_name("d", "a"), None);
+        assert_eq!(ng.shortest_path_by_name("a", "lonely"), None);
+        assert_eq!(ng.shortest_path_by_name("a", "missing"), None);
+
+        assert_eq!(
+            ng.shortest_weighted_path_by_name("a", "d"),
+            Some((names(&["a", "b", "c", "d"]), 3.0))
+        );
+        assert_eq!(
+            ng.shortest_weighted_path_by_name("c", "c"),
+            Some((names(&["c"]), 0.0))
+        );
+        assert_eq!(ng.shortest_weighted_path_by_name("d", "a"), None);
+    }
+
+    #[test]
+    fn connected_components_after_removing_a_bridge() {
+        let mut ng = NamedGraph::<u32>::new_undirected();
+        for (a, b) in [("a", "b"), ("b", "bridge"), ("bridge", "c"), ("c", "d")] {
+            ng.add_edge_by_name(a, b, 1);
+        }
+        assert_eq!(ng.connected_components().len(), 1);
+
+        ng.remove_node_by_name("bridge");
+        assert_eq!(
+            ng.connected_components(),
+            vec![
+                vec!["a".to_string(), "b".to_string()],
+                vec!["c".to_string(), "d".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn to_dot_escapes_names() {
+        let mut ng = NamedGraph::<u32, Directed>::new_directed();
+        ng.add_edge_by_name(r#"He said "hi""#, r"C:\temp", 1);
+        ng.set_node_subgraph(r#"He said "hi""#, r#"The "quoted" group"#);
+
+        let dot = ng.to_dot();
+        assert!(dot.contains(r#"label = "The \"quoted\" group";"#));
+        assert!(dot.contains(r#""He said \"hi\"" -> "C:\\temp""#));
+
+        let chunks = graph_delta::parser::parse_dot_to_chunks(&dot).unwrap();
+        let node_ids: Vec<_> = chunks
+            .iter()
+            .filter(|c| c.kind == "node")
+            .filter_map(|c| c.id.as_deref())
+            .collect();
+        assert!(node_ids.contains(&r#""He said \"hi\"""#));
+
+        // The edge refers to the same two nodes instead of creating new ones.
+        let parsed: StableGraph<DotNodeWeight, _> = ParseFromDot::try_from(dot.as_str()).unwrap();
+        assert_eq!(parsed.node_count(), 2);
+        assert_eq!(parsed.edge_count(), 1);
+    }
+
+    #[test]
+    fn to_dot_stable_keeps_previous_order() {
+        let mut ng = NamedGraph::<u32, Directed>::new_directed();
+        ng.add_edge_by_name("c", "a", 1);
+        ng.add_edge_by_name("a", "b", 2);
+        let (_, order) = ng.to_dot_stable(&[]);
+        assert_eq!(order, ["a", "b", "c"]);
+
+        let prev = vec!["c".to_string(), "b".to_string(), "a".to_string()];
+        ng.add_edge_by_name("a", "new", 3);
+        ng.remove_node_by_name("b");
+        let (dot, order) = ng.to_dot_stable(&prev);
+        assert_eq!(order, ["c", "a", "new"]);
+
+        let lines: Vec<&str> = dot.lines().map(str::trim).collect();
+        let at = |line: &str| lines.iter().position(|l| *l == line).unwrap();
+        assert!(at(r#""c";"#) < at(r#""a";"#));
+        assert!(at(r#""a";"#) < at(r#""new";"#));
+        assert!(at(r#""c" -> "a" [label="1"];"#) < at(r#""a" -> "new" [label="3"];"#));
+    }
+
+    #[test]
+    fn to_dot_escapes_newlines_in_names() {
+        let mut ng = NamedGraph::<u32, Directed>::new_directed();
+        ng.add_edge_by_name("Node \"A\"", "two\nlines", 1);
+        ng.set_node_subgraph("two\nlines", "multi\nline group");
+
+        let dot = ng.to_dot();
+        // Every statement stays on its own line.
+        assert!(dot.contains(r#"label = "multi\nline group";"#));
+        assert!(dot.contains(r#""Node \"A\"" -> "two\nlines" [label="1"];"#));
+        assert!(graph_delta::parser::parse_dot_to_chunks(&dot).is_ok());
+        let parsed: StableGraph<DotNodeWeight, _> = ParseFromDot::try_from(dot.as_str()).unwrap();
+        assert_eq!(parsed.node_count(), 2);
+    }
+
+    #[test]
+    fn to_dot_escapes_edge_labels() {
+        let mut ng = NamedGraph::<String, Directed>::new_directed();
+        ng.add_edge_by_name("A", "B", r#"says "hi" to C:\"#.to_string());
+
+        let dot = ng.to_dot();
+        assert!(dot.contains(r#""A" -> "B" [label="says \"hi\" to C:\\"];"#));
+        assert!(graph_delta::parser::parse_dot_to_chunks(&dot).is_ok());
+        let parsed: StableGraph<DotNodeWeight, _> = ParseFromDot::try_from(dot.as_str()).unwrap();
+        assert_eq!(parsed.edge_count(), 1);
+    }
+
+    #[test]
+    fn add_node_strict_rejects_duplicates() {
+        let mut ng = NamedGraph::<u32>::new_undirected();
+        let a = ng.add_node_strict("a").unwrap();
+        assert_eq!(ng.get_node_index("a"), Some(a));
+
+        let err = ng.add_node_strict("a").unwrap_err();
+        assert_eq!(err, GraphError::DuplicateNode("a".to_string()));
+        assert_eq!(err.to_string(), "Node 'a' already exists");
+        assert_eq!(ng.node_names(), ["a"]);
+    }
 }
@@ -1,4 +1,7 @@
-fn sanitize_id(id: &str) -> String {
+//! Turning arbitrary names into valid DOT ids.
+
+/// `id` as a DOT id: bare if it is a simple identifier, otherwise quoted and escaped.
+pub fn sanitize_id(id: &str) -> String {
     // DOT spec: IDs can be:
     // 1. Alphanumeric + underscore (no leading digit)
     // 2. Numeral [-]?(.[0-9]+ | [0-9]+(.[0-9]*)?)
@@ -13,6 +16,9 @@ fn sanitize_id(id: &str) -> String {
     }
 }
 
+/// DOT keywords, which are case-insensitive and can't be used as bare ids.
+const KEYWORDS: &[&str] = &["node", "edge", "graph", "digraph", "subgraph", "strict"];
+
 fn is_simple_id(s: &str) -> bool {
     if s.is_empty() {
         return false;
@@ -21,12 +27,43 @@ fn is_simple_id(s: &str) -> bool {
     let first = s.chars().next().unwrap();
 
     // Check if it's a valid simple ID (alphanumeric + underscore, no leading digit)
-    first.is_alphabetic() || first == '_' && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+    (first.is_alphabetic() || first == '_')
+        && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !KEYWORDS
+            .iter()
+            .any(|keyword| keyword.eq_ignore_ascii_case(s))
 }
 
-fn escape_quotes(s: &str) -> String {
+/// Escapes `s` for use inside a double-quoted DOT id.
+pub(crate) fn escape_quotes(s: &str) -> String {
     s.replace('\\', "\\\\")
         .replace('"', "\\\"")
         .replace('\n', "\\n")
         .replace('\r', "\\r")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_ids() {
+        assert!(!is_simple_id("A B"));
+        assert!(!is_simple_id("A-1"));
+        assert!(is_simple_id("_valid"));
+        assert!(is_simple_id("node_1"));
+        assert!(!is_simple_id("1st"));
+        assert!(!is_simple_id(""));
+        assert!(!is_simple_id("a.b"));
+        assert!(!is_simple_id("node"));
+        assert!(!is_simple_id("Edge"));
+        assert!(is_simple_id("nodes"));
+
+        assert_eq!(sanitize_id("A B"), r#""A B""#);
+        assert_eq!(sanitize_id("_valid"), "_valid");
+        assert_eq!(sanitize_id(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(sanitize_id("1abc"), r#""1abc""#);
+        assert_eq!(sanitize_id("subgraph"), r#""subgraph""#);
+        assert_eq!(sanitize_id(r"dir\"), r#""dir\\""#);
+    }
+}
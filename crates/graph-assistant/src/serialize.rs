@@ -0,0 +1,171 @@
+//! Serde support for [NamedGraph] behind the `serde` feature, e.g. to save a session as JSON
+//! and load it back.
+use crate::NamedGraph;
+use petgraph::EdgeType;
+use petgraph::graph::NodeIndex;
+use petgraph::stable_graph::StableGraph;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences as _};
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Extra DOT attributes of one edge.
+type Attrs = Vec<(String, String)>;
+
+/// What gets written out. Node indices are not kept: on load the nodes are re-added in order
+/// and edges refer to them by name. Maps are sorted so equal graphs serialize identically.
+#[derive(Serialize)]
+struct GraphRef<'a, E> {
+    directed: bool,
+    nodes: Vec<&'a str>,
+    edges: Vec<(&'a str, &'a str, &'a E)>,
+    node_to_subgraph: BTreeMap<&'a str, &'a str>,
+    subgraph_labels: BTreeSet<&'a str>,
+    subgraph_parent: BTreeMap<&'a str, &'a str>,
+    node_attrs: BTreeMap<&'a str, BTreeMap<&'a str, &'a str>>,
+    edge_attrs: Vec<(&'a str, &'a str, &'a Attrs)>,
+    stable_cluster_ids: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(bound = "E: DeserializeOwned")]
+struct GraphData<E> {
+    directed: bool,
+    nodes: Vec<String>,
+    edges: Vec<(String, String, E)>,
+    #[serde(default)]
+    node_to_subgraph: BTreeMap<String, String>,
+    #[serde(default)]
+    subgraph_labels: BTreeSet<String>,
+    #[serde(default)]
+    subgraph_parent: BTreeMap<String, String>,
+    #[serde(default)]
+    node_attrs: BTreeMap<String, BTreeMap<String, String>>,
+    #[serde(default)]
+    edge_attrs: Vec<(String, String, Attrs)>,
+    #[serde(default)]
+    stable_cluster_ids: bool,
+}
+
+impl<E: Serialize, Ty: EdgeType> Serialize for NamedGraph<E, Ty> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = |idx: NodeIndex| self.graph[idx].as_str();
+        let mut edge_attrs: Vec<_> = self
+            .edge_attrs
+            .iter()
+            .map(|((s, t), attrs)| (s.as_str(), t.as_str(), attrs))
+            .collect();
+        edge_attrs.sort();
+        GraphRef {
+            directed: self.graph.is_directed(),
+            nodes: self.graph.node_weights().map(String::as_str).collect(),
+            edges: self
+                .graph
+                .edge_references()
+                .map(|e| (name(e.source()), name(e.target()), e.weight()))
+                .collect(),
+            node_to_subgraph: self
+                .node_to_subgraph
+                .iter()
+                .map(|(node, subgraph)| (node.as_str(), subgraph.as_str()))
+                .collect(),
+            subgraph_labels: self.subgraph_labels.iter().map(String::as_str).collect(),
+            subgraph_parent: self
+                .subgraph_parent
+                .iter()
+                .map(|(child, parent)| (child.as_str(), parent.as_str()))
+                .collect(),
+            node_attrs: self
+                .node_attrs
+                .iter()
+                .map(|(node, attrs)| {
+                    let attrs = attrs.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+                    (node.as_str(), attrs.collect())
+                })
+                .collect(),
+            edge_attrs,
+            stable_cluster_ids: self.stable_cluster_ids,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Fails if the data was saved from a graph of the other direction than `Ty`.
+impl<'de, E: DeserializeOwned, Ty: EdgeType> Deserialize<'de> for NamedGraph<E, Ty> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = GraphData::<E>::deserialize(deserializer)?;
+        if data.directed != Ty::is_directed() {
+            let expected = if Ty::is_directed() {
+                "a directed graph"
+            } else {
+                "an undirected graph"
+            };
+            return Err(D::Error::custom(format!("expected {}", expected)));
+        }
+
+        let mut ng = NamedGraph::from_owned_graph(StableGraph::with_capacity(
+            data.nodes.len(),
+            data.edges.len(),
+        ));
+        for name in data.nodes {
+            ng.ensure_node(name);
+        }
+        for (s, t, weight) in data.edges {
+            ng.add_edge_by_name(&s, &t, weight);
+        }
+        for (node, subgraph) in data.node_to_subgraph {
+            ng.set_node_subgraph(&node, subgraph);
+        }
+        ng.subgraph_labels.extend(data.subgraph_labels);
+        for (child, parent) in data.subgraph_parent {
+            ng.set_subgraph_parent(&child, &parent)
+                .map_err(D::Error::custom)?;
+        }
+        for (node, attrs) in data.node_attrs {
+            for (key, value) in attrs {
+                ng.set_node_attr(&node, &key, &value);
+            }
+        }
+        for (s, t, attrs) in data.edge_attrs {
+            for (key, value) in attrs {
+                ng.set_edge_attr(&s, &t, key, value);
+            }
+        }
+        ng.set_stable_cluster_ids(data.stable_cluster_ids);
+        Ok(ng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use petgraph::{Directed, Undirected};
+
+    #[test]
+    fn json_round_trip() {
+        let mut ng = NamedGraph::<u32, Directed>::new_directed();
+        ng.add_edge_by_name("a", "b", 1);
+        ng.add_edge_by_name("b", "c", 2);
+        ng.add_edge_by_name("c", "a", 3);
+        ng.ensure_node("lonely");
+        ng.set_node_subgraph("a", "Inputs");
+        ng.set_subgraph_parent("Inputs", "Pipeline").unwrap();
+        ng.set_edge_attr("a", "b", "color", "red");
+        ng.set_node_attr("b", "shape", "box");
+        ng.ensure_node("gone");
+        ng.set_node_subgraph("gone", "Empty");
+        ng.remove_node_by_name("gone");
+
+        let json = serde_json::to_string(&ng).unwrap();
+        let loaded: NamedGraph<u32, Directed> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(loaded.node_names(), ng.node_names());
+        assert_eq!(loaded.edges_with_names(), ng.edges_with_names());
+        assert_eq!(loaded.get_node_index("lonely"), ng.get_node_index("lonely"));
+        assert_eq!(loaded.to_dot(), ng.to_dot());
+        assert_eq!(loaded.subgraph_report(), ng.subgraph_report());
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+
+        assert!(serde_json::from_str::<NamedGraph<u32, Undirected>>(&json).is_err());
+    }
+}
@@ -72,13 +72,15 @@ async fn main() -> Result<()> {
 
     println!("--- LLM Response (DSL) ---");
     let mut llm_resp = String::new();
-    model.generate(&full_prompt, 64, |s| {
-        // Reduced from 256 to 64 tokens
+    let mut on_text = |s: String| -> Result<()> {
         print!("{s}");
         std::io::stdout().flush()?;
         llm_resp.push_str(&s);
         Ok(())
-    })?;
+    };
+    // Reduced from 256 to 64 tokens
+    model.generate(&full_prompt, 64, &mut on_text)?;
+    model.finish_generation(&mut on_text)?;
     llm_resp = llm_resp.trim().to_string();
 
     // Sanitize LLM output: keep only valid DSL lines
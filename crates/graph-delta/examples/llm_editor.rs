@@ -94,12 +94,14 @@ Tool calls:"#,
 
     println!("--- Querying LLM ---");
     let mut llm_response = String::new();
-    model.generate(&prompt, 512, |s| {
+    let mut on_text = |s: String| -> Result<()> {
         print!("{}", s);
         std::io::stdout().flush()?;
         llm_response.push_str(&s);
         Ok(())
-    })?;
+    };
+    model.generate(&prompt, 512, &mut on_text)?;
+    model.finish_generation(&mut on_text)?;
     println!("\n");
 
     // 5. Parse tool calls from response
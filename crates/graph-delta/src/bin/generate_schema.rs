@@ -0,0 +1,57 @@
+//! Writes the JSON schemas for [DotCommand] and the DSL AST, plus DSL examples, to `generated/`.
+//!
+//! Run with: cargo run -p graph-delta --bin generate_schema
+use graph_delta::commands::DotCommand;
+use graph_delta::dsl::{DslCommand, parse_dsl};
+use std::fs;
+use std::path::Path;
+
+/// (description, DSL script) pairs rendered into `dsl_examples.md`.
+const DSL_EXAMPLES: &[(&str, &str)] = &[
+    ("Add or update a node", r#"node A label="Start" shape=box"#),
+    ("Delete a node", "node delete A"),
+    ("Add or update an edge", "edge A -> B color=red penwidth=3"),
+    ("Delete an edge", "edge delete A -> B"),
+    ("Add or update a cluster", r#"subgraph Main label="Main""#),
+    ("Move a node into a cluster", "subgraph move A to Main"),
+    (
+        "Graph attributes and defaults",
+        "graph set rankdir=LR\nnode defaults shape=box\nedge defaults color=gray",
+    ),
+    ("Keep nodes on the same rank", "rank same A, B, C"),
+];
+
+fn main() -> anyhow::Result<()> {
+    let out_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("generated");
+    fs::create_dir_all(&out_dir)?;
+
+    let dot_schema = schemars::schema_for!(DotCommand);
+    fs::write(
+        out_dir.join("dot_command_schema.json"),
+        serde_json::to_string_pretty(&dot_schema)?,
+    )?;
+
+    let dsl_schema = schemars::schema_for!(DslCommand);
+    fs::write(
+        out_dir.join("dsl_command_schema.json"),
+        serde_json::to_string_pretty(&dsl_schema)?,
+    )?;
+
+    let mut examples = String::from(
+        "# DSL Examples\n\nValid DSL scripts and the commands they parse to.\nGenerated automatically - do not edit manually.\n",
+    );
+    for (description, script) in DSL_EXAMPLES {
+        let commands = parse_dsl(script).map_err(|e| anyhow::anyhow!("{script}: {e}"))?;
+        examples.push_str(&format!(
+            "\n## {}\n\n```\n{}\n```\n\n```json\n{}\n```\n",
+            description,
+            script,
+            // Via Value so the attribute maps print with sorted keys.
+            serde_json::to_string_pretty(&serde_json::to_value(&commands)?)?
+        ));
+    }
+    fs::write(out_dir.join("dsl_examples.md"), examples)?;
+
+    println!("Wrote schemas and examples to {}", out_dir.display());
+    Ok(())
+}
@@ -0,0 +1,190 @@
+//! Graph analysis over parsed DOT chunks.
+use crate::parser::Chunk;
+use crate::port;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Node ids in first-seen order, plus the outgoing adjacency built from `edge` chunks.
+/// Declared `node` chunks are included so that isolated nodes show up too.
+fn adjacency(chunks: &[Chunk]) -> (Vec<String>, Vec<Vec<usize>>) {
+    let mut ids: Vec<String> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut adj: Vec<Vec<usize>> = Vec::new();
+
+    let mut intern = |id: &str, ids: &mut Vec<String>, adj: &mut Vec<Vec<usize>>| -> usize {
+        *index.entry(id.to_string()).or_insert_with(|| {
+            ids.push(id.to_string());
+            adj.push(Vec::new());
+            ids.len() - 1
+        })
+    };
+
+    for chunk in chunks {
+        match chunk.kind.as_str() {
+            "node" | "bare_node" => {
+                if let Some(id) = &chunk.id {
+                    intern(id, &mut ids, &mut adj);
+                }
+            }
+            "edge" => {
+                if let (Some(from), Some(to)) = (&chunk.id, &chunk.extra) {
+                    // `A:p1 -> B` connects node `A`.
+                    let f = intern(port::parse_port(from).0, &mut ids, &mut adj);
+                    let t = intern(port::parse_port(to).0, &mut ids, &mut adj);
+                    adj[f].push(t);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    (ids, adj)
+}
+
+/// Every node id, declared or only named by an edge, in first-seen order.
+pub(crate) fn node_ids(chunks: &[Chunk]) -> Vec<String> {
+    adjacency(chunks).0
+}
+
+/// Groups node ids into strongly connected components, treating edge chunks as directed
+/// `id -> extra` edges (Tarjan's algorithm, iterative so long chains don't overflow the stack).
+///
+/// Every node gets a group; nodes that are not on any cycle form singleton groups. Each group
+/// is sorted and the groups are sorted by their first id, so the output is deterministic.
+pub fn strongly_connected_components(chunks: &[Chunk]) -> Vec<Vec<String>> {
+    let (ids, adj) = adjacency(chunks);
+    let n = ids.len();
+
+    let mut next_index = 0;
+    let mut indices: Vec<Option<usize>> = vec![None; n];
+    let mut lowlink = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack: Vec<usize> = Vec::new();
+    let mut components: Vec<Vec<String>> = Vec::new();
+
+    for root in 0..n {
+        if indices[root].is_some() {
+            continue;
+        }
+
+        // (node, position of the next neighbor to visit)
+        let mut call_stack: Vec<(usize, usize)> = vec![(root, 0)];
+        indices[root] = Some(next_index);
+        lowlink[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+
+        while let Some(&(v, pos)) = call_stack.last() {
+            if let Some(&w) = adj[v].get(pos) {
+                if let Some(frame) = call_stack.last_mut() {
+                    frame.1 += 1;
+                }
+                match indices[w] {
+                    None => {
+                        indices[w] = Some(next_index);
+                        lowlink[w] = next_index;
+                        next_index += 1;
+                        stack.push(w);
+                        on_stack[w] = true;
+                        call_stack.push((w, 0));
+                    }
+                    Some(w_index) if on_stack[w] => {
+                        lowlink[v] = lowlink[v].min(w_index);
+                    }
+                    Some(_) => {}
+                }
+                continue;
+            }
+
+            call_stack.pop();
+            if let Some(&(parent, _)) = call_stack.last() {
+                lowlink[parent] = lowlink[parent].min(lowlink[v]);
+            }
+
+            if Some(lowlink[v]) == indices[v] {
+                let mut component = Vec::new();
+                while let Some(w) = stack.pop() {
+                    on_stack[w] = false;
+                    component.push(ids[w].clone());
+                    if w == v {
+                        break;
+                    }
+                }
+                component.sort();
+                components.push(component);
+            }
+        }
+    }
+
+    components.sort();
+    components
+}
+
+/// Node ids in topological order of the `id -> extra` edges (Kahn's algorithm). Ties, and nodes
+/// stuck on cycles, fall back to first-seen order so every node appears exactly once.
+pub(crate) fn topological_order(chunks: &[Chunk]) -> Vec<String> {
+    let (ids, adj) = adjacency(chunks);
+    let n = ids.len();
+
+    let mut in_degree = vec![0; n];
+    for targets in &adj {
+        for &t in targets {
+            in_degree[t] += 1;
+        }
+    }
+
+    let mut ready: BinaryHeap<Reverse<usize>> = (0..n)
+        .filter(|&i| in_degree[i] == 0)
+        .map(Reverse)
+        .collect();
+    let mut emitted = vec![false; n];
+    let mut order = Vec::with_capacity(n);
+
+    while order.len() < n {
+        let v = match ready.pop() {
+            Some(Reverse(v)) if emitted[v] => continue,
+            Some(Reverse(v)) => v,
+            // Only cycles are left: break one open at its earliest node.
+            None => (0..n).find(|&i| !emitted[i]).unwrap(),
+        };
+        emitted[v] = true;
+        order.push(ids[v].clone());
+        for &t in &adj[v] {
+            in_degree[t] -= 1;
+            if in_degree[t] == 0 && !emitted[t] {
+                ready.push(Reverse(t));
+            }
+        }
+    }
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_dot_to_chunks;
+
+    #[test]
+    fn test_scc_cycle_with_acyclic_tail() {
+        let dot = r#"digraph G {
+    A -> B;
+    B -> C;
+    C -> A;
+    C -> D;
+    D -> E;
+}"#;
+        let chunks = parse_dot_to_chunks(dot).unwrap();
+        let sccs = strongly_connected_components(&chunks);
+
+        assert_eq!(
+            sccs,
+            vec![
+                vec!["A".to_string(), "B".to_string(), "C".to_string()],
+                vec!["D".to_string()],
+                vec!["E".to_string()],
+            ]
+        );
+    }
+}
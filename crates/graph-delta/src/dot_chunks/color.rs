@@ -0,0 +1,137 @@
+//! Color attribute helpers aware of Graphviz `colorscheme` indices.
+use crate::parser::Chunk;
+use std::collections::HashMap;
+
+/// A subset of the Brewer schemes Graphviz ships, as `(name, colors)`; index `n` is `colors[n - 1]`.
+const BREWER_SCHEMES: &[(&str, &[&str])] = &[
+    (
+        "blues9",
+        &[
+            "#f7fbff", "#deebf7", "#c6dbef", "#9ecae1", "#6baed6", "#4292c6", "#2171b5", "#08519c",
+            "#08306b",
+        ],
+    ),
+    (
+        "greens9",
+        &[
+            "#f7fcf5", "#e5f5e0", "#c7e9c0", "#a1d99b", "#74c476", "#41ab5d", "#238b45", "#006d2c",
+            "#00441b",
+        ],
+    ),
+    (
+        "greys9",
+        &[
+            "#ffffff", "#f0f0f0", "#d9d9d9", "#bdbdbd", "#969696", "#737373", "#525252", "#252525",
+            "#000000",
+        ],
+    ),
+    (
+        "oranges9",
+        &[
+            "#fff5eb", "#fee6ce", "#fdd0a2", "#fdae6b", "#fd8d3c", "#f16913", "#d94801", "#a63603",
+            "#7f2704",
+        ],
+    ),
+    (
+        "purples9",
+        &[
+            "#fcfbfd", "#efedf5", "#dadaeb", "#bcbddc", "#9e9ac8", "#807dba", "#6a51a3", "#54278f",
+            "#3f007d",
+        ],
+    ),
+    (
+        "reds9",
+        &[
+            "#fff5f0", "#fee0d2", "#fcbba1", "#fc9272", "#fb6a4a", "#ef3b2c", "#cb181d", "#a50f15",
+            "#67000d",
+        ],
+    ),
+    (
+        "set19",
+        &[
+            "#e41a1c", "#377eb8", "#4daf4a", "#984ea3", "#ff7f00", "#ffff33", "#a65628", "#f781bf",
+            "#999999",
+        ],
+    ),
+    (
+        "dark28",
+        &[
+            "#1b9e77", "#d95f02", "#7570b3", "#e7298a", "#66a61e", "#e6ab02", "#a6761d", "#666666",
+        ],
+    ),
+    (
+        "paired12",
+        &[
+            "#a6cee3", "#1f78b4", "#b2df8a", "#33a02c", "#fb9a99", "#e31a1c", "#fdbf6f", "#ff7f00",
+            "#cab2d6", "#6a3d9a", "#ffff99", "#b15928",
+        ],
+    ),
+];
+
+/// Looks up color `index` (1-based) in the named scheme, ignoring case.
+pub fn scheme_color(scheme: &str, index: usize) -> Option<&'static str> {
+    let (_, colors) = BREWER_SCHEMES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(scheme))?;
+    colors.get(index.checked_sub(1)?).copied()
+}
+
+/// Resolves a single color value: `/scheme/n` or a bare `n` under `colorscheme` becomes the
+/// scheme's hex color. Anything unknown (named colors, hex, unknown schemes) is returned as is.
+pub fn resolve_color(value: &str, colorscheme: Option<&str>) -> String {
+    let resolved = match value.strip_prefix('/').and_then(|v| v.split_once('/')) {
+        Some((scheme, index)) => index.parse().ok().and_then(|i| scheme_color(scheme, i)),
+        None => colorscheme
+            .zip(value.parse().ok())
+            .and_then(|(scheme, i)| scheme_color(scheme, i)),
+    };
+    resolved
+        .map(str::to_string)
+        .unwrap_or_else(|| value.to_string())
+}
+
+/// The color attribute `key` (`color`, `fillcolor`, `fontcolor`, ...) resolved against the
+/// attributes' own `colorscheme`.
+pub fn attr_color(attrs: &HashMap<String, String>, key: &str) -> Option<String> {
+    let value = attrs.get(key)?;
+    Some(resolve_color(
+        value,
+        attrs.get("colorscheme").map(String::as_str),
+    ))
+}
+
+impl Chunk {
+    /// See [attr_color].
+    pub fn attr_color(&self, key: &str) -> Option<String> {
+        attr_color(&self.attrs, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_dot_to_chunks;
+
+    #[test]
+    fn test_attr_color_resolves_brewer_index() {
+        let dot = r#"digraph G {
+    A [colorscheme=blues9, color=3, fillcolor="/reds9/9", fontcolor=red];
+    B [colorscheme=nosuchscheme, color=3];
+}"#;
+        let chunks = parse_dot_to_chunks(dot).unwrap();
+        let a = chunks
+            .iter()
+            .find(|c| c.id.as_deref() == Some("A"))
+            .unwrap();
+        let b = chunks
+            .iter()
+            .find(|c| c.id.as_deref() == Some("B"))
+            .unwrap();
+
+        assert_eq!(a.attr_color("color").as_deref(), Some("#c6dbef"));
+        assert_eq!(a.attr_color("fillcolor").as_deref(), Some("#67000d"));
+        assert_eq!(a.attr_color("fontcolor").as_deref(), Some("red"));
+        assert_eq!(a.attr_color("pencolor"), None);
+
+        assert_eq!(b.attr_color("color").as_deref(), Some("3"));
+    }
+}
@@ -1,9 +1,13 @@
 //! Commands for modifying DOT graph structures.
+use crate::analysis;
 use crate::parser::{self, Chunk};
+use crate::port;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
 use std::collections::HashMap;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "action", rename_all = "snake_case")]
 pub enum DotCommand {
     // Node operations
@@ -23,6 +27,27 @@ pub enum DotCommand {
     DeleteNode {
         id: String,
     },
+    /// Typed styling of an existing node; prefer this over hand-written `attrs` in
+    /// [DotCommand::UpdateNode]. Unset fields are left alone.
+    StyleNode {
+        id: String,
+        /// Fill color, a color name like `red` or `#rrggbb` / `#rrggbbaa`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fill: Option<String>,
+        /// Graphviz node shape, e.g. `box`, `ellipse`, `circle`, `diamond` or `record`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        shape: Option<String>,
+        /// Adds `style=filled`, which Graphviz needs to paint `fill`
+        #[serde(default)]
+        filled: bool,
+        /// Number of outlines drawn around the node, e.g. 2 for a double circle
+        #[serde(skip_serializing_if = "Option::is_none")]
+        peripheries: Option<u32>,
+    },
+    /// Removes every attribute of an existing node, leaving a bare `id;` statement.
+    ClearNodeAttrs {
+        id: String,
+    },
 
     // Edge operations
     CreateEdge {
@@ -44,8 +69,15 @@ pub enum DotCommand {
         from: String,
         to: String,
     },
+    /// Removes every attribute of an existing edge, leaving a bare `from -> to;` statement.
+    ClearEdgeAttrs {
+        from: String,
+        to: String,
+    },
 
     // Subgraph operations
+    /// `id` gets a `cluster_` prefix if it lacks one, so the subgraph is drawn as a cluster.
+    /// Parents and [DotCommand::DeleteSubgraph] may name it with or without the prefix.
     CreateSubgraph {
         #[serde(skip_serializing_if = "Option::is_none")]
         id: Option<String>,
@@ -70,6 +102,73 @@ pub enum DotCommand {
     DeleteAttr {
         key: String,
     },
+
+    // Layout operations
+    /// Reorders statements within each graph/subgraph body; subgraph membership is unchanged.
+    Reorder {
+        strategy: OrderStrategy,
+    },
+    /// Sets the common layout-tuning graph attributes in one go; unset fields are left alone.
+    SetLayout {
+        /// `TB`, `LR`, `BT` or `RL`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rankdir: Option<String>,
+        /// Minimum space between nodes of the same rank, in inches (>= 0)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nodesep: Option<f64>,
+        /// Space between ranks, in inches (>= 0)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ranksep: Option<f64>,
+        /// Edge routing, e.g. `spline`, `ortho`, `polyline`, `line`, `curved` or `none`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        splines: Option<String>,
+    },
+    /// Adds a `{ rank=...; ... }` constraint over `nodes`.
+    SetRank {
+        kind: RankKind,
+        nodes: Vec<String>,
+    },
+}
+
+/// Rank constraint of a [DotCommand::SetRank], as in Graphviz's `rank` subgraph attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RankKind {
+    /// All nodes on the same rank.
+    Same,
+    /// All nodes on the minimum rank.
+    Min,
+    /// All nodes on the maximum rank.
+    Max,
+    /// The only nodes on the minimum rank.
+    Source,
+    /// The only nodes on the maximum rank.
+    Sink,
+}
+
+impl RankKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RankKind::Same => "same",
+            RankKind::Min => "min",
+            RankKind::Max => "max",
+            RankKind::Source => "source",
+            RankKind::Sink => "sink",
+        }
+    }
+}
+
+/// How [DotCommand::Reorder] arranges the statements of each body. Every strategy groups by kind
+/// first (graph attributes, defaults, nodes, subgraphs, then edges) and is stable within a group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStrategy {
+    /// Only group by kind, keeping the existing order inside each group.
+    GroupByKind,
+    /// Within each group sort by id (edges by source, then target).
+    Alphabetical,
+    /// Within the edge group follow a topological order of the edge endpoints.
+    TopologicalEdges,
 }
 
 impl std::fmt::Display for DotCommand {
@@ -90,9 +189,7 @@ pub fn apply_command(chunks: &mut Vec<Chunk>, command: &DotCommand) -> Result<()
             }
 
             let (insert_pos, line) = if let Some(parent_name) = parent {
-                let parent_pos = chunks
-                    .iter()
-                    .position(|c| c.kind == "subgraph" && c.id.as_ref() == Some(parent_name))
+                let parent_pos = subgraph_position(chunks, parent_name)
                     .ok_or_else(|| format!("Parent subgraph '{}' not found", parent_name))?;
                 let parent_range = chunks[parent_pos].range;
                 let last_child_pos = chunks
@@ -133,6 +230,7 @@ pub fn apply_command(chunks: &mut Vec<Chunk>, command: &DotCommand) -> Result<()
                         .unwrap_or_default(),
                     range: (line, line),
                     extra: None,
+                    span: None,
                 },
             );
             Ok(())
@@ -151,6 +249,22 @@ pub fn apply_command(chunks: &mut Vec<Chunk>, command: &DotCommand) -> Result<()
             Ok(())
         }
 
+        DotCommand::StyleNode {
+            id,
+            fill,
+            shape,
+            filled,
+            peripheries,
+        } => {
+            let attrs = style_attrs(fill.as_deref(), shape.as_deref(), *filled, *peripheries)?;
+            let node = chunks
+                .iter_mut()
+                .find(|c| c.kind == "node" && c.id.as_ref() == Some(id))
+                .ok_or_else(|| format!("Node '{}' not found", id))?;
+            node.attrs.extend(attrs);
+            Ok(())
+        }
+
         DotCommand::DeleteNode { id } => {
             let pos = chunks
                 .iter()
@@ -160,22 +274,27 @@ pub fn apply_command(chunks: &mut Vec<Chunk>, command: &DotCommand) -> Result<()
             Ok(())
         }
 
+        DotCommand::ClearNodeAttrs { id } => {
+            let node = chunks
+                .iter_mut()
+                .find(|c| c.kind == "node" && c.id.as_ref() == Some(id))
+                .ok_or_else(|| format!("Node '{}' not found", id))?;
+            node.attrs.clear();
+            Ok(())
+        }
+
         DotCommand::CreateEdge {
             from,
             to,
             attrs,
             parent,
         } => {
-            if chunks.iter().any(|c| {
-                c.kind == "edge" && c.id.as_ref() == Some(from) && c.extra.as_ref() == Some(to)
-            }) {
+            if chunks.iter().any(|c| is_edge(c, from, to)) {
                 return Err(format!("Edge '{}' -> '{}' already exists", from, to));
             }
 
             let (insert_pos, line) = if let Some(parent_name) = parent {
-                let parent_pos = chunks
-                    .iter()
-                    .position(|c| c.kind == "subgraph" && c.id.as_ref() == Some(parent_name))
+                let parent_pos = subgraph_position(chunks, parent_name)
                     .ok_or_else(|| format!("Parent subgraph '{}' not found", parent_name))?;
                 let parent_range = chunks[parent_pos].range;
                 let last_child_pos = chunks
@@ -209,22 +328,22 @@ pub fn apply_command(chunks: &mut Vec<Chunk>, command: &DotCommand) -> Result<()
                 insert_pos,
                 Chunk {
                     kind: "edge".to_string(),
-                    id: Some(from.clone()),
+                    id: Some(port::normalize_port(from)),
                     attrs: attrs
                         .as_deref()
                         .map(parser::parse_attribute_string)
                         .unwrap_or_default(),
                     range: (line, line),
-                    extra: Some(to.clone()),
+                    extra: Some(port::normalize_port(to)),
+                    span: None,
                 },
             );
             Ok(())
         }
 
         DotCommand::UpdateEdge { from, to, attrs } => {
-            if let Some(edge) = chunks.iter_mut().find(|c| {
-                c.kind == "edge" && c.id.as_ref() == Some(from) && c.extra.as_ref() == Some(to)
-            }) {
+            if let Some(pos) = edge_position(chunks, from, to) {
+                let edge = &mut chunks[pos];
                 if let Some(new_attrs_str) = attrs {
                     let new_attrs_map = parser::parse_attribute_string(new_attrs_str);
                     edge.attrs.extend(new_attrs_map);
@@ -238,31 +357,37 @@ pub fn apply_command(chunks: &mut Vec<Chunk>, command: &DotCommand) -> Result<()
                 };
                 chunks.push(Chunk {
                     kind: "edge".to_string(),
-                    id: Some(from.clone()),
+                    id: Some(port::normalize_port(from)),
                     attrs: attrs
                         .as_deref()
                         .map(parser::parse_attribute_string)
                         .unwrap_or_default(),
                     range: (line, line),
-                    extra: Some(to.clone()),
+                    extra: Some(port::normalize_port(to)),
+                    span: None,
                 });
                 Ok(())
             }
         }
 
         DotCommand::DeleteEdge { from, to } => {
-            let pos = chunks
-                .iter()
-                .position(|c| {
-                    c.kind == "edge" && c.id.as_ref() == Some(from) && c.extra.as_ref() == Some(to)
-                })
+            let pos = edge_position(chunks, from, to)
                 .ok_or_else(|| format!("Edge '{}' -> '{}' not found", from, to))?;
             chunks.remove(pos);
             Ok(())
         }
 
+        DotCommand::ClearEdgeAttrs { from, to } => {
+            let pos = edge_position(chunks, from, to)
+                .ok_or_else(|| format!("Edge '{}' -> '{}' not found", from, to))?;
+            chunks[pos].attrs.clear();
+            Ok(())
+        }
+
         DotCommand::CreateSubgraph { id, parent } => {
-            if let Some(id_str) = id {
+            // Graphviz only draws subgraphs named `cluster_*` as boxes, like the DSL's clusters.
+            let id = id.as_deref().map(cluster_id);
+            if let Some(id_str) = &id {
                 if chunks
                     .iter()
                     .any(|c| c.kind == "subgraph" && c.id.as_ref() == Some(id_str))
@@ -272,9 +397,7 @@ pub fn apply_command(chunks: &mut Vec<Chunk>, command: &DotCommand) -> Result<()
             }
 
             let (insert_pos, line_start, line_end) = if let Some(parent_name) = parent {
-                let parent_pos = chunks
-                    .iter()
-                    .position(|c| c.kind == "subgraph" && c.id.as_ref() == Some(parent_name))
+                let parent_pos = subgraph_position(chunks, parent_name)
                     .ok_or_else(|| format!("Parent subgraph '{}' not found", parent_name))?;
                 let parent_range = chunks[parent_pos].range;
                 (parent_pos + 1, parent_range.0 + 1, parent_range.1 - 1)
@@ -291,19 +414,18 @@ pub fn apply_command(chunks: &mut Vec<Chunk>, command: &DotCommand) -> Result<()
                 insert_pos,
                 Chunk {
                     kind: "subgraph".to_string(),
-                    id: id.clone(),
+                    id,
                     attrs: HashMap::new(),
                     range: (line_start, line_end),
                     extra: None,
+                    span: None,
                 },
             );
             Ok(())
         }
 
         DotCommand::DeleteSubgraph { id } => {
-            let subgraph_pos = chunks
-                .iter()
-                .position(|c| c.kind == "subgraph" && c.id.as_ref() == Some(id))
+            let subgraph_pos = subgraph_position(chunks, id)
                 .ok_or_else(|| format!("Subgraph '{}' not found", id))?;
             let subgraph_range = chunks[subgraph_pos].range;
             chunks.retain(|c| !(c.range.0 >= subgraph_range.0 && c.range.1 <= subgraph_range.1));
@@ -325,6 +447,7 @@ pub fn apply_command(chunks: &mut Vec<Chunk>, command: &DotCommand) -> Result<()
                         attrs: HashMap::new(),
                         range: (1, 1),
                         extra: Some(value.clone()),
+                        span: None,
                     },
                 );
             }
@@ -351,6 +474,7 @@ pub fn apply_command(chunks: &mut Vec<Chunk>, command: &DotCommand) -> Result<()
                         attrs: new_attrs,
                         range: (1, 1),
                         extra: None,
+                        span: None,
                     },
                 );
             }
@@ -378,6 +502,7 @@ pub fn apply_command(chunks: &mut Vec<Chunk>, command: &DotCommand) -> Result<()
                         attrs: new_attrs,
                         range: (1, 1),
                         extra: None,
+                        span: None,
                     },
                 );
             }
@@ -392,7 +517,446 @@ pub fn apply_command(chunks: &mut Vec<Chunk>, command: &DotCommand) -> Result<()
             chunks.remove(pos);
             Ok(())
         }
+
+        DotCommand::Reorder { strategy } => {
+            reorder_chunks(chunks, *strategy);
+            Ok(())
+        }
+
+        DotCommand::SetLayout {
+            rankdir,
+            nodesep,
+            ranksep,
+            splines,
+        } => {
+            // Validate everything first so a bad value leaves the graph untouched.
+            let attrs = layout_attrs(rankdir.as_deref(), *nodesep, *ranksep, splines.as_deref())?;
+            for (key, value) in attrs {
+                apply_command(chunks, &DotCommand::SetGraphAttr { key, value })?;
+            }
+            Ok(())
+        }
+
+        DotCommand::SetRank { kind, nodes } => {
+            if nodes.is_empty() {
+                return Err("Rank constraint needs at least one node".to_string());
+            }
+            let known = analysis::node_ids(chunks);
+            if let Some(id) = nodes.iter().find(|id| !known.contains(id)) {
+                return Err(format!("Node '{}' not found", id));
+            }
+            // After every existing line, so it doesn't land inside a subgraph.
+            let line = chunks.iter().map(|c| c.range.1).max().unwrap_or(0) + 1;
+            let mut rank = Chunk {
+                kind: "rank".to_string(),
+                id: Some(kind.as_str().to_string()),
+                attrs: HashMap::new(),
+                range: (line, line),
+                extra: None,
+                span: None,
+            };
+            rank.set_rank_members(nodes);
+            chunks.push(rank);
+            Ok(())
+        }
+    }
+}
+
+/// Positions of node and edge chunks by id, so that [apply_commands] can resolve updates without
+/// scanning the whole chunk vector. The first chunk wins for duplicate ids, like the linear
+/// lookups in [apply_command].
+#[derive(Debug, Default)]
+pub struct ChunkIndex {
+    nodes: HashMap<String, usize>,
+    edges: HashMap<(String, String), usize>,
+}
+
+impl ChunkIndex {
+    pub fn build(chunks: &[Chunk]) -> Self {
+        let mut index = Self::default();
+        for (pos, chunk) in chunks.iter().enumerate() {
+            match (chunk.kind.as_str(), &chunk.id, &chunk.extra) {
+                ("node", Some(id), _) => {
+                    index.nodes.entry(id.clone()).or_insert(pos);
+                }
+                ("edge", Some(from), Some(to)) => {
+                    let key = (port::normalize_port(from), port::normalize_port(to));
+                    index.edges.entry(key).or_insert(pos);
+                }
+                _ => {}
+            }
+        }
+        index
+    }
+
+    pub fn node(&self, id: &str) -> Option<usize> {
+        self.nodes.get(id).copied()
+    }
+
+    pub fn edge(&self, from: &str, to: &str) -> Option<usize> {
+        let key = (port::normalize_port(from), port::normalize_port(to));
+        self.edges.get(&key).copied()
+    }
+}
+
+/// Applies `commands` in order, stopping at the first error; commands before it stay applied.
+///
+/// Node and edge updates go through a [ChunkIndex] instead of a linear search. Any other command
+/// may move chunks around, so it is applied with [apply_command] and the index is rebuilt the
+/// next time an update needs it; long runs of updates are where this pays off.
+pub fn apply_commands(chunks: &mut Vec<Chunk>, commands: &[DotCommand]) -> Result<(), String> {
+    let mut index: Option<ChunkIndex> = None;
+    for command in commands {
+        let indexed = index.get_or_insert_with(|| ChunkIndex::build(chunks));
+        let pos = match command {
+            DotCommand::UpdateNode { id, .. } => Some(
+                indexed
+                    .node(id)
+                    .ok_or_else(|| format!("Node '{}' not found", id))?,
+            ),
+            // A missing edge, or one that only matches by base id, is left to apply_command.
+            DotCommand::UpdateEdge { from, to, .. } => indexed.edge(from, to),
+            _ => None,
+        };
+        match (pos, command) {
+            (Some(pos), DotCommand::UpdateNode { attrs, .. })
+            | (Some(pos), DotCommand::UpdateEdge { attrs, .. }) => {
+                if let Some(new_attrs_str) = attrs {
+                    let new_attrs_map = parser::parse_attribute_string(new_attrs_str);
+                    chunks[pos].attrs.extend(new_attrs_map);
+                }
+            }
+            _ => {
+                apply_command(chunks, command)?;
+                index = None;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Like [apply_commands], but all or nothing: if any command fails, `chunks` is left as it was.
+pub fn apply_commands_atomic(
+    chunks: &mut Vec<Chunk>,
+    commands: &[DotCommand],
+) -> Result<(), String> {
+    let mut working = chunks.clone();
+    apply_commands(&mut working, commands)?;
+    *chunks = working;
+    Ok(())
+}
+
+/// `id` with the `cluster_` prefix, inside the quotes for a quoted id.
+fn cluster_id(id: &str) -> String {
+    match id.strip_prefix('"') {
+        Some(inner) if !inner.starts_with("cluster_") => format!("\"cluster_{}", inner),
+        None if !id.starts_with("cluster_") => format!("cluster_{}", id),
+        _ => id.to_string(),
+    }
+}
+
+/// The subgraph called `name`, or `cluster_<name>` as [DotCommand::CreateSubgraph] names it.
+fn subgraph_position(chunks: &[Chunk], name: &str) -> Option<usize> {
+    let find = |name: &str| {
+        chunks
+            .iter()
+            .position(|c| c.kind == "subgraph" && c.id.as_deref() == Some(name))
+    };
+    find(name).or_else(|| find(&cluster_id(name)))
+}
+
+/// Whether `chunk` is the edge `from -> to`, ports and compass points included.
+fn is_edge(chunk: &Chunk, from: &str, to: &str) -> bool {
+    let parse = port::parse_port;
+    match (chunk.kind.as_str(), &chunk.id, &chunk.extra) {
+        ("edge", Some(id), Some(extra)) => parse(id) == parse(from) && parse(extra) == parse(to),
+        _ => false,
+    }
+}
+
+/// The edge `from -> to`: the first with exactly these ports, else the first that
+/// [port::same_endpoint] matches, so `A -> B` also finds `A:p1 -> B`.
+fn edge_position(chunks: &[Chunk], from: &str, to: &str) -> Option<usize> {
+    chunks
+        .iter()
+        .position(|c| is_edge(c, from, to))
+        .or_else(|| {
+            chunks
+                .iter()
+                .position(|c| match (c.kind.as_str(), &c.id, &c.extra) {
+                    ("edge", Some(id), Some(extra)) => {
+                        port::same_endpoint(id, from) && port::same_endpoint(extra, to)
+                    }
+                    _ => false,
+                })
+        })
+}
+
+const RANKDIRS: &[&str] = &["TB", "LR", "BT", "RL"];
+const SPLINES: &[&str] = &[
+    "none", "false", "line", "polyline", "curved", "ortho", "spline", "true",
+];
+
+/// The graph attributes a [DotCommand::SetLayout] sets, in field order.
+pub(crate) fn layout_attrs(
+    rankdir: Option<&str>,
+    nodesep: Option<f64>,
+    ranksep: Option<f64>,
+    splines: Option<&str>,
+) -> Result<Vec<(String, String)>, String> {
+    let mut attrs = Vec::new();
+    if let Some(rankdir) = rankdir {
+        if !RANKDIRS.contains(&rankdir) {
+            return Err(format!(
+                "Invalid rankdir '{}', expected one of {}",
+                rankdir,
+                RANKDIRS.join(", ")
+            ));
+        }
+        attrs.push(("rankdir".to_string(), rankdir.to_string()));
+    }
+    for (key, value) in [("nodesep", nodesep), ("ranksep", ranksep)] {
+        if let Some(value) = value {
+            if !value.is_finite() || value < 0.0 {
+                return Err(format!("Invalid {} {}, must be >= 0", key, value));
+            }
+            attrs.push((key.to_string(), value.to_string()));
+        }
+    }
+    if let Some(splines) = splines {
+        if !SPLINES.contains(&splines) {
+            return Err(format!(
+                "Invalid splines '{}', expected one of {}",
+                splines,
+                SPLINES.join(", ")
+            ));
+        }
+        attrs.push(("splines".to_string(), splines.to_string()));
+    }
+    Ok(attrs)
+}
+
+const SHAPES: &[&str] = &[
+    "box",
+    "polygon",
+    "ellipse",
+    "oval",
+    "circle",
+    "point",
+    "egg",
+    "triangle",
+    "plaintext",
+    "plain",
+    "diamond",
+    "trapezium",
+    "parallelogram",
+    "house",
+    "pentagon",
+    "hexagon",
+    "septagon",
+    "octagon",
+    "doublecircle",
+    "doubleoctagon",
+    "tripleoctagon",
+    "invtriangle",
+    "invtrapezium",
+    "invhouse",
+    "Mdiamond",
+    "Msquare",
+    "Mcircle",
+    "rect",
+    "rectangle",
+    "square",
+    "star",
+    "none",
+    "underline",
+    "cylinder",
+    "note",
+    "tab",
+    "folder",
+    "box3d",
+    "component",
+    "record",
+    "Mrecord",
+];
+
+fn is_color(color: &str) -> bool {
+    match color.strip_prefix('#') {
+        Some(hex) => matches!(hex.len(), 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => !color.is_empty() && color.chars().all(|c| c.is_ascii_alphanumeric()),
+    }
+}
+
+/// The node attributes a [DotCommand::StyleNode] sets, in field order.
+pub(crate) fn style_attrs(
+    fill: Option<&str>,
+    shape: Option<&str>,
+    filled: bool,
+    peripheries: Option<u32>,
+) -> Result<Vec<(String, String)>, String> {
+    let mut attrs = Vec::new();
+    if let Some(fill) = fill {
+        if !is_color(fill) {
+            return Err(format!(
+                "Invalid fill '{}', expected a color name or #rrggbb",
+                fill
+            ));
+        }
+        attrs.push(("fillcolor".to_string(), fill.to_string()));
+    }
+    if let Some(shape) = shape {
+        if !SHAPES.contains(&shape) {
+            return Err(format!(
+                "Invalid shape '{}', expected one of {}",
+                shape,
+                SHAPES.join(", ")
+            ));
+        }
+        attrs.push(("shape".to_string(), shape.to_string()));
+    }
+    if filled {
+        attrs.push(("style".to_string(), "filled".to_string()));
+    }
+    if let Some(peripheries) = peripheries {
+        attrs.push(("peripheries".to_string(), peripheries.to_string()));
+    }
+    Ok(attrs)
+}
+
+fn kind_rank(kind: &str) -> u8 {
+    match kind {
+        "id_eq" => 0,
+        "attr_stmt" => 1,
+        "node" | "bare_node" => 2,
+        "rank" => 3,
+        "subgraph" => 4,
+        "edge" => 5,
+        _ => 6,
+    }
+}
+
+/// Sorts each body's statements by `strategy`, then renumbers the line ranges in the new order so
+/// that the range-based nesting (and `chunks_to_dot_nested`) sees the same subgraph membership.
+fn reorder_chunks(chunks: &mut Vec<Chunk>, strategy: OrderStrategy) {
+    let parent = parent_subgraphs(chunks);
+    let mut document_order: Vec<usize> = (0..chunks.len()).collect();
+    document_order.sort_by_key(|&i| chunks[i].range.0);
+    let (mut roots, mut children) = bodies(&parent, document_order);
+
+    let topo: HashMap<String, usize> = if strategy == OrderStrategy::TopologicalEdges {
+        analysis::topological_order(chunks)
+            .into_iter()
+            .enumerate()
+            .map(|(pos, id)| (id, pos))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+    let compare = |a: &usize, b: &usize| {
+        let (ca, cb) = (&chunks[*a], &chunks[*b]);
+        let by_kind = kind_rank(&ca.kind).cmp(&kind_rank(&cb.kind));
+        match strategy {
+            OrderStrategy::GroupByKind => by_kind,
+            OrderStrategy::Alphabetical => {
+                by_kind.then_with(|| (&ca.id, &ca.extra).cmp(&(&cb.id, &cb.extra)))
+            }
+            OrderStrategy::TopologicalEdges if ca.kind == "edge" && cb.kind == "edge" => {
+                let pos = |c: &Chunk| {
+                    let lookup = |id: &Option<String>| id.as_ref().and_then(|id| topo.get(id));
+                    (lookup(&c.id), lookup(&c.extra))
+                };
+                by_kind.then_with(|| pos(ca).cmp(&pos(cb)))
+            }
+            OrderStrategy::TopologicalEdges => by_kind,
+        }
+    };
+    roots.sort_by(compare);
+    for body in &mut children {
+        body.sort_by(compare);
+    }
+
+    *chunks = layout(&roots, &children, chunks);
+}
+
+/// Innermost subgraph enclosing each chunk, as an index into `chunks`. Chunks parsed from the
+/// same DOT nest by their byte spans; otherwise by line range, with inclusive bounds so that
+/// members of a subgraph written on one line are inside it, and identical ranges nesting in
+/// vector order.
+pub(crate) fn parent_subgraphs(chunks: &[Chunk]) -> Vec<Option<usize>> {
+    let inside = |j: usize, i: usize| {
+        let (s, c) = (&chunks[j], &chunks[i]);
+        match (s.span, c.span) {
+            (Some(s), Some(c)) => s.0 <= c.0 && c.1 <= s.1,
+            _ => {
+                let (s, r) = (s.range, c.range);
+                s.0 <= r.0 && r.1 <= s.1 && (s != r || j < i)
+            }
+        }
+    };
+    let size = |c: &Chunk| (c.range.1 - c.range.0, c.span.map(|s| s.1 - s.0));
+    (0..chunks.len())
+        .map(|i| {
+            (0..chunks.len())
+                .filter(|&j| j != i && chunks[j].kind == "subgraph" && inside(j, i))
+                .min_by_key(|&j| (size(&chunks[j]), Reverse(j)))
+        })
+        .collect()
+}
+
+/// `chunks` laid out one statement per line with each subgraph's range around its members, so
+/// that range-based nesting sees `parent` (as returned by [parent_subgraphs]). Each body keeps
+/// the order of `chunks`. Chunks whose parents form a cycle are left out.
+pub(crate) fn renumber(chunks: &[Chunk], parent: &[Option<usize>]) -> Vec<Chunk> {
+    let (roots, children) = bodies(parent, 0..chunks.len());
+    layout(&roots, &children, chunks)
+}
+
+/// The top-level chunks and the members of each subgraph, each in `order`.
+fn bodies(
+    parent: &[Option<usize>],
+    order: impl IntoIterator<Item = usize>,
+) -> (Vec<usize>, Vec<Vec<usize>>) {
+    let mut roots = Vec::new();
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); parent.len()];
+    for i in order {
+        match parent[i] {
+            Some(p) => children[p].push(i),
+            None => roots.push(i),
+        }
+    }
+    (roots, children)
+}
+
+fn layout(roots: &[usize], children: &[Vec<usize>], chunks: &[Chunk]) -> Vec<Chunk> {
+    fn emit(
+        body: &[usize],
+        children: &[Vec<usize>],
+        chunks: &[Chunk],
+        line: &mut usize,
+        out: &mut Vec<Chunk>,
+    ) {
+        for &i in body {
+            let mut chunk = chunks[i].clone();
+            chunk.span = None;
+            let start = *line;
+            *line += 1;
+            if chunk.kind == "subgraph" {
+                let pos = out.len();
+                out.push(chunk);
+                emit(&children[i], children, chunks, line, out);
+                out[pos].range = (start, *line);
+                *line += 1;
+            } else {
+                chunk.range = (start, start);
+                out.push(chunk);
+            }
+        }
     }
+
+    let mut line = 1;
+    let mut out = Vec::with_capacity(chunks.len());
+    emit(roots, children, chunks, &mut line, &mut out);
+    out
 }
 
 #[cfg(test)]
@@ -408,6 +972,7 @@ mod tests {
                 attrs: parser::parse_attribute_string(r#"label="Node A""#),
                 range: (1, 1),
                 extra: None,
+                span: None,
             },
             Chunk {
                 kind: "node".to_string(),
@@ -415,6 +980,7 @@ mod tests {
                 attrs: parser::parse_attribute_string(r#"label="Node B""#),
                 range: (2, 2),
                 extra: None,
+                span: None,
             },
             Chunk {
                 kind: "edge".to_string(),
@@ -422,6 +988,7 @@ mod tests {
                 attrs: parser::parse_attribute_string(r#"label="A to B""#),
                 range: (3, 3),
                 extra: Some("B".to_string()),
+                span: None,
             },
         ]
     }
@@ -471,7 +1038,11 @@ mod tests {
         apply_command(&mut chunks, &cmd).unwrap();
         assert_eq!(chunks.len(), 2);
         // Check that no NODE with id="A" exists (edges can still have id="A" as the "from" node)
-        assert!(!chunks.iter().any(|c| c.kind == "node" && c.id.as_deref() == Some("A")));
+        assert!(
+            !chunks
+                .iter()
+                .any(|c| c.kind == "node" && c.id.as_deref() == Some("A"))
+        );
     }
 
     #[test]
@@ -524,6 +1095,36 @@ mod tests {
         assert!(!chunks.iter().any(|c| c.kind == "edge"));
     }
 
+    #[test]
+    fn test_clear_attrs() {
+        let mut chunks = create_test_chunks();
+        let clear_node = DotCommand::ClearNodeAttrs {
+            id: "A".to_string(),
+        };
+        let clear_edge = DotCommand::ClearEdgeAttrs {
+            from: "A".to_string(),
+            to: "B".to_string(),
+        };
+        apply_command(&mut chunks, &clear_node).unwrap();
+        apply_command(&mut chunks, &clear_edge).unwrap();
+
+        let dot = parser::chunks_to_dot_nested(&chunks, None);
+        let lines: Vec<&str> = dot.lines().map(str::trim).collect();
+        assert!(lines.contains(&"A;"));
+        assert!(lines.contains(&"A -> B;"));
+        assert!(dot.contains("Node B"));
+
+        let missing_node = DotCommand::ClearNodeAttrs {
+            id: "missing".to_string(),
+        };
+        assert!(apply_command(&mut chunks, &missing_node).is_err());
+        let missing_edge = DotCommand::ClearEdgeAttrs {
+            from: "B".to_string(),
+            to: "A".to_string(),
+        };
+        assert!(apply_command(&mut chunks, &missing_edge).is_err());
+    }
+
     #[test]
     fn test_json_serialization() {
         let cmd = DotCommand::CreateNode {
@@ -542,4 +1143,319 @@ mod tests {
             _ => panic!("Wrong command type"),
         }
     }
+
+    #[test]
+    fn test_reorder_group_by_kind() {
+        let dot = r#"digraph G {
+    A -> B;
+    A [label="A"];
+    subgraph cluster_x {
+        C -> D;
+        C [label="C"];
+        D [label="D"];
+    }
+    B [label="B"];
+}"#;
+        let mut chunks = parser::parse_dot_to_chunks(dot).unwrap();
+        let cmd = DotCommand::Reorder {
+            strategy: OrderStrategy::GroupByKind,
+        };
+        apply_command(&mut chunks, &cmd).unwrap();
+
+        let cluster = chunks.iter().find(|c| c.kind == "subgraph").unwrap().range;
+        let inside = |c: &Chunk| c.range.0 > cluster.0 && c.range.1 < cluster.1;
+        let position = |kind: &str, id: &str| {
+            chunks
+                .iter()
+                .position(|c| c.kind == kind && c.id.as_deref() == Some(id))
+                .unwrap()
+        };
+
+        let (a, b, c, d) = (
+            position("node", "A"),
+            position("node", "B"),
+            position("node", "C"),
+            position("node", "D"),
+        );
+        let (ab, cd) = (position("edge", "A"), position("edge", "C"));
+        assert!(a < ab && b < ab);
+        assert!(c < cd && d < cd);
+
+        assert!(!inside(&chunks[a]) && !inside(&chunks[b]) && !inside(&chunks[ab]));
+        assert!(inside(&chunks[c]) && inside(&chunks[d]) && inside(&chunks[cd]));
+
+        let rendered = parser::chunks_to_dot_nested(&chunks, Some("G"));
+        let a_decl = rendered.find("A [").unwrap();
+        let a_edge = rendered.find("A -> B").unwrap();
+        assert!(a_decl < a_edge);
+    }
+
+    #[test]
+    fn test_set_layout() {
+        let mut chunks = parser::parse_dot_to_chunks("digraph G {\n    A -> B;\n}").unwrap();
+        let cmd = DotCommand::SetLayout {
+            rankdir: Some("LR".to_string()),
+            nodesep: Some(0.5),
+            ranksep: Some(1.0),
+            splines: Some("ortho".to_string()),
+        };
+        apply_command(&mut chunks, &cmd).unwrap();
+
+        let mut graph_attrs: Vec<(&str, &str)> = chunks
+            .iter()
+            .filter(|c| c.kind == "id_eq")
+            .map(|c| (c.id.as_deref().unwrap(), c.extra.as_deref().unwrap()))
+            .collect();
+        graph_attrs.sort();
+        assert_eq!(
+            graph_attrs,
+            vec![
+                ("nodesep", "0.5"),
+                ("rankdir", "LR"),
+                ("ranksep", "1"),
+                ("splines", "ortho"),
+            ]
+        );
+
+        let before = chunks.clone();
+        let cmd = DotCommand::SetLayout {
+            rankdir: Some("TB".to_string()),
+            nodesep: Some(-0.1),
+            ranksep: None,
+            splines: None,
+        };
+        assert!(apply_command(&mut chunks, &cmd).is_err());
+        assert_eq!(chunks, before);
+    }
+
+    #[test]
+    fn test_apply_commands_indexed_updates() {
+        let mut dot = String::from("digraph G {\n");
+        for i in 0..1000 {
+            dot.push_str(&format!("    n{i};\n    n{i} -> n{};\n", i + 1));
+        }
+        dot.push('}');
+        let original = parser::parse_dot_to_chunks(&dot).unwrap();
+
+        let mut commands = Vec::new();
+        for i in 0..1000 {
+            commands.push(DotCommand::UpdateNode {
+                id: format!("n{i}"),
+                attrs: Some(format!("label=\"Node {i}\"")),
+            });
+            if i % 100 == 0 {
+                // Structural changes in between force the index to be rebuilt.
+                commands.push(DotCommand::CreateNode {
+                    id: format!("extra{i}"),
+                    attrs: None,
+                    parent: None,
+                });
+                commands.push(DotCommand::UpdateEdge {
+                    from: format!("n{i}"),
+                    to: format!("n{}", i + 1),
+                    attrs: Some("color=red".to_string()),
+                });
+            }
+        }
+
+        let mut indexed = original.clone();
+        apply_commands(&mut indexed, &commands).unwrap();
+
+        let mut sequential = original;
+        for command in &commands {
+            apply_command(&mut sequential, command).unwrap();
+        }
+        assert_eq!(indexed, sequential);
+
+        let n999 = indexed
+            .iter()
+            .find(|c| c.kind == "node" && c.id.as_deref() == Some("n999"))
+            .unwrap();
+        assert_eq!(n999.attrs.get("label"), Some(&"Node 999".to_string()));
+        assert_eq!(indexed.iter().filter(|c| c.kind == "node").count(), 1010);
+    }
+
+    #[test]
+    fn test_apply_commands_atomic_rolls_back() {
+        let mut chunks = create_test_chunks();
+        let before = chunks.clone();
+        let commands = vec![
+            DotCommand::UpdateNode {
+                id: "A".to_string(),
+                attrs: Some("color=red".to_string()),
+            },
+            DotCommand::UpdateNode {
+                id: "missing".to_string(),
+                attrs: Some("color=red".to_string()),
+            },
+        ];
+
+        assert!(apply_commands_atomic(&mut chunks, &commands).is_err());
+        assert_eq!(chunks, before);
+
+        assert!(apply_commands(&mut chunks, &commands).is_err());
+        assert_eq!(chunks[0].attrs.get("color"), Some(&"red".to_string()));
+    }
+
+    #[test]
+    fn test_set_rank() {
+        let mut chunks = parser::parse_dot_to_chunks(
+            "digraph G {\n    subgraph cluster_x {\n        A;\n    }\n    B;\n    C;\n}",
+        )
+        .unwrap();
+        let cmd = DotCommand::SetRank {
+            kind: RankKind::Same,
+            nodes: vec!["A".to_string(), "B".to_string(), "C".to_string()],
+        };
+        apply_command(&mut chunks, &cmd).unwrap();
+
+        let dot = parser::chunks_to_dot_nested(&chunks, None);
+        let rank_line = dot.lines().find(|l| l.contains("rank=")).unwrap();
+        assert_eq!(rank_line.trim(), "{ rank=same; A; B; C }");
+        // Emitted after the cluster closes, at the top level.
+        assert!(dot.find("rank=").unwrap() > dot.find("    }").unwrap());
+
+        let mut chunks = parser::parse_dot_to_chunks(r#"digraph G { "a,b"; "c d"; }"#).unwrap();
+        let quoted = DotCommand::SetRank {
+            kind: RankKind::Min,
+            nodes: vec![r#""a,b""#.to_string(), r#""c d""#.to_string()],
+        };
+        apply_command(&mut chunks, &quoted).unwrap();
+        assert_eq!(
+            chunks.last().unwrap().to_dot().trim(),
+            r#"{ rank=min; "a,b"; "c d" }"#
+        );
+
+        let before = chunks.clone();
+        let unknown = DotCommand::SetRank {
+            kind: RankKind::Same,
+            nodes: vec![r#""a,b""#.to_string(), "missing".to_string()],
+        };
+        assert_eq!(
+            apply_command(&mut chunks, &unknown),
+            Err("Node 'missing' not found".to_string())
+        );
+        assert_eq!(chunks, before);
+
+        let empty = DotCommand::SetRank {
+            kind: RankKind::Sink,
+            nodes: vec![],
+        };
+        assert!(apply_command(&mut chunks, &empty).is_err());
+    }
+
+    #[test]
+    fn test_style_node() {
+        let mut chunks = create_test_chunks();
+        let cmd = DotCommand::StyleNode {
+            id: "A".to_string(),
+            fill: Some("red".to_string()),
+            shape: Some("doublecircle".to_string()),
+            filled: true,
+            peripheries: Some(2),
+        };
+        apply_command(&mut chunks, &cmd).unwrap();
+
+        let a = &chunks[0];
+        assert_eq!(a.attrs.get("style"), Some(&"filled".to_string()));
+        assert_eq!(a.attrs.get("fillcolor"), Some(&"red".to_string()));
+        assert_eq!(a.attrs.get("shape"), Some(&"doublecircle".to_string()));
+        assert_eq!(a.attrs.get("peripheries"), Some(&"2".to_string()));
+
+        let dot = parser::chunks_to_dot_nested(&chunks, None);
+        assert!(dot.contains("style=filled"));
+        assert!(dot.contains("fillcolor=red"));
+
+        let before = chunks.clone();
+        for (fill, shape) in [(Some("#12345"), None), (None, Some("blob"))] {
+            let cmd = DotCommand::StyleNode {
+                id: "A".to_string(),
+                fill: fill.map(str::to_string),
+                shape: shape.map(str::to_string),
+                filled: false,
+                peripheries: None,
+            };
+            assert!(apply_command(&mut chunks, &cmd).is_err());
+        }
+        assert_eq!(chunks, before);
+
+        let missing = DotCommand::StyleNode {
+            id: "missing".to_string(),
+            fill: None,
+            shape: None,
+            filled: true,
+            peripheries: None,
+        };
+        assert!(apply_command(&mut chunks, &missing).is_err());
+    }
+
+    #[test]
+    fn test_edges_match_by_base_id() {
+        let mut chunks =
+            parser::parse_dot_to_chunks("digraph G {\n    A : p1 : n -> B;\n    C -> D:e;\n}")
+                .unwrap();
+        assert_eq!(chunks[0].id.as_deref(), Some("A:p1:n"));
+
+        // The exact port wins; a bare id falls back to the base.
+        let update = DotCommand::UpdateEdge {
+            from: "A:p1:n".to_string(),
+            to: "B".to_string(),
+            attrs: Some("color=red".to_string()),
+        };
+        apply_command(&mut chunks, &update).unwrap();
+        assert_eq!(chunks[0].attrs.get("color"), Some(&"red".to_string()));
+        let other_port = DotCommand::DeleteEdge {
+            from: "A:p2".to_string(),
+            to: "B".to_string(),
+        };
+        assert!(apply_command(&mut chunks, &other_port).is_err());
+
+        let dot = parser::chunks_to_dot_nested(&chunks, None);
+        assert!(dot.contains("A:p1:n -> B"));
+        assert!(dot.contains("C -> D:e"));
+
+        let delete = DotCommand::DeleteEdge {
+            from: "C".to_string(),
+            to: "D".to_string(),
+        };
+        apply_command(&mut chunks, &delete).unwrap();
+        assert_eq!(chunks.iter().filter(|c| c.kind == "edge").count(), 1);
+    }
+
+    #[test]
+    fn test_create_subgraph_adds_cluster_prefix() {
+        let mut chunks = parser::parse_dot_to_chunks("digraph G {\n    A;\n}").unwrap();
+        let cmd = DotCommand::CreateSubgraph {
+            id: Some("Backend".to_string()),
+            parent: None,
+        };
+        apply_command(&mut chunks, &cmd).unwrap();
+        // Already prefixed, so this is a duplicate.
+        let again = DotCommand::CreateSubgraph {
+            id: Some("cluster_Backend".to_string()),
+            parent: None,
+        };
+        assert!(apply_command(&mut chunks, &again).is_err());
+
+        // The unprefixed name still works as a parent.
+        let node = DotCommand::CreateNode {
+            id: "Api".to_string(),
+            attrs: None,
+            parent: Some("Backend".to_string()),
+        };
+        apply_command(&mut chunks, &node).unwrap();
+
+        let dot = parser::chunks_to_dot_nested(&chunks, Some("G"));
+        assert!(dot.contains("subgraph cluster_Backend {"));
+        assert!(!dot.contains("subgraph Backend"));
+        let api = dot.find("Api").unwrap();
+        assert!(dot.find("cluster_Backend").unwrap() < api);
+        assert!(api < dot.rfind("    }").unwrap());
+
+        let delete = DotCommand::DeleteSubgraph {
+            id: "Backend".to_string(),
+        };
+        apply_command(&mut chunks, &delete).unwrap();
+        assert!(!chunks.iter().any(|c| c.kind == "subgraph"));
+    }
 }
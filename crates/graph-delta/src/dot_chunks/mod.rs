@@ -1,2 +1,6 @@
+pub mod analysis;
+pub mod color;
 pub mod commands;
 pub mod parser;
+pub mod patch;
+pub mod port;
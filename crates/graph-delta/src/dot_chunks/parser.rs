@@ -1,4 +1,5 @@
 //! This module provides functionality to parse DOT files into structured chunks
+use crate::port;
 use pest::Parser;
 use pest::iterators::Pair;
 use pest_derive::Parser;
@@ -33,27 +34,149 @@ pub struct Chunk {
     pub range: (usize, usize),
     /// Extra info, e.g., for edges, the target node. For id_eq, the value.
     pub extra: Option<String>,
+    /// Byte range of the statement in the DOT it was parsed from. Statements on one line nest by
+    /// it; chunks created or laid out since have none.
+    #[serde(skip)]
+    pub span: Option<(usize, usize)>,
 }
 
 /// Formats a HashMap of attributes into a DOT attribute string.
 fn format_dot_attributes(attrs: &HashMap<String, String>) -> String {
     attrs
         .iter()
-        .map(|(k, v)| {
-            // Per DOT language spec, identifiers that are not simple alphanumeric
-            // must be quoted. HTML-like labels start with '<' and must not be quoted.
-            if v.starts_with('<') && v.ends_with('>') {
-                format!("{}={}", k, v)
-            } else if v.chars().any(|c| !c.is_alphanumeric()) || v.is_empty() {
-                format!(r#"{}="{}""#, k, v.replace('"', r#"\""#))
-            } else {
-                format!("{}={}", k, v)
-            }
-        })
+        .map(|(k, v)| format!("{}={}", k, format_dot_value(v)))
         .collect::<Vec<String>>()
         .join(", ")
 }
 
+/// Renders an attribute value as a DOT id: numerals (`1.5`, `-.5`) and simple ids (`box`,
+/// `node_1`) stay bare, HTML-like labels `<...>` pass through, and everything else is quoted.
+fn format_dot_value(value: &str) -> String {
+    if is_html_value(value)
+        || is_dot_numeral(value)
+        || is_simple_dot_id(value)
+        || is_quoted_dot_string(value)
+    {
+        value.to_string()
+    } else {
+        quote_dot_string(value)
+    }
+}
+
+/// One `<...>` block spanning the whole value. Record labels like `<f0> left|<f1> right` or
+/// `<f0>|<f1>` open and close several port fields, so they are not mistaken for HTML.
+fn is_html_value(value: &str) -> bool {
+    if !value.starts_with('<') {
+        return false;
+    }
+    let mut depth = 0usize;
+    for (i, c) in value.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => {
+                depth = match depth.checked_sub(1) {
+                    Some(depth) => depth,
+                    None => return false,
+                };
+                if depth == 0 {
+                    return i == value.len() - 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// `[-]?(.[0-9]+ | [0-9]+(.[0-9]*)?)`
+fn is_dot_numeral(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let (int, frac) = match digits.split_once('.') {
+        Some((int, frac)) => (int, Some(frac)),
+        None => (digits, None),
+    };
+    let all_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    match frac {
+        None => !int.is_empty() && all_digits(int),
+        Some(frac) => all_digits(int) && all_digits(frac) && !(int.is_empty() && frac.is_empty()),
+    }
+}
+
+/// `[A-Za-z_][A-Za-z0-9_]*`, excluding the (case-insensitive) DOT keywords.
+fn is_simple_dot_id(s: &str) -> bool {
+    const KEYWORDS: &[&str] = &["node", "edge", "graph", "digraph", "subgraph", "strict"];
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !KEYWORDS.iter().any(|k| k.eq_ignore_ascii_case(s))
+}
+
+/// A complete quoted string already: `"` at both ends and every inner `"` escaped.
+fn is_quoted_dot_string(s: &str) -> bool {
+    let Some(inner) = s.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) else {
+        return false;
+    };
+    let mut escaped = false;
+    for c in inner.chars() {
+        match c {
+            '"' if !escaped => return false,
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    !escaped
+}
+
+/// Quotes `s`, escaping `"`. Backslash escapes are written as they are, so `line\nbreak` is still
+/// a line break and `C:\\new` keeps its escaped backslash; a backslash that would escape a quote
+/// or the closing quote is doubled.
+fn quote_dot_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => match chars.peek() {
+                Some(&next) if next != '"' => {
+                    out.push('\\');
+                    out.push(next);
+                    chars.next();
+                }
+                _ => out.push_str("\\\\"),
+            },
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The value of a quoted DOT string, without the quotes: `\"` loses its backslash, other escapes
+/// are kept as written for Graphviz and [quote_dot_string].
+fn unquote_dot_string(s: &str) -> String {
+    let inner = &s[1..s.len() - 1];
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => out.push('"'),
+            Some(next) => {
+                out.push('\\');
+                out.push(next);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
 /// Parses a string of DOT attributes into a HashMap.
 pub fn parse_attribute_string(s: &str) -> HashMap<String, String> {
     match DotParser::parse(Rule::a_list, s) {
@@ -63,8 +186,15 @@ pub fn parse_attribute_string(s: &str) -> HashMap<String, String> {
 }
 
 impl Chunk {
-    /// Render this chunk back to DOT format
+    /// Render this chunk back to DOT format. Nodes and edges without attributes are written
+    /// without brackets, so `A [];` and `A;` both come back as `A;`.
     pub fn to_dot(&self) -> String {
+        self.to_dot_as(true)
+    }
+
+    /// Like [Chunk::to_dot], with edges written `--` unless the graph is `directed`.
+    fn to_dot_as(&self, directed: bool) -> String {
+        let edge_op = if directed { "->" } else { "--" };
         let attrs_str = format_dot_attributes(&self.attrs);
         match self.kind.as_str() {
             "node" => {
@@ -83,18 +213,15 @@ impl Chunk {
                 let from = self.id.as_deref().unwrap_or("unknown");
                 let to = self.extra.as_deref().unwrap_or("unknown");
                 if !self.attrs.is_empty() {
-                    format!("    {} -> {} [{}];", from, to, attrs_str)
+                    format!("    {} {} {} [{}];", from, edge_op, to, attrs_str)
                 } else {
-                    format!("    {} -> {};", from, to)
+                    format!("    {} {} {};", from, edge_op, to)
                 }
             }
             "attr_stmt" => {
+                // Unlike nodes, `graph`/`node`/`edge` need their brackets even when empty.
                 let stmt_type = self.id.as_deref().unwrap_or("graph");
-                if !self.attrs.is_empty() {
-                    format!("    {} [{}];", stmt_type, attrs_str)
-                } else {
-                    format!("    {};", stmt_type)
-                }
+                format!("    {} [{}];", stmt_type, attrs_str)
             }
             "id_eq" => {
                 let key = self.id.as_deref().unwrap_or("unknown");
@@ -111,12 +238,9 @@ impl Chunk {
             "rank" => {
                 let rank_type = self.id.as_deref().unwrap_or("same");
                 let nodes = self
-                    .attrs
-                    .get("nodes")
-                    .cloned()
-                    .unwrap_or_default()
-                    .split(',')
-                    .map(|s| format!("\"{}\"", s))
+                    .rank_members()
+                    .iter()
+                    .map(|id| format_dot_value(id))
                     .collect::<Vec<_>>()
                     .join("; ");
                 format!("    {{ rank={}; {} }}", rank_type, nodes)
@@ -124,6 +248,21 @@ impl Chunk {
             _ => format!("    // Unknown chunk type: {}", self.kind),
         }
     }
+
+    /// Node ids of a `rank` chunk, kept as a JSON array in its `nodes` attribute so that ids
+    /// containing commas survive. Older chunks stored them comma-separated.
+    pub fn rank_members(&self) -> Vec<String> {
+        let Some(nodes) = self.attrs.get("nodes") else {
+            return Vec::new();
+        };
+        serde_json::from_str(nodes)
+            .unwrap_or_else(|_| nodes.split(',').map(str::to_string).collect())
+    }
+
+    pub fn set_rank_members(&mut self, members: &[String]) {
+        let nodes = serde_json::to_string(members).expect("strings serialize");
+        self.attrs.insert("nodes".to_string(), nodes);
+    }
 }
 
 fn span_to_line_range(dot: &str, start: usize, end: usize) -> (usize, usize) {
@@ -141,8 +280,8 @@ fn parse_dot_attributes(pair: Pair<Rule>) -> HashMap<String, String> {
             let key = inner.next().unwrap().as_str().to_string();
             let mut value = inner.next().unwrap().as_str().to_string();
             // Unquote the value if it's a quoted string
-            if value.starts_with('"') && value.ends_with('"') {
-                value = value[1..value.len() - 1].replace(r#"\""#, r#"""#);
+            if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                value = unquote_dot_string(&value);
             }
             attrs.insert(key, value);
         }
@@ -150,168 +289,273 @@ fn parse_dot_attributes(pair: Pair<Rule>) -> HashMap<String, String> {
     attrs
 }
 
-pub fn parse_dot_to_chunks(dot: &str) -> Result<Vec<Chunk>, Error> {
-    let mut chunks = Vec::new();
-
-    let file = DotParser::parse(Rule::dotfile, dot)?
+/// Attributes of one `[...]` list; an empty `[]` has none.
+fn attr_list_attributes(pair: Pair<Rule>) -> HashMap<String, String> {
+    pair.into_inner()
         .next()
-        .ok_or_else(|| {
-            Error::ParseError("Failed to parse DOT file: no dotfile rule found".to_string())
-        })?;
+        .map(parse_dot_attributes)
+        .unwrap_or_default()
+}
 
-    fn walk(pair: Pair<Rule>, dot: &str, chunks: &mut Vec<Chunk>) {
-        match pair.as_rule() {
-            Rule::node_stmt => {
-                let span = pair.as_span();
-                let (start_line, end_line) = span_to_line_range(dot, span.start(), span.end());
-
-                let mut inner = pair.into_inner();
-                let node_id_pair = inner.next().unwrap();
-                let id = node_id_pair
-                    .into_inner()
-                    .next()
-                    .unwrap()
-                    .as_str()
-                    .to_string();
+/// Attributes of every `[...]` list in `pairs`, later lists overriding earlier ones as in
+/// Graphviz (`A [] [color=red]` is `A [color=red]`).
+fn merged_attr_lists<'a>(pairs: impl Iterator<Item = Pair<'a, Rule>>) -> HashMap<String, String> {
+    pairs
+        .filter(|p| p.as_rule() == Rule::attr_list)
+        .flat_map(attr_list_attributes)
+        .collect()
+}
+
+/// The id an edge endpoint is stored under; node ids get their port in canonical form.
+fn endpoint_id(pair: Pair<Rule>) -> String {
+    match pair.as_rule() {
+        Rule::node_id => port::normalize_port(pair.as_str()),
+        _ => pair.as_str().trim().to_string(),
+    }
+}
 
-                let attrs = inner
-                    .next()
-                    .and_then(|p| p.into_inner().next().map(parse_dot_attributes))
-                    .unwrap_or_default();
+/// Collects chunks from every statement under `pair`, recursing into subgraphs.
+fn walk(pair: Pair<Rule>, dot: &str, chunks: &mut Vec<Chunk>) {
+    match pair.as_rule() {
+        Rule::node_stmt => {
+            let span = pair.as_span();
+            let (start_line, end_line) = span_to_line_range(dot, span.start(), span.end());
+
+            let mut inner = pair.into_inner();
+            let node_id_pair = inner.next().unwrap();
+            let id = node_id_pair
+                .into_inner()
+                .next()
+                .unwrap()
+                .as_str()
+                .to_string();
+
+            let attrs = merged_attr_lists(inner);
+
+            chunks.push(Chunk {
+                kind: "node".to_string(),
+                id: Some(id),
+                attrs,
+                range: (start_line, end_line),
+                extra: None,
+                span: Some((span.start(), span.end())),
+            });
+        }
 
-                chunks.push(Chunk {
-                    kind: "node".to_string(),
-                    id: Some(id),
-                    attrs,
-                    range: (start_line, end_line),
-                    extra: None,
-                });
-            }
+        Rule::edge_stmt => {
+            let span = pair.as_span();
+            let (start_line, end_line) = span_to_line_range(dot, span.start(), span.end());
 
-            Rule::edge_stmt => {
-                let span = pair.as_span();
-                let (start_line, end_line) = span_to_line_range(dot, span.start(), span.end());
-
-                let mut inner = pair.into_inner();
-                let from_pair = inner.next().unwrap();
-                let from = from_pair.as_str().trim().to_string();
-
-                let mut targets = Vec::new();
-                let mut attrs = HashMap::new();
-                for p in inner {
-                    match p.as_rule() {
-                        Rule::edge_rhs => {
-                            let target = p.into_inner().next().unwrap();
-                            targets.push(target.as_str().trim().to_string());
-                        }
-                        Rule::attr_list => {
-                            attrs = p
-                                .into_inner()
-                                .next()
-                                .map(parse_dot_attributes)
-                                .unwrap_or_default();
-                        }
-                        _ => {}
-                    }
-                }
+            let mut inner = pair.into_inner();
+            let from = endpoint_id(inner.next().unwrap());
 
-                if let Some(to) = targets.first() {
-                    chunks.push(Chunk {
-                        kind: "edge".to_string(),
-                        id: Some(from),
-                        extra: Some(to.clone()),
-                        attrs: attrs.clone(),
-                        range: (start_line, end_line),
-                    });
-                }
-                for i in 1..targets.len() {
-                    chunks.push(Chunk {
-                        kind: "edge".to_string(),
-                        id: Some(targets[i - 1].clone()),
-                        extra: Some(targets[i].clone()),
-                        attrs: attrs.clone(),
-                        range: (start_line, end_line),
-                    });
+            let mut targets = Vec::new();
+            let mut attrs = HashMap::new();
+            for p in inner {
+                match p.as_rule() {
+                    Rule::edge_rhs => {
+                        targets.push(endpoint_id(p.into_inner().next().unwrap()));
+                    }
+                    Rule::attr_list => attrs.extend(attr_list_attributes(p)),
+                    _ => {}
                 }
             }
 
-            Rule::subgraph => {
-                let span = pair.as_span();
-                let (start_line, end_line) = span_to_line_range(dot, span.start(), span.end());
-
-                let mut inner = pair.clone().into_inner();
-                let id = inner
-                    .find(|p| p.as_rule() == Rule::ident)
-                    .map(|p| p.as_str().to_string());
-
-                // Subgraphs can have attributes applied via an `attr_stmt` inside them,
-                // but we will handle this via the interpreter applying updates.
-                // Here we just create the subgraph chunk.
-
+            if let Some(to) = targets.first() {
+                chunks.push(Chunk {
+                    kind: "edge".to_string(),
+                    id: Some(from),
+                    extra: Some(to.clone()),
+                    attrs: attrs.clone(),
+                    range: (start_line, end_line),
+                    span: Some((span.start(), span.end())),
+                });
+            }
+            for i in 1..targets.len() {
                 chunks.push(Chunk {
-                    kind: "subgraph".to_string(),
-                    id,
-                    attrs: HashMap::new(), // Placeholder, to be populated by interpreter if needed
+                    kind: "edge".to_string(),
+                    id: Some(targets[i - 1].clone()),
+                    extra: Some(targets[i].clone()),
+                    attrs: attrs.clone(),
                     range: (start_line, end_line),
-                    extra: None,
+                    span: Some((span.start(), span.end())),
                 });
+            }
+        }
 
-                for inner_pair in pair.into_inner() {
-                    if inner_pair.as_rule() == Rule::stmt_list {
-                        for stmt in inner_pair.into_inner() {
-                            walk(stmt, dot, chunks);
-                        }
+        Rule::subgraph => {
+            let span = pair.as_span();
+            let (start_line, end_line) = span_to_line_range(dot, span.start(), span.end());
+
+            let mut inner = pair.clone().into_inner();
+            let id = inner
+                .find(|p| p.as_rule() == Rule::ident)
+                .map(|p| p.as_str().to_string());
+
+            // Subgraphs can have attributes applied via an `attr_stmt` inside them,
+            // but we will handle this via the interpreter applying updates.
+            // Here we just create the subgraph chunk.
+
+            chunks.push(Chunk {
+                kind: "subgraph".to_string(),
+                id,
+                attrs: HashMap::new(), // Placeholder, to be populated by interpreter if needed
+                range: (start_line, end_line),
+                extra: None,
+                span: Some((span.start(), span.end())),
+            });
+
+            for inner_pair in pair.into_inner() {
+                if inner_pair.as_rule() == Rule::stmt_list {
+                    for stmt in inner_pair.into_inner() {
+                        walk(stmt, dot, chunks);
                     }
                 }
             }
+        }
 
-            Rule::id_eq => {
-                let span = pair.as_span();
-                let (start_line, end_line) = span_to_line_range(dot, span.start(), span.end());
+        Rule::id_eq => {
+            let span = pair.as_span();
+            let (start_line, end_line) = span_to_line_range(dot, span.start(), span.end());
+
+            let mut inner = pair.into_inner();
+            let key = inner.next().map(|p| p.as_str().trim().to_string());
+            let value = inner.next().map(|p| p.as_str().trim().to_string());
+
+            chunks.push(Chunk {
+                kind: "id_eq".to_string(),
+                id: key,
+                attrs: HashMap::new(),
+                range: (start_line, end_line),
+                extra: value,
+                span: Some((span.start(), span.end())),
+            });
+        }
 
-                let mut inner = pair.into_inner();
-                let key = inner.next().map(|p| p.as_str().trim().to_string());
-                let value = inner.next().map(|p| p.as_str().trim().to_string());
+        Rule::attr_stmt => {
+            let span = pair.as_span();
+            let (start_line, end_line) = span_to_line_range(dot, span.start(), span.end());
+
+            let mut inner = pair.into_inner();
+            let stmt_type = inner.next().map(|p| p.as_str().trim().to_string());
+            let attrs = merged_attr_lists(inner);
+
+            chunks.push(Chunk {
+                kind: "attr_stmt".to_string(),
+                id: stmt_type,
+                attrs,
+                range: (start_line, end_line),
+                extra: None,
+                span: Some((span.start(), span.end())),
+            });
+        }
 
-                chunks.push(Chunk {
-                    kind: "id_eq".to_string(),
-                    id: key,
-                    attrs: HashMap::new(),
-                    range: (start_line, end_line),
-                    extra: value,
-                });
+        _ => {
+            for inner in pair.into_inner() {
+                walk(inner, dot, chunks);
             }
+        }
+    }
+}
 
-            Rule::attr_stmt => {
-                let span = pair.as_span();
-                let (start_line, end_line) = span_to_line_range(dot, span.start(), span.end());
+pub fn parse_dot_to_chunks(dot: &str) -> Result<Vec<Chunk>, Error> {
+    let mut chunks = Vec::new();
 
-                let mut inner = pair.into_inner();
-                let stmt_type = inner.next().map(|p| p.as_str().trim().to_string());
-                let attrs = inner
-                    .next()
-                    .and_then(|p| p.into_inner().next().map(parse_dot_attributes))
-                    .unwrap_or_default();
+    let file = DotParser::parse(Rule::dotfile, dot)?
+        .next()
+        .ok_or_else(|| {
+            Error::ParseError("Failed to parse DOT file: no dotfile rule found".to_string())
+        })?;
 
-                chunks.push(Chunk {
-                    kind: "attr_stmt".to_string(),
-                    id: stmt_type,
-                    attrs,
-                    range: (start_line, end_line),
-                    extra: None,
-                });
-            }
+    walk(file, dot, &mut chunks);
+    Ok(chunks)
+}
 
-            _ => {
-                for inner in pair.into_inner() {
-                    walk(inner, dot, chunks);
-                }
+/// One top-level `graph`/`digraph` block of a DOT file.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GraphDocument {
+    /// Graph name as written (quotes included), if any
+    pub name: Option<String>,
+    /// `digraph` rather than `graph`
+    pub directed: bool,
+    /// Declared `strict`
+    pub strict: bool,
+    /// Chunks of this graph only; line ranges are relative to the whole file
+    pub chunks: Vec<Chunk>,
+}
+
+/// Parses every top-level graph in `dot` into its own [GraphDocument], in file order.
+pub fn parse_all_graphs(dot: &str) -> Result<Vec<GraphDocument>, Error> {
+    let file = DotParser::parse(Rule::dotfile, dot)?
+        .next()
+        .ok_or_else(|| {
+            Error::ParseError("Failed to parse DOT file: no dotfile rule found".to_string())
+        })?;
+
+    let mut documents = Vec::new();
+    for graph in file.into_inner().filter(|p| p.as_rule() == Rule::dotgraph) {
+        let mut document = GraphDocument {
+            name: None,
+            directed: false,
+            strict: false,
+            chunks: Vec::new(),
+        };
+        for part in graph.into_inner() {
+            match part.as_rule() {
+                Rule::strict => document.strict = true,
+                Rule::digraph => document.directed = true,
+                Rule::ident => document.name = Some(part.as_str().to_string()),
+                Rule::stmt_list => walk(part, dot, &mut document.chunks),
+                _ => {}
             }
         }
+        documents.push(document);
     }
+    Ok(documents)
+}
 
-    walk(file, dot, &mut chunks);
-    Ok(chunks)
+/// Attributes Graphviz writes back when it lays a graph out (`dot -Tdot`, `-Txdot`). They describe
+/// one particular layout and go stale as soon as the graph is edited.
+pub const LAYOUT_ATTRS: &[&str] = &[
+    "pos",
+    "width",
+    "height",
+    "bb",
+    "lp",
+    "xlp",
+    "head_lp",
+    "tail_lp",
+    "lwidth",
+    "lheight",
+    "rects",
+    "vertices",
+    "_draw_",
+    "_ldraw_",
+    "_hdraw_",
+    "_tdraw_",
+    "_hldraw_",
+    "_tldraw_",
+    "xdotversion",
+];
+
+/// Removes every [LAYOUT_ATTRS] key from node, edge and attribute-statement chunks, and drops
+/// `key = value` statements that set one. Attribute statements left with nothing to say
+/// (e.g. a lone `graph [bb="..."]`) are dropped too.
+pub fn strip_layout_attrs(chunks: &mut Vec<Chunk>) {
+    chunks.retain_mut(|chunk| match chunk.kind.as_str() {
+        "id_eq" => !chunk
+            .id
+            .as_deref()
+            .is_some_and(|key| LAYOUT_ATTRS.contains(&key)),
+        "node" | "edge" | "attr_stmt" => {
+            let had_attrs = !chunk.attrs.is_empty();
+            chunk
+                .attrs
+                .retain(|key, _| !LAYOUT_ATTRS.contains(&key.as_str()));
+            !(chunk.kind == "attr_stmt" && had_attrs && chunk.attrs.is_empty())
+        }
+        _ => true,
+    });
 }
 
 pub fn chunks_to_dot(chunks: &[Chunk]) -> String {
@@ -350,10 +594,129 @@ pub fn chunks_to_complete_dot(chunks: &[Chunk], graph_name: Option<&str>) -> Str
     chunks_to_dot_nested(chunks, graph_name)
 }
 
+/// One level of indentation in emitted DOT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indent {
+    Spaces(usize),
+    Tab,
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Indent::Spaces(4)
+    }
+}
+
+impl Indent {
+    fn repeat(&self, level: usize) -> String {
+        match self {
+            Indent::Spaces(width) => " ".repeat(width * level),
+            Indent::Tab => "\t".repeat(level),
+        }
+    }
+}
+
+/// Options for [chunks_to_dot_with_options].
+#[derive(Debug, Clone, Default)]
+pub struct DotOptions {
+    /// Rename every subgraph to [stable_cluster_id] of its label, so ids don't drift between
+    /// reconstructions or between graph-delta and graph-assistant output.
+    pub stable_cluster_ids: bool,
+    /// Indentation per nesting level, four spaces by default.
+    pub indent: Indent,
+    /// Write an undirected `graph` with `--` edges instead of a `digraph`.
+    pub undirected: bool,
+}
+
+/// Deterministic cluster id for a subgraph label: `cluster_` followed by the 8 hex digit FNV-1a
+/// hash of the label. The same label always maps to the same id, on any machine or run.
+pub fn stable_cluster_id(label: &str) -> String {
+    let hash = label.bytes().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    });
+    format!("cluster_{:08x}", hash)
+}
+
+fn is_stable_cluster_id(id: &str) -> bool {
+    id.strip_prefix("cluster_")
+        .is_some_and(|hash| hash.len() == 8 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Id to render for `subgraph` in stable mode. The label is the subgraph's `label` attribute or
+/// its own `label = ...` statement (not a nested subgraph's), else its unquoted id without the
+/// `cluster_` prefix. Ids already in stable form are kept, so reconstructing again is a no-op.
+fn stable_subgraph_id(subgraph: &Chunk, chunks: &[Chunk]) -> String {
+    let inside = |outer: (usize, usize), inner: (usize, usize)| {
+        outer != inner && outer.0 <= inner.0 && inner.1 <= outer.1
+    };
+    let own_label = chunks
+        .iter()
+        .filter(|c| c.kind == "id_eq" && c.id.as_deref() == Some("label"))
+        .filter(|c| inside(subgraph.range, c.range))
+        .find(|c| {
+            !chunks.iter().any(|nested| {
+                nested.kind == "subgraph"
+                    && inside(subgraph.range, nested.range)
+                    && inside(nested.range, c.range)
+            })
+        })
+        .and_then(|c| c.extra.as_deref());
+
+    let id = subgraph.id.as_deref().unwrap_or("").trim_matches('"');
+    let label = subgraph
+        .attrs
+        .get("label")
+        .map(String::as_str)
+        .or(own_label);
+    match label {
+        Some(label) => stable_cluster_id(label.trim_matches('"')),
+        None if is_stable_cluster_id(id) => id.to_string(),
+        None => stable_cluster_id(id.strip_prefix("cluster_").unwrap_or(id)),
+    }
+}
+
 pub fn chunks_to_dot_nested(chunks: &[Chunk], graph_name: Option<&str>) -> String {
+    chunks_to_dot_with_options(chunks, graph_name, &DotOptions::default())
+}
+
+pub fn chunks_to_dot_with_options(
+    chunks: &[Chunk],
+    graph_name: Option<&str>,
+    options: &DotOptions,
+) -> String {
+    render_dot(chunks, graph_name, options, false)
+}
+
+/// Single-line DOT for sending over the wire: no newlines or indentation (`options.indent` is
+/// ignored). Re-parsing puts every chunk on line 1, so the chunks come back with the same
+/// content but without the line ranges that encode subgraph nesting.
+pub fn chunks_to_dot_min(chunks: &[Chunk], options: &DotOptions) -> String {
+    render_dot(chunks, None, options, true)
+}
+
+fn render_dot(
+    chunks: &[Chunk],
+    graph_name: Option<&str>,
+    options: &DotOptions,
+    minify: bool,
+) -> String {
+    let newline = if minify { "" } else { "\n" };
+    let indent = |level: usize| {
+        if minify {
+            String::new()
+        } else {
+            options.indent.repeat(level)
+        }
+    };
+
     let mut output = String::new();
     let name = graph_name.unwrap_or("G");
-    output.push_str(&format!("digraph {} {{\n", name));
+    let graph_type = if options.undirected {
+        "graph"
+    } else {
+        "digraph"
+    };
+    output.push_str(&format!("{} {} {{{}", graph_type, name, newline));
 
     let mut sorted_chunks = chunks.to_vec();
     sorted_chunks.sort_by_key(|c| c.range.0);
@@ -364,42 +727,64 @@ pub fn chunks_to_dot_nested(chunks: &[Chunk], graph_name: Option<&str>) -> Strin
         while let Some((_, _, end)) = subgraph_stack.last() {
             if chunk.range.0 > *end && *end != 0 {
                 subgraph_stack.pop();
-                let indent = "    ".repeat(subgraph_stack.len());
-                output.push_str(&format!("{}}}}}\n", indent));
+                let indent = indent(subgraph_stack.len() + 1);
+                output.push_str(&format!("{}}}{}", indent, newline));
             } else {
                 break;
             }
         }
 
-        let indent = "    ".repeat(subgraph_stack.len() + 1);
+        let indent = indent(subgraph_stack.len() + 1);
 
         match chunk.kind.as_str() {
             "subgraph" => {
-                let id_str = chunk.id.as_deref().unwrap_or("");
+                let stable_id;
+                let id_str = if options.stable_cluster_ids {
+                    stable_id = stable_subgraph_id(chunk, &sorted_chunks);
+                    stable_id.as_str()
+                } else {
+                    chunk.id.as_deref().unwrap_or("")
+                };
                 let attrs_str = format_dot_attributes(&chunk.attrs);
-                output.push_str(&format!("{}subgraph {} {{\n", indent, id_str));
+                output.push_str(&format!("{}subgraph {} {{{}", indent, id_str, newline));
                 if !attrs_str.is_empty() {
-                    output.push_str(&format!("{}    graph [{}];\n", indent, attrs_str));
+                    let inner = if minify {
+                        ""
+                    } else {
+                        &options.indent.repeat(1)
+                    };
+                    output.push_str(&format!(
+                        "{}{}graph [{}];{}",
+                        indent, inner, attrs_str, newline
+                    ));
                 }
                 subgraph_stack.push((id_str.to_string(), chunk.range.0, chunk.range.1));
             }
-            "rank" => {
+            "rank" if !minify => {
                 output.push_str(&format!("{}{}\n", indent, chunk.to_dot()));
             }
             _ => {
-                let chunk_str = chunk.to_dot().trim_start().to_string();
-                output.push_str(&format!("{}{}\n", indent, chunk_str));
+                let chunk_str = chunk
+                    .to_dot_as(!options.undirected)
+                    .trim_start()
+                    .to_string();
+                // A `//` comment would swallow the rest of a single-line graph.
+                if minify && chunk_str.starts_with("//") {
+                    continue;
+                }
+                output.push_str(&format!("{}{}{}", indent, chunk_str, newline));
             }
         }
     }
 
     while !subgraph_stack.is_empty() {
         subgraph_stack.pop();
-        let indent = "    ".repeat(subgraph_stack.len());
-        output.push_str(&format!("{}}}}}\n", indent));
+        let indent = indent(subgraph_stack.len() + 1);
+        output.push_str(&format!("{}}}{}", indent, newline));
     }
 
-    output.push_str("}\n");
+    output.push('}');
+    output.push_str(newline);
     output
 }
 
@@ -529,4 +914,301 @@ mod tests {
             Some(&"Outer Cluster".to_string())
         );
     }
+
+    #[test]
+    fn test_parse_all_graphs() {
+        let dot = r#"digraph First {
+    A -> B;
+}
+
+strict graph {
+    C -- D;
+    D [label="D"];
+}"#;
+
+        let documents = parse_all_graphs(dot).unwrap();
+        assert_eq!(documents.len(), 2);
+
+        assert_eq!(documents[0].name.as_deref(), Some("First"));
+        assert!(documents[0].directed && !documents[0].strict);
+        assert_eq!(documents[0].chunks.len(), 1);
+
+        assert_eq!(documents[1].name, None);
+        assert!(!documents[1].directed && documents[1].strict);
+        assert_eq!(documents[1].chunks.len(), 2);
+    }
+
+    #[test]
+    fn test_stable_cluster_ids() {
+        let dot = r#"digraph G {
+    subgraph cluster_0 {
+        label = "Backend";
+        A;
+    }
+    subgraph cluster_1 {
+        B;
+    }
+}"#;
+        let options = DotOptions {
+            stable_cluster_ids: true,
+            ..Default::default()
+        };
+        let chunks = parse_dot_to_chunks(dot).unwrap();
+        let first = chunks_to_dot_with_options(&chunks, Some("G"), &options);
+        assert!(first.contains(&format!("subgraph {} {{", stable_cluster_id("Backend"))));
+        assert!(first.contains(&format!("subgraph {} {{", stable_cluster_id("1"))));
+
+        // Reconstructing the reconstruction keeps the same ids.
+        let reparsed = parse_dot_to_chunks(&first).unwrap();
+        let second = chunks_to_dot_with_options(&reparsed, Some("G"), &options);
+        assert_eq!(first, second);
+
+        assert_eq!(stable_cluster_id("Backend"), stable_cluster_id("Backend"));
+        assert_ne!(stable_cluster_id("Backend"), stable_cluster_id("Frontend"));
+    }
+
+    #[test]
+    fn test_indent_option() {
+        let dot = r#"digraph G {
+    A;
+    subgraph cluster_x {
+        B;
+    }
+}"#;
+        let chunks = parse_dot_to_chunks(dot).unwrap();
+        let options = DotOptions {
+            indent: Indent::Spaces(2),
+            ..Default::default()
+        };
+        let output = chunks_to_dot_with_options(&chunks, Some("G"), &options);
+        assert_eq!(
+            output,
+            "digraph G {\n  A;\n  subgraph cluster_x {\n    B;\n  }\n}\n"
+        );
+
+        let options = DotOptions {
+            indent: Indent::Tab,
+            ..Default::default()
+        };
+        let output = chunks_to_dot_with_options(&chunks, Some("G"), &options);
+        assert!(output.contains("\n\tsubgraph cluster_x {\n\t\tB;\n\t}\n"));
+    }
+
+    #[test]
+    fn test_undirected_option() {
+        let chunks =
+            parse_dot_to_chunks(r#"graph G { "a b":n -- C [color=red]; C -- D; }"#).unwrap();
+        let options = DotOptions {
+            undirected: true,
+            ..Default::default()
+        };
+        let output = chunks_to_dot_with_options(&chunks, Some("G"), &options);
+        assert!(output.starts_with("graph G {\n"), "{output}");
+        assert!(output.contains(r#""a b":n -- C [color=red];"#), "{output}");
+        assert!(output.contains("C -- D;"), "{output}");
+        assert!(!output.contains("->"), "{output}");
+    }
+
+    #[test]
+    fn test_strip_layout_attrs() {
+        let dot = r#"digraph G {
+    graph [bb="0,0,62,108"];
+    bb = "0,0,62,108";
+    A [label="Start", pos="27,90", width=0.75, height=0.5];
+    A -> B [label=go, pos="e,27,36.1 27,71.7 27,63.98 27,54.71 27,46.11"];
+}"#;
+        let mut chunks = parse_dot_to_chunks(dot).unwrap();
+        strip_layout_attrs(&mut chunks);
+
+        assert!(
+            chunks
+                .iter()
+                .all(|c| c.kind != "attr_stmt" && c.kind != "id_eq")
+        );
+        let node = chunks.iter().find(|c| c.kind == "node").unwrap();
+        assert_eq!(node.attrs.len(), 1);
+        assert_eq!(node.attrs.get("label"), Some(&"Start".to_string()));
+        let edge = chunks.iter().find(|c| c.kind == "edge").unwrap();
+        assert!(!edge.attrs.contains_key("pos"));
+        assert_eq!(edge.attrs.get("label"), Some(&"go".to_string()));
+    }
+
+    #[test]
+    fn test_chunks_to_dot_min() {
+        let dot = r#"digraph G {
+    rankdir = LR;
+    node [shape=box, style="rounded,filled"];
+    A [label="Start here"];
+    subgraph cluster_x {
+        label = "X";
+        B;
+        C [label=<<b>C</b>>];
+    }
+    A -> B [label="a, b"];
+    B -> C;
+}"#;
+        let chunks = parse_dot_to_chunks(dot).unwrap();
+        let min = chunks_to_dot_min(&chunks, &DotOptions::default());
+        assert!(!min.contains('\n'));
+        assert!(!min.contains("    "));
+
+        let without_ranges = |chunks: Vec<Chunk>| {
+            chunks
+                .into_iter()
+                .map(|c| Chunk {
+                    range: (0, 0),
+                    span: None,
+                    ..c
+                })
+                .collect::<Vec<_>>()
+        };
+        let reparsed = parse_dot_to_chunks(&min).unwrap();
+        assert_eq!(without_ranges(reparsed), without_ranges(chunks));
+    }
+
+    #[test]
+    fn test_attribute_value_quoting() {
+        let format = |key: &str, value: &str| {
+            format_dot_attributes(&HashMap::from([(key.to_string(), value.to_string())]))
+        };
+        assert_eq!(format("penwidth", "1.5"), "penwidth=1.5");
+        assert_eq!(format("weight", "-.5"), "weight=-.5");
+        assert_eq!(format("shape", "box"), "shape=box");
+        assert_eq!(format("label", "1abc"), r#"label="1abc""#);
+        assert_eq!(format("label", "node"), r#"label="node""#);
+        assert_eq!(format("label", ""), r#"label="""#);
+        assert_eq!(format("label", r#"a"b"#), r#"label="a\"b""#);
+        assert_eq!(format("label", r#""quoted""#), r#"label="quoted""#);
+        assert_eq!(
+            format("label", r#"say "hi" now"#),
+            r#"label="say \"hi\" now""#
+        );
+        assert_eq!(format("label", r"line\nbreak"), r#"label="line\nbreak""#);
+        assert_eq!(format("label", r"dir\"), r#"label="dir\\""#);
+        assert_eq!(format("label", r"C:\\new"), r#"label="C:\\new""#);
+        assert_eq!(format("label", "<<b>bold</b>>"), "label=<<b>bold</b>>");
+
+        // Values parse back as they were, backslash escapes included.
+        for value in [
+            "a\"b",
+            "say \"hi\" now",
+            "1abc",
+            "a, b",
+            r"C:\path",
+            r"a\\b",
+            r"line\nbreak",
+            r"\\n",
+        ] {
+            let parsed = parse_attribute_string(&format("label", value));
+            assert_eq!(parsed.get("label").map(String::as_str), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_backslash_roundtrip() {
+        let dot = r#"digraph G {
+    A [label="C:\\new"];
+    B [label="\\n"];
+    C [label="line\nbreak"];
+    D [label="say \"hi\""];
+}"#;
+        let chunks = parse_dot_to_chunks(dot).unwrap();
+        let rendered = chunks_to_dot_nested(&chunks, Some("G"));
+        for label in [
+            r#"label="C:\\new""#,
+            r#"label="\\n""#,
+            r#"label="line\nbreak""#,
+            r#"label="say \"hi\"""#,
+        ] {
+            assert!(rendered.contains(label), "{label} in {rendered}");
+        }
+    }
+
+    #[test]
+    fn test_record_label_roundtrip() {
+        let dot = r#"digraph G {
+    rec [shape=record, label="<f0> left|{<f1> mid
+second line|<f2> right}"];
+    fields [shape=record, label="<a>|<b>"];
+    rec:f1 -> fields:b:s;
+}"#;
+        let chunks = parse_dot_to_chunks(dot).unwrap();
+        let label = "<f0> left|{<f1> mid\nsecond line|<f2> right}";
+        let rec = chunks
+            .iter()
+            .find(|c| c.id.as_deref() == Some("rec"))
+            .unwrap();
+        assert_eq!(rec.attrs.get("label").map(String::as_str), Some(label));
+
+        let rendered = chunks_to_dot_nested(&chunks, Some("G"));
+        assert!(rendered.contains(r#"label="<a>|<b>""#));
+        assert!(rendered.contains("rec:f1 -> fields:b:s;"));
+
+        let reparsed = parse_dot_to_chunks(&rendered).unwrap();
+        let attrs = |chunks: &[Chunk], id: &str| {
+            chunks
+                .iter()
+                .find(|c| c.kind == "node" && c.id.as_deref() == Some(id))
+                .map(|c| c.attrs.clone())
+        };
+        assert_eq!(attrs(&reparsed, "rec"), attrs(&chunks, "rec"));
+        assert_eq!(attrs(&reparsed, "fields"), attrs(&chunks, "fields"));
+
+        let edge = reparsed.iter().find(|c| c.kind == "edge").unwrap();
+        let (from, to) = (edge.id.as_deref().unwrap(), edge.extra.as_deref().unwrap());
+        assert!(port::same_endpoint(from, "rec") && port::same_endpoint(from, "rec:f1"));
+        assert!(port::same_endpoint(to, "fields:b:s"));
+    }
+
+    #[test]
+    fn test_optional_semicolons() {
+        let chunks = parse_dot_to_chunks("digraph { A -> B }").unwrap();
+        let edge = chunks.iter().find(|c| c.kind == "edge").unwrap();
+        assert_eq!(edge.id.as_deref(), Some("A"));
+        assert_eq!(edge.extra.as_deref(), Some("B"));
+
+        let dot = r#"digraph G {
+    rankdir=LR
+    A [label=Start]
+    subgraph cluster_x { B C }
+    A -> B
+    B -> C
+}"#;
+        let chunks = parse_dot_to_chunks(dot).unwrap();
+        let count = |kind: &str| chunks.iter().filter(|c| c.kind == kind).count();
+        assert_eq!(count("id_eq"), 1);
+        assert_eq!(count("subgraph"), 1);
+        assert_eq!(count("edge"), 2);
+        let a = chunks
+            .iter()
+            .find(|c| c.id.as_deref() == Some("A"))
+            .unwrap();
+        assert_eq!(a.attrs.get("label").map(String::as_str), Some("Start"));
+    }
+
+    #[test]
+    fn test_empty_attr_list_roundtrip() {
+        let dot = "digraph { A []; B [] [color=red]; A -> B []; node []; }";
+        let chunks = parse_dot_to_chunks(dot).unwrap();
+        let find = |id: &str| {
+            chunks
+                .iter()
+                .find(|c| c.kind == "node" && c.id.as_deref() == Some(id))
+                .unwrap()
+        };
+        assert!(find("A").attrs.is_empty());
+        assert_eq!(find("A").to_dot(), "    A;");
+        assert_eq!(
+            find("B").attrs.get("color").map(String::as_str),
+            Some("red")
+        );
+        let edge = chunks.iter().find(|c| c.kind == "edge").unwrap();
+        assert_eq!(edge.to_dot(), "    A -> B;");
+        let attr_stmt = chunks.iter().find(|c| c.kind == "attr_stmt").unwrap();
+        assert_eq!(attr_stmt.to_dot(), "    node [];");
+
+        let emitted = chunks_to_dot(&chunks);
+        let reparsed = parse_dot_to_chunks(&format!("digraph {{\n{}}}", emitted)).unwrap();
+        assert_eq!(chunks_to_dot(&reparsed), emitted);
+    }
 }
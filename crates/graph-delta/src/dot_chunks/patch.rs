@@ -0,0 +1,612 @@
+//! Chunk-level patches: the changes between two chunk lists, small enough to send between
+//! collaborators instead of the whole document.
+use crate::commands::{parent_subgraphs, renumber};
+use crate::parser::Chunk;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Identifies a chunk by what it describes rather than where it sits: its kind and id, the
+/// target for edges, and which of the chunks sharing those it is (`0` for the first), since
+/// e.g. several `node [...]` statements may appear in one graph.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ChunkKey {
+    pub kind: String,
+    pub id: Option<String>,
+    /// Target node of an edge.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    #[serde(default)]
+    pub nth: usize,
+}
+
+impl ChunkKey {
+    /// Keys of every chunk in `chunks`, in order.
+    pub fn all(chunks: &[Chunk]) -> Vec<ChunkKey> {
+        let mut seen: HashMap<(String, Option<String>, Option<String>), usize> = HashMap::new();
+        chunks
+            .iter()
+            .map(|chunk| {
+                let to = match chunk.kind.as_str() {
+                    "edge" => chunk.extra.clone(),
+                    _ => None,
+                };
+                let count = seen
+                    .entry((chunk.kind.clone(), chunk.id.clone(), to.clone()))
+                    .or_default();
+                let key = ChunkKey {
+                    kind: chunk.kind.clone(),
+                    id: chunk.id.clone(),
+                    to,
+                    nth: *count,
+                };
+                *count += 1;
+                key
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ChunkChange {
+    /// Inserts `chunk` so that it ends up at `index` in the patched list, inside the subgraph
+    /// `parent` (keyed as in the patched list) or at the top level.
+    Add {
+        index: usize,
+        chunk: Chunk,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parent: Option<ChunkKey>,
+    },
+    Remove {
+        key: ChunkKey,
+    },
+    /// Replaces the chunk in place, keeping its position, and moves it into `parent` like
+    /// [ChunkChange::Add].
+    Modify {
+        key: ChunkKey,
+        chunk: Chunk,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parent: Option<ChunkKey>,
+    },
+}
+
+/// Why [apply_patch] rejected a patch.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum CommandError {
+    #[error("Chunk {0:?} not found")]
+    ChunkNotFound(ChunkKey),
+    #[error("Cannot add a chunk at {index}, the patched list has {len} chunks")]
+    IndexOutOfRange { index: usize, len: usize },
+    #[error("Chunk {0:?} is not nested in a subgraph of the patched list")]
+    InvalidParent(ChunkKey),
+}
+
+/// Changes from one chunk list to another, as [diff_chunks] finds them. Keys refer to the
+/// chunks before the patch.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChunkPatch {
+    pub changes: Vec<ChunkChange>,
+}
+
+impl ChunkPatch {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// The patch that turns `old` into `new`. Chunks with the same [ChunkKey] on both sides are
+/// kept, and modified if their statement or enclosing subgraph differs; the longest run of kept
+/// chunks that is still in order stays put, every other chunk is removed and added at its new
+/// position. Line ranges alone do not count as a change, since inserting one line shifts every
+/// range after it.
+pub fn diff_chunks(old: &[Chunk], new: &[Chunk]) -> ChunkPatch {
+    let old_keys = ChunkKey::all(old);
+    let new_keys = ChunkKey::all(new);
+    let new_pos: HashMap<&ChunkKey, usize> = new_keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| (key, i))
+        .collect();
+
+    // (old position, new position) of the chunks on both sides, in old order.
+    let common: Vec<(usize, usize)> = old_keys
+        .iter()
+        .enumerate()
+        .filter_map(|(i, key)| new_pos.get(key).map(|&j| (i, j)))
+        .collect();
+    let stay = increasing_run(&common.iter().map(|&(_, j)| j).collect::<Vec<_>>());
+    let mut kept = vec![None; old.len()];
+    for &k in &stay {
+        let (i, j) = common[k];
+        kept[i] = Some(j);
+    }
+    let mut placed = vec![false; new.len()];
+
+    let (old_parent, new_parent) = (parent_subgraphs(old), parent_subgraphs(new));
+    let parent_key = |j: usize| new_parent[j].map(|p| new_keys[p].clone());
+    let mut changes = Vec::new();
+    for (i, key) in old_keys.iter().enumerate() {
+        match kept[i] {
+            Some(j) => {
+                placed[j] = true;
+                let same_parent = match (old_parent[i], new_parent[j]) {
+                    (None, None) => true,
+                    (Some(p), Some(q)) => kept[p] == Some(q),
+                    _ => false,
+                };
+                if !same_statement(&old[i], &new[j]) || !same_parent {
+                    changes.push(ChunkChange::Modify {
+                        key: key.clone(),
+                        chunk: new[j].clone(),
+                        parent: parent_key(j),
+                    });
+                }
+            }
+            None => changes.push(ChunkChange::Remove { key: key.clone() }),
+        }
+    }
+    for (index, chunk) in new.iter().enumerate() {
+        if !placed[index] {
+            changes.push(ChunkChange::Add {
+                index,
+                chunk: chunk.clone(),
+                parent: parent_key(index),
+            });
+        }
+    }
+    ChunkPatch { changes }
+}
+
+/// Whether `a` and `b` are the same statement, wherever they are written.
+fn same_statement(a: &Chunk, b: &Chunk) -> bool {
+    (&a.kind, &a.id, &a.attrs, &a.extra) == (&b.kind, &b.id, &b.attrs, &b.extra)
+}
+
+/// Indices into `values` of a longest strictly increasing subsequence.
+fn increasing_run(values: &[usize]) -> Vec<usize> {
+    // tails[l]: index of the smallest value ending an increasing run of length l + 1.
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev = vec![None; values.len()];
+    for (i, &value) in values.iter().enumerate() {
+        let len = tails.partition_point(|&t| values[t] < value);
+        prev[i] = len.checked_sub(1).map(|l| tails[l]);
+        if len == tails.len() {
+            tails.push(i);
+        } else {
+            tails[len] = i;
+        }
+    }
+    let mut run = Vec::with_capacity(tails.len());
+    let mut next = tails.last().copied();
+    while let Some(i) = next {
+        run.push(i);
+        next = prev[i];
+    }
+    run.reverse();
+    run
+}
+
+/// Where a chunk being patched is nested: in the subgraph it had before the patch (an index into
+/// the unpatched list), or in the one a change puts it in.
+enum Parent {
+    Old(Option<usize>),
+    New(Option<ChunkKey>),
+}
+
+/// Applies `patch` to `chunks`, all or nothing: if a key does not resolve or an index is out of
+/// range, `chunks` is left as it was. The patched chunks get fresh line ranges, one statement per
+/// line, nesting them as the patch says.
+pub fn apply_patch(chunks: &mut Vec<Chunk>, patch: &ChunkPatch) -> Result<(), CommandError> {
+    let positions: HashMap<ChunkKey, usize> = ChunkKey::all(chunks)
+        .into_iter()
+        .enumerate()
+        .map(|(i, key)| (key, i))
+        .collect();
+    let find = |key: &ChunkKey| {
+        positions
+            .get(key)
+            .copied()
+            .ok_or_else(|| CommandError::ChunkNotFound(key.clone()))
+    };
+
+    // (chunk, its index before the patch, its parent)
+    let old_parent = parent_subgraphs(chunks);
+    let mut working: Vec<Option<(Chunk, Option<usize>, Parent)>> = chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| Some((chunk.clone(), Some(i), Parent::Old(old_parent[i]))))
+        .collect();
+    let mut adds = Vec::new();
+    for change in &patch.changes {
+        match change {
+            ChunkChange::Modify { key, chunk, parent } => {
+                let i = find(key)?;
+                working[i] = Some((chunk.clone(), Some(i), Parent::New(parent.clone())));
+            }
+            ChunkChange::Remove { key } => working[find(key)?] = None,
+            ChunkChange::Add {
+                index,
+                chunk,
+                parent,
+            } => adds.push((*index, chunk, parent)),
+        }
+    }
+
+    let mut working: Vec<_> = working.into_iter().flatten().collect();
+    adds.sort_by_key(|&(index, _, _)| index);
+    for (index, chunk, parent) in adds {
+        if index > working.len() {
+            return Err(CommandError::IndexOutOfRange {
+                index,
+                len: working.len(),
+            });
+        }
+        working.insert(index, (chunk.clone(), None, Parent::New(parent.clone())));
+    }
+
+    let patched: Vec<Chunk> = working.iter().map(|(chunk, _, _)| chunk.clone()).collect();
+    let keys = ChunkKey::all(&patched);
+    let by_key: HashMap<&ChunkKey, usize> =
+        keys.iter().enumerate().map(|(i, key)| (key, i)).collect();
+    let by_origin: HashMap<usize, usize> = working
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (_, origin, _))| origin.map(|origin| (origin, i)))
+        .collect();
+    let mut parent = Vec::with_capacity(working.len());
+    for (i, (_, _, p)) in working.iter().enumerate() {
+        let resolved = match p {
+            Parent::Old(p) => p.map(|p| by_origin.get(&p).copied()),
+            Parent::New(key) => key.as_ref().map(|key| by_key.get(key).copied()),
+        };
+        match resolved {
+            None => parent.push(None),
+            Some(Some(p)) if patched[p].kind == "subgraph" => parent.push(Some(p)),
+            Some(_) => return Err(CommandError::InvalidParent(keys[i].clone())),
+        }
+    }
+    // Subgraphs nested in each other would be dropped by `renumber`.
+    let in_cycle = |i: usize| {
+        std::iter::successors(parent[i], |&p| parent[p])
+            .nth(parent.len())
+            .is_some()
+    };
+    if let Some(i) = (0..parent.len()).find(|&i| in_cycle(i)) {
+        return Err(CommandError::InvalidParent(keys[i].clone()));
+    }
+
+    *chunks = renumber(&patched, &parent);
+    Ok(())
+}
+
+/// Something [merge3] could not decide because both sides changed it differently.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Conflict {
+    pub key: ChunkKey,
+    /// The attribute both sides set, or `None` when the chunk as a whole conflicts: one side
+    /// removed it while the other changed it, or both changed its value (`extra`).
+    pub attr: Option<String>,
+    /// Our value, the rendered chunk for a whole-chunk conflict; `None` if removed.
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+}
+
+/// Three-way merge of two edited versions of `base`. Chunks are matched by [ChunkKey] and
+/// merged attribute by attribute, so ours changing a node's color and theirs its label both
+/// apply, while both setting its color differently is a [Conflict].
+///
+/// The result keeps our order, with chunks only theirs has placed after the chunk preceding
+/// them on their side. Each chunk stays in the subgraph holding it on our side, or on theirs if
+/// only they have it, and line ranges are renumbered to match.
+pub fn merge3<'a>(
+    base: &'a [Chunk],
+    ours: &'a [Chunk],
+    theirs: &'a [Chunk],
+) -> Result<Vec<Chunk>, Vec<Conflict>> {
+    // Key of the subgraph each chunk is in, on its own side.
+    let parent_keys = |chunks: &[Chunk]| -> Vec<Option<ChunkKey>> {
+        let keys = ChunkKey::all(chunks);
+        parent_subgraphs(chunks)
+            .into_iter()
+            .map(|p| p.map(|p| keys[p].clone()))
+            .collect()
+    };
+    let (our_parent, their_parent) = (parent_keys(ours), parent_keys(theirs));
+    let (base, ours, theirs) = (keyed(base), keyed(ours), keyed(theirs));
+    let lookup = |side: &[(ChunkKey, &'a Chunk)]| -> HashMap<ChunkKey, &'a Chunk> {
+        side.iter().cloned().collect()
+    };
+    let (base_map, our_map, their_map) = (lookup(&base), lookup(&ours), lookup(&theirs));
+
+    let mut conflicts = Vec::new();
+    let mut merged: Vec<(ChunkKey, Chunk, Option<ChunkKey>)> = Vec::new();
+    for (i, (key, chunk)) in ours.iter().enumerate() {
+        let base_chunk = base_map.get(key).copied();
+        let their_chunk = their_map.get(key).copied();
+        if let Some(chunk) = merge_chunk(key, base_chunk, Some(chunk), their_chunk, &mut conflicts)
+        {
+            merged.push((key.clone(), chunk, our_parent[i].clone()));
+        }
+    }
+    for (i, (key, chunk)) in theirs.iter().enumerate() {
+        if our_map.contains_key(key) {
+            continue;
+        }
+        let base_chunk = base_map.get(key).copied();
+        let Some(chunk) = merge_chunk(key, base_chunk, None, Some(chunk), &mut conflicts) else {
+            continue;
+        };
+        let after = theirs[..i]
+            .iter()
+            .rev()
+            .find_map(|(prev, _)| merged.iter().position(|(key, _, _)| key == prev));
+        merged.insert(
+            after.map_or(0, |pos| pos + 1),
+            (key.clone(), chunk, their_parent[i].clone()),
+        );
+    }
+
+    if conflicts.is_empty() {
+        // A parent the merge dropped leaves its members at the top level.
+        let parent: Vec<Option<usize>> = merged
+            .iter()
+            .map(|(_, _, parent)| {
+                let parent = parent.as_ref()?;
+                merged.iter().position(|(key, _, _)| key == parent)
+            })
+            .collect();
+        let chunks: Vec<Chunk> = merged.into_iter().map(|(_, chunk, _)| chunk).collect();
+        Ok(renumber(&chunks, &parent))
+    } else {
+        Err(conflicts)
+    }
+}
+
+/// `chunks` paired with their keys.
+fn keyed(chunks: &[Chunk]) -> Vec<(ChunkKey, &Chunk)> {
+    ChunkKey::all(chunks).into_iter().zip(chunks).collect()
+}
+
+/// One chunk of [merge3]; `None` for a side that does not have it.
+fn merge_chunk(
+    key: &ChunkKey,
+    base: Option<&Chunk>,
+    ours: Option<&Chunk>,
+    theirs: Option<&Chunk>,
+    conflicts: &mut Vec<Conflict>,
+) -> Option<Chunk> {
+    if ours == theirs || theirs == base {
+        return ours.cloned();
+    }
+    if ours == base {
+        return theirs.cloned();
+    }
+    let (Some(ours), Some(theirs)) = (ours, theirs) else {
+        let render = |chunk: Option<&Chunk>| chunk.map(|c| c.to_dot().trim().to_string());
+        conflicts.push(Conflict {
+            key: key.clone(),
+            attr: None,
+            ours: render(ours),
+            theirs: render(theirs),
+        });
+        return ours.cloned();
+    };
+
+    let mut merged = ours.clone();
+    let base_extra = base.and_then(|b| b.extra.as_ref());
+    match (ours.extra.as_ref(), theirs.extra.as_ref()) {
+        (o, t) if o == t || t == base_extra => {}
+        (o, t) if o == base_extra => merged.extra = t.cloned(),
+        _ => conflicts.push(Conflict {
+            key: key.clone(),
+            attr: None,
+            ours: Some(ours.to_dot().trim().to_string()),
+            theirs: Some(theirs.to_dot().trim().to_string()),
+        }),
+    }
+
+    let empty = HashMap::new();
+    let base_attrs = base.map_or(&empty, |b| &b.attrs);
+    let mut names: Vec<&String> = base_attrs
+        .keys()
+        .chain(ours.attrs.keys())
+        .chain(theirs.attrs.keys())
+        .collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let (b, o, t) = (
+            base_attrs.get(name),
+            ours.attrs.get(name),
+            theirs.attrs.get(name),
+        );
+        if o == t || t == b {
+            continue;
+        }
+        if o == b {
+            match t {
+                Some(value) => merged.attrs.insert(name.clone(), value.clone()),
+                None => merged.attrs.remove(name),
+            };
+        } else {
+            conflicts.push(Conflict {
+                key: key.clone(),
+                attr: Some(name.clone()),
+                ours: o.cloned(),
+                theirs: t.cloned(),
+            });
+        }
+    }
+    Some(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{chunks_to_dot_nested, parse_dot_to_chunks};
+
+    #[test]
+    fn test_apply_diff_roundtrip() {
+        let old = parse_dot_to_chunks(
+            r#"digraph G {
+                node [shape=box];
+                A [label="Start"];
+                B;
+                C;
+                A -> B;
+                B -> C;
+                subgraph cluster_x { label="X"; D; }
+            }"#,
+        )
+        .unwrap();
+        let new = parse_dot_to_chunks(
+            r#"digraph G {
+                node [shape=box];
+                C [color=red];
+                A [label="Begin"];
+                E;
+                A -> B;
+                A -> E;
+                node [shape=ellipse];
+                subgraph cluster_x { label="X"; D; E2; }
+            }"#,
+        )
+        .unwrap();
+
+        let patch = diff_chunks(&old, &new);
+        let mut patched = old.clone();
+        apply_patch(&mut patched, &patch).unwrap();
+        assert_eq!(patched.len(), new.len());
+        assert!(patched.iter().zip(&new).all(|(a, b)| same_statement(a, b)));
+        assert_eq!(
+            chunks_to_dot_nested(&patched, Some("G")),
+            chunks_to_dot_nested(&new, Some("G"))
+        );
+
+        assert!(diff_chunks(&new, &new).is_empty());
+        let json = serde_json::to_string(&patch).unwrap();
+        assert_eq!(serde_json::from_str::<ChunkPatch>(&json).unwrap(), patch);
+    }
+
+    #[test]
+    fn test_patch_only_touches_changed_chunks() {
+        let old = parse_dot_to_chunks("digraph G {\n A;\n B;\n A -> B;\n}").unwrap();
+        let new = parse_dot_to_chunks("digraph G {\n A [color=red];\n B;\n A -> B;\n}").unwrap();
+
+        let patch = diff_chunks(&old, &new);
+        assert_eq!(patch.changes.len(), 1);
+        assert!(matches!(
+            &patch.changes[0],
+            ChunkChange::Modify { key, .. } if key.id.as_deref() == Some("A")
+        ));
+    }
+
+    #[test]
+    fn test_inserting_a_line_is_one_change() {
+        let old = parse_dot_to_chunks(
+            "digraph G {\n A;\n B;\n A -> B;\n subgraph cluster_x {\n C;\n }\n}",
+        )
+        .unwrap();
+        let new = parse_dot_to_chunks(
+            "digraph G {\n Z;\n A;\n B;\n A -> B;\n subgraph cluster_x {\n C;\n }\n}",
+        )
+        .unwrap();
+
+        let patch = diff_chunks(&old, &new);
+        assert_eq!(patch.changes.len(), 1);
+        assert!(matches!(
+            &patch.changes[0],
+            ChunkChange::Add { index: 0, chunk, parent: None } if chunk.id.as_deref() == Some("Z")
+        ));
+
+        let mut patched = old.clone();
+        apply_patch(&mut patched, &patch).unwrap();
+        assert_eq!(
+            chunks_to_dot_nested(&patched, Some("G")),
+            chunks_to_dot_nested(&new, Some("G"))
+        );
+    }
+
+    #[test]
+    fn test_apply_patch_is_atomic() {
+        let mut chunks = parse_dot_to_chunks("digraph G { A; B; }").unwrap();
+        let before = chunks.clone();
+        let patch = ChunkPatch {
+            changes: vec![
+                ChunkChange::Remove {
+                    key: ChunkKey::all(&chunks)[0].clone(),
+                },
+                ChunkChange::Remove {
+                    key: ChunkKey {
+                        kind: "node".to_string(),
+                        id: Some("missing".to_string()),
+                        to: None,
+                        nth: 0,
+                    },
+                },
+            ],
+        };
+        assert!(matches!(
+            apply_patch(&mut chunks, &patch),
+            Err(CommandError::ChunkNotFound(_))
+        ));
+        assert_eq!(chunks, before);
+    }
+
+    #[test]
+    fn test_merge3() {
+        let base =
+            parse_dot_to_chunks("digraph G {\n A [color=blue];\n B;\n A -> B [style=dashed];\n}")
+                .unwrap();
+        let ours = parse_dot_to_chunks(
+            "digraph G {\n A [color=blue];\n B;\n C;\n A -> B [style=dashed];\n}",
+        )
+        .unwrap();
+        let theirs = parse_dot_to_chunks(
+            "digraph G {\n A [color=blue, label=Start];\n B;\n A -> B [style=bold];\n}",
+        )
+        .unwrap();
+
+        let merged = merge3(&base, &ours, &theirs).unwrap();
+        let ids: Vec<_> = merged.iter().filter_map(|c| c.id.as_deref()).collect();
+        assert_eq!(ids, ["A", "B", "C", "A"]);
+        assert_eq!(merged[0].attrs["label"], "Start");
+        assert_eq!(merged[3].attrs["style"], "bold");
+
+        let red =
+            parse_dot_to_chunks("digraph G {\n A [color=red];\n B;\n A -> B [style=dashed];\n}")
+                .unwrap();
+        let green =
+            parse_dot_to_chunks("digraph G {\n A [color=green];\n B;\n A -> B [style=dotted];\n}")
+                .unwrap();
+        let conflicts = merge3(&base, &red, &green).unwrap_err();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].key.id.as_deref(), Some("A"));
+        assert_eq!(conflicts[0].attr.as_deref(), Some("color"));
+        assert_eq!(conflicts[0].ours.as_deref(), Some("red"));
+        assert_eq!(conflicts[0].theirs.as_deref(), Some("green"));
+    }
+
+    #[test]
+    fn test_merge3_renders_nesting() {
+        let base = parse_dot_to_chunks("digraph G {\n A;\n subgraph cluster_x {\n B;\n }\n C;\n}")
+            .unwrap();
+        let ours =
+            parse_dot_to_chunks("digraph G {\n A;\n subgraph cluster_x {\n B;\n D;\n }\n C;\n}")
+                .unwrap();
+        let theirs = parse_dot_to_chunks(
+            "digraph G {\n A;\n E;\n subgraph cluster_x {\n B;\n F;\n }\n C;\n}",
+        )
+        .unwrap();
+
+        let merged = merge3(&base, &ours, &theirs).unwrap();
+        let expected = parse_dot_to_chunks(
+            "digraph G {\n A;\n E;\n subgraph cluster_x {\n B;\n F;\n D;\n }\n C;\n}",
+        )
+        .unwrap();
+        assert_eq!(
+            chunks_to_dot_nested(&merged, Some("G")),
+            chunks_to_dot_nested(&expected, Some("G"))
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! Port syntax in node ids: `node`, `node:port`, `node:compass` and `node:port:compass`.
+
+/// Compass point of a port, as in `A:p1:ne`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compass {
+    N,
+    Ne,
+    E,
+    Se,
+    S,
+    Sw,
+    W,
+    Nw,
+    C,
+    /// `_`, let Graphviz pick the side.
+    Any,
+}
+
+impl Compass {
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "n" => Compass::N,
+            "ne" => Compass::Ne,
+            "e" => Compass::E,
+            "se" => Compass::Se,
+            "s" => Compass::S,
+            "sw" => Compass::Sw,
+            "w" => Compass::W,
+            "nw" => Compass::Nw,
+            "c" => Compass::C,
+            "_" => Compass::Any,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compass::N => "n",
+            Compass::Ne => "ne",
+            Compass::E => "e",
+            Compass::Se => "se",
+            Compass::S => "s",
+            Compass::Sw => "sw",
+            Compass::W => "w",
+            Compass::Nw => "nw",
+            Compass::C => "c",
+            Compass::Any => "_",
+        }
+    }
+}
+
+/// Byte offsets of the `:` separators outside quoted and HTML ids.
+fn separators(id: &str) -> Vec<usize> {
+    let mut seps = Vec::new();
+    let (mut quoted, mut escaped, mut html_depth) = (false, false, 0usize);
+    for (i, c) in id.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' if html_depth == 0 => quoted = !quoted,
+            '<' if !quoted => html_depth += 1,
+            '>' if !quoted => html_depth = html_depth.saturating_sub(1),
+            ':' if !quoted && html_depth == 0 => seps.push(i),
+            _ => {}
+        }
+    }
+    seps
+}
+
+/// Splits a node id into its base id, port and compass point, trimming whitespace around the
+/// parts. As in the DOT grammar, a single suffix that is a compass point (`A:n`) is read as one;
+/// colons inside quoted ids (`"a:b"`) are part of the base.
+pub fn parse_port(id: &str) -> (&str, Option<&str>, Option<Compass>) {
+    let seps = separators(id);
+    let Some(&first) = seps.first() else {
+        return (id.trim(), None, None);
+    };
+    let base = id[..first].trim();
+    let rest = id[first + 1..].trim();
+    if let Some(compass) = Compass::parse(rest) {
+        return (base, None, Some(compass));
+    }
+    if let Some(&last) = seps.get(1..).and_then(<[usize]>::last)
+        && let Some(compass) = Compass::parse(id[last + 1..].trim())
+    {
+        return (base, Some(id[first + 1..last].trim()), Some(compass));
+    }
+    (base, Some(rest), None)
+}
+
+/// Rebuilds a node id from the parts [parse_port] returns, without whitespace around the colons.
+pub fn port_id(base: &str, port: Option<&str>, compass: Option<Compass>) -> String {
+    let mut id = base.to_string();
+    for part in [port, compass.as_ref().map(Compass::as_str)]
+        .into_iter()
+        .flatten()
+    {
+        id.push(':');
+        id.push_str(part);
+    }
+    id
+}
+
+/// `id` in the canonical form of [port_id].
+pub fn normalize_port(id: &str) -> String {
+    let (base, port, compass) = parse_port(id);
+    port_id(base, port, compass)
+}
+
+/// Whether the edge endpoint `stored` is the one `wanted` refers to: the base ids must match,
+/// and the port and compass point too if `wanted` gives any, so `A` matches `A:p1:n` but `A:p2`
+/// does not.
+pub fn same_endpoint(stored: &str, wanted: &str) -> bool {
+    let stored = parse_port(stored);
+    match parse_port(wanted) {
+        (base, None, None) => stored.0 == base,
+        wanted => stored == wanted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_port() {
+        assert_eq!(parse_port("n:p:e"), ("n", Some("p"), Some(Compass::E)));
+        assert_eq!(parse_port("n:e"), ("n", None, Some(Compass::E)));
+        assert_eq!(parse_port("n:p1"), ("n", Some("p1"), None));
+        assert_eq!(parse_port("n"), ("n", None, None));
+        assert_eq!(
+            parse_port(r#""a:b":"p:q""#),
+            (r#""a:b""#, Some(r#""p:q""#), None)
+        );
+        assert_eq!(
+            parse_port("A : p1 : _"),
+            ("A", Some("p1"), Some(Compass::Any))
+        );
+
+        for id in ["n:p:e", "n:e", "n:p1", "n", r#""a:b":"p:q""#] {
+            assert_eq!(normalize_port(id), id);
+        }
+        assert_eq!(normalize_port("A : p1 : sw"), "A:p1:sw");
+    }
+
+    #[test]
+    fn test_same_endpoint() {
+        assert!(same_endpoint("A:p1:n", "A"));
+        assert!(same_endpoint("A:p1:n", "A:p1:n"));
+        assert!(same_endpoint("A : p1", "A:p1"));
+        assert!(!same_endpoint("A:p1", "A:p2"));
+        assert!(!same_endpoint("A", "A:p1"));
+        assert!(!same_endpoint("AB:p1", "A"));
+    }
+}
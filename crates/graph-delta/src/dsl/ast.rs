@@ -1,7 +1,10 @@
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::collections::HashMap;
 
-/// Data strucutres for parsed DSL
-#[derive(Debug)]
+/// Data structures for parsed DSL
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
 pub enum DslCommand {
     Node(NodeCmd),
     Edge(EdgeCmd),
@@ -10,40 +13,45 @@ pub enum DslCommand {
     Rank(RankCmd),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
 pub enum NodeCmd {
-    Set { id: String, attrs: Attrs },  // Auto-detects add vs update
+    Set { id: String, attrs: Attrs }, // Auto-detects add vs update
     Delete { id: String },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
 pub enum EdgeCmd {
     Set {
         from: String,
         to: String,
         attrs: Attrs,
-    },  // Auto-detects add vs update
+    }, // Auto-detects add vs update
     Delete {
         from: String,
         to: String,
     },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
 pub enum ClusterCmd {
-    Set { id: String, attrs: Attrs },  // Auto-detects add vs update
+    Set { id: String, attrs: Attrs }, // Auto-detects add vs update
     Delete { id: String },
     Move { node: String, cluster: String },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
 pub enum GlobalCmd {
     Set(Attrs),
     NodeDefaults(Attrs),
     EdgeDefaults(Attrs),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
 pub enum RankCmd {
     Same(Vec<String>),
     Min(Vec<String>),
@@ -51,3 +59,29 @@ pub enum RankCmd {
 }
 
 pub type Attrs = HashMap<String, String>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dsl_command_schema_shapes() {
+        let schema = serde_json::to_value(schemars::schema_for!(DslCommand)).unwrap();
+        let variants: Vec<&str> = schema["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|v| v["required"].as_array().unwrap())
+            .map(|r| r.as_str().unwrap())
+            .collect();
+        for command in ["node", "edge", "cluster", "rank"] {
+            assert!(variants.contains(&command), "missing {command}");
+        }
+        for shape in ["NodeCmd", "EdgeCmd", "ClusterCmd", "RankCmd"] {
+            assert!(
+                schema["definitions"].get(shape).is_some(),
+                "missing {shape}"
+            );
+        }
+    }
+}
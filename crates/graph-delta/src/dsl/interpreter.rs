@@ -1,6 +1,7 @@
 //! Applies DslCommands to a vector of Chunks, modifying the graph structure.
 use crate::dsl::ast::{ClusterCmd, DslCommand, EdgeCmd, GlobalCmd, NodeCmd, RankCmd};
 use crate::parser::Chunk;
+use crate::port;
 
 pub fn apply_commands(chunks: &mut Vec<Chunk>, cmds: Vec<DslCommand>) {
     for cmd in cmds {
@@ -36,22 +37,25 @@ fn apply_node(chunks: &mut Vec<Chunk>, cmd: NodeCmd) {
                     }
                     // Update all edges connected to this node
                     for edge_chunk in chunks.iter_mut().filter(|c| c.kind == "edge") {
-                        if edge_chunk.id.as_deref() == Some(&id) {
-                            edge_chunk.id = Some(new_id.clone());
-                        }
-                        if edge_chunk.extra.as_deref() == Some(&id) {
-                            edge_chunk.extra = Some(new_id.clone());
+                        for end in [&mut edge_chunk.id, &mut edge_chunk.extra]
+                            .into_iter()
+                            .flatten()
+                        {
+                            // Keep the port: `A:p1` becomes `B:p1`.
+                            let (base, port, compass) = port::parse_port(end);
+                            if base == id {
+                                *end = port::port_id(&new_id, port, compass);
+                            }
                         }
                     }
                     // Update rank statements
                     for rank_chunk in chunks.iter_mut().filter(|c| c.kind == "rank") {
-                        if let Some(nodes_str) = rank_chunk.attrs.get_mut("nodes") {
-                            *nodes_str = nodes_str
-                                .split(',')
-                                .map(|s| if s == id { new_id.clone() } else { s.to_string() })
-                                .collect::<Vec<_>>()
-                                .join(",");
-                        }
+                        let members: Vec<String> = rank_chunk
+                            .rank_members()
+                            .into_iter()
+                            .map(|s| if s == id { new_id.clone() } else { s })
+                            .collect();
+                        rank_chunk.set_rank_members(&members);
                     }
                 }
                 // Merge other attributes (preserves existing attributes not specified)
@@ -69,6 +73,7 @@ fn apply_node(chunks: &mut Vec<Chunk>, cmd: NodeCmd) {
                     attrs,
                     range: (0, 0),
                     extra: None,
+                    span: None,
                 });
             }
         }
@@ -78,7 +83,10 @@ fn apply_node(chunks: &mut Vec<Chunk>, cmd: NodeCmd) {
             // Remove edges connected to the node
             chunks.retain(|c| {
                 !(c.kind == "edge"
-                    && (c.id.as_deref() == Some(&id) || c.extra.as_deref() == Some(&id)))
+                    && [&c.id, &c.extra]
+                        .into_iter()
+                        .flatten()
+                        .any(|end| port::same_endpoint(end, &id)))
             });
         }
     }
@@ -104,6 +112,7 @@ fn apply_edge(chunks: &mut Vec<Chunk>, cmd: EdgeCmd) {
                     extra: Some(to),
                     attrs,
                     range: (0, 0),
+                    span: None,
                 });
             }
         }
@@ -143,6 +152,7 @@ fn apply_cluster(chunks: &mut Vec<Chunk>, cmd: ClusterCmd) {
                     attrs,
                     range: (0, 0),
                     extra: None,
+                    span: None,
                 });
             }
         }
@@ -183,6 +193,7 @@ fn apply_global(chunks: &mut Vec<Chunk>, cmd: GlobalCmd) {
             attrs: attrs_to_add,
             range: (0, 0),
             extra: None,
+            span: None,
         });
     }
 }
@@ -195,14 +206,14 @@ fn apply_rank(chunks: &mut Vec<Chunk>, cmd: RankCmd) {
         RankCmd::Max(nodes) => ("max", nodes),
     };
 
-    let mut attrs = std::collections::HashMap::new();
-    attrs.insert("nodes".to_string(), nodes.join(","));
-
-    chunks.push(Chunk {
+    let mut rank = Chunk {
         kind: "rank".to_string(),
         id: Some(kind.to_string()),
-        attrs,
+        attrs: std::collections::HashMap::new(),
         range: (0, 0),
         extra: None,
-    });
+        span: None,
+    };
+    rank.set_rank_members(&nodes);
+    chunks.push(rank);
 }
\ No newline at end of file
@@ -6,3 +6,6 @@ pub use interpreter::apply_commands;
 
 mod parser;
 pub use parser::parse_dsl;
+
+mod transpile;
+pub use transpile::command_to_dsl;
@@ -147,3 +147,27 @@ fn parse_rank_max_cmd(pair: Pair<Rule>) -> DslCommand {
     let list = parse_ident_list(inner.next().unwrap());
     DslCommand::Rank(RankCmd::Max(list))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_are_not_nodes() {
+        let cmds =
+            parse_dsl("node defaults shape=box\nedge defaults color=gray\nnode defaults").unwrap();
+        assert!(matches!(
+            &cmds[0],
+            DslCommand::Global(GlobalCmd::NodeDefaults(attrs)) if attrs["shape"] == "box"
+        ));
+        assert!(matches!(
+            &cmds[1],
+            DslCommand::Global(GlobalCmd::EdgeDefaults(attrs)) if attrs["color"] == "gray"
+        ));
+        // Without attributes it is still a node named `defaults`.
+        assert!(matches!(
+            &cmds[2],
+            DslCommand::Node(NodeCmd::Set { id, .. }) if id == "defaults"
+        ));
+    }
+}
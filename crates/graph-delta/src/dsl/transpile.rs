@@ -0,0 +1,269 @@
+//! Renders DotCommands as DSL lines, the inverse of the interpreter.
+use crate::commands::{self, DotCommand, RankKind};
+use crate::parser;
+
+/// The DSL line(s) equivalent to `cmd`, joined by newlines. Node parents become a
+/// `subgraph move` line; edge and subgraph parents are dropped since the DSL can only move
+/// nodes. Commands the DSL has no syntax for (`delete_attr`, `clear_*_attrs`, `reorder`,
+/// unnamed subgraphs, `source`/`sink` ranks, ids or values containing a `"`) or that fail
+/// validation give an empty string.
+pub fn command_to_dsl(cmd: &DotCommand) -> String {
+    to_dsl(cmd).unwrap_or_default()
+}
+
+fn to_dsl(cmd: &DotCommand) -> Option<String> {
+    match cmd {
+        DotCommand::CreateNode { id, attrs, parent } => {
+            let node = with_attrs(format!("node {}", ident(id)?), &dot_attrs(attrs.as_deref()))?;
+            match parent {
+                Some(parent) => Some(format!(
+                    "{}\nsubgraph move {} to {}",
+                    node,
+                    ident(id)?,
+                    ident(parent)?
+                )),
+                None => Some(node),
+            }
+        }
+        DotCommand::UpdateNode { id, attrs } => {
+            with_attrs(format!("node {}", ident(id)?), &dot_attrs(attrs.as_deref()))
+        }
+        DotCommand::DeleteNode { id } => Some(format!("node delete {}", ident(id)?)),
+        DotCommand::StyleNode {
+            id,
+            fill,
+            shape,
+            filled,
+            peripheries,
+        } => {
+            let attrs =
+                commands::style_attrs(fill.as_deref(), shape.as_deref(), *filled, *peripheries)
+                    .ok()?;
+            with_attrs(format!("node {}", ident(id)?), &attrs)
+        }
+        DotCommand::CreateEdge {
+            from, to, attrs, ..
+        }
+        | DotCommand::UpdateEdge { from, to, attrs } => with_attrs(
+            format!("edge {} -> {}", ident(from)?, ident(to)?),
+            &dot_attrs(attrs.as_deref()),
+        ),
+        DotCommand::DeleteEdge { from, to } => {
+            Some(format!("edge delete {} -> {}", ident(from)?, ident(to)?))
+        }
+        DotCommand::CreateSubgraph { id, .. } => {
+            Some(format!("subgraph {}", ident(id.as_deref()?)?))
+        }
+        DotCommand::DeleteSubgraph { id } => Some(format!("subgraph delete {}", ident(id)?)),
+        DotCommand::SetGraphAttr { key, value } => {
+            required_attrs("graph set", &[(key.clone(), value.clone())])
+        }
+        DotCommand::SetNodeDefault { attrs } => {
+            required_attrs("node defaults", &dot_attrs(Some(attrs)))
+        }
+        DotCommand::SetEdgeDefault { attrs } => {
+            required_attrs("edge defaults", &dot_attrs(Some(attrs)))
+        }
+        DotCommand::SetLayout {
+            rankdir,
+            nodesep,
+            ranksep,
+            splines,
+        } => {
+            let attrs =
+                commands::layout_attrs(rankdir.as_deref(), *nodesep, *ranksep, splines.as_deref())
+                    .ok()?;
+            required_attrs("graph set", &attrs)
+        }
+        DotCommand::SetRank { kind, nodes } => match kind {
+            RankKind::Same | RankKind::Min | RankKind::Max if !nodes.is_empty() => {
+                let nodes = nodes.iter().map(|n| ident(n)).collect::<Option<Vec<_>>>()?;
+                Some(format!("rank {} {}", kind.as_str(), nodes.join(", ")))
+            }
+            _ => None,
+        },
+        DotCommand::ClearNodeAttrs { .. }
+        | DotCommand::ClearEdgeAttrs { .. }
+        | DotCommand::DeleteAttr { .. }
+        | DotCommand::Reorder { .. } => None,
+    }
+}
+
+/// A DOT attribute string as `(key, value)` pairs sorted by key, so output is deterministic.
+fn dot_attrs(attrs: Option<&str>) -> Vec<(String, String)> {
+    let mut attrs: Vec<_> = attrs
+        .map(parser::parse_attribute_string)
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    attrs.sort();
+    attrs
+}
+
+/// `head` followed by `key=value` pairs, if any.
+fn with_attrs(head: String, attrs: &[(String, String)]) -> Option<String> {
+    let mut line = head;
+    for (key, val) in attrs {
+        line.push_str(&format!(" {}={}", key, value(val)?));
+    }
+    Some(line)
+}
+
+/// Like [with_attrs], for statements the grammar only accepts with at least one attribute.
+fn required_attrs(head: &str, attrs: &[(String, String)]) -> Option<String> {
+    if attrs.is_empty() {
+        None
+    } else {
+        with_attrs(head.to_string(), attrs)
+    }
+}
+
+/// DSL identifiers are alphanumeric or quoted.
+fn ident(id: &str) -> Option<String> {
+    if !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Some(id.to_string())
+    } else {
+        quote(id)
+    }
+}
+
+/// Unquoted DSL values end at whitespace or a comma, so anything containing those is quoted.
+fn value(value: &str) -> Option<String> {
+    if !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c == ',' || c == '"')
+    {
+        Some(value.to_string())
+    } else {
+        quote(value)
+    }
+}
+
+/// `text` in DSL quotes, or as it is if it already is in quotes. The quotes have no escapes, so
+/// `None` if a `"` is left inside.
+fn quote(text: &str) -> Option<String> {
+    let inner = text
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .unwrap_or(text);
+    (!inner.contains('"')).then(|| format!("\"{}\"", inner))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl::parse_dsl;
+
+    #[test]
+    fn test_create_node_to_dsl() {
+        let cmd = DotCommand::CreateNode {
+            id: "A".to_string(),
+            attrs: Some(r#"shape=box, label="Start here""#.to_string()),
+            parent: None,
+        };
+        assert_eq!(
+            command_to_dsl(&cmd),
+            r#"node A label="Start here" shape=box"#
+        );
+
+        let cmd = DotCommand::CreateNode {
+            id: "node_1".to_string(),
+            attrs: None,
+            parent: Some("Backend".to_string()),
+        };
+        assert_eq!(
+            command_to_dsl(&cmd),
+            "node \"node_1\"\nsubgraph move \"node_1\" to Backend"
+        );
+    }
+
+    #[test]
+    fn test_create_edge_to_dsl() {
+        let cmd = DotCommand::CreateEdge {
+            from: "A".to_string(),
+            to: "B".to_string(),
+            attrs: Some("color=red, penwidth=3".to_string()),
+            parent: Some("Backend".to_string()),
+        };
+        assert_eq!(command_to_dsl(&cmd), "edge A -> B color=red penwidth=3");
+
+        let cmd = DotCommand::DeleteEdge {
+            from: "A".to_string(),
+            to: "B".to_string(),
+        };
+        assert_eq!(command_to_dsl(&cmd), "edge delete A -> B");
+    }
+
+    #[test]
+    fn test_update_node_to_dsl() {
+        let cmd = DotCommand::UpdateNode {
+            id: "A".to_string(),
+            attrs: Some("color=blue".to_string()),
+        };
+        assert_eq!(command_to_dsl(&cmd), "node A color=blue");
+
+        let cmd = DotCommand::StyleNode {
+            id: "A".to_string(),
+            fill: Some("red".to_string()),
+            shape: None,
+            filled: true,
+            peripheries: None,
+        };
+        assert_eq!(command_to_dsl(&cmd), "node A fillcolor=red style=filled");
+    }
+
+    #[test]
+    fn test_dsl_output_parses() {
+        let commands = [
+            DotCommand::UpdateNode {
+                id: "A".to_string(),
+                attrs: Some(r#"label="Two words""#.to_string()),
+            },
+            DotCommand::SetRank {
+                kind: RankKind::Same,
+                nodes: vec!["A".to_string(), "B".to_string()],
+            },
+            DotCommand::SetLayout {
+                rankdir: Some("LR".to_string()),
+                nodesep: None,
+                ranksep: Some(0.5),
+                splines: None,
+            },
+        ];
+        let dsl: Vec<String> = commands.iter().map(command_to_dsl).collect();
+        assert_eq!(dsl[1], "rank same A, B");
+        assert_eq!(dsl[2], "graph set rankdir=LR ranksep=0.5");
+        assert_eq!(parse_dsl(&dsl.join("\n")).unwrap().len(), 3);
+
+        let unsupported = DotCommand::DeleteAttr {
+            key: "rankdir".to_string(),
+        };
+        assert_eq!(command_to_dsl(&unsupported), "");
+    }
+
+    #[test]
+    fn test_quoted_text_to_dsl() {
+        // Ids already in DOT quotes are not quoted again.
+        let cmd = DotCommand::CreateEdge {
+            from: r#""a b""#.to_string(),
+            to: "c d".to_string(),
+            attrs: None,
+            parent: None,
+        };
+        let dsl = command_to_dsl(&cmd);
+        assert_eq!(dsl, r#"edge "a b" -> "c d""#);
+        assert_eq!(parse_dsl(&dsl).unwrap().len(), 1);
+
+        // DSL quotes have no escapes, so a `"` inside can't be written.
+        let cmd = DotCommand::UpdateNode {
+            id: "A".to_string(),
+            attrs: Some(r#"label="say \"hi\"""#.to_string()),
+        };
+        assert_eq!(command_to_dsl(&cmd), "");
+        let cmd = DotCommand::DeleteNode {
+            id: r#""a"b""#.to_string(),
+        };
+        assert_eq!(command_to_dsl(&cmd), "");
+    }
+}
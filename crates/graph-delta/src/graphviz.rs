@@ -0,0 +1,54 @@
+//! Detection of a locally installed Graphviz.
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Name of the Graphviz layout binary on this platform.
+const DOT_BINARY: &str = if cfg!(windows) { "dot.exe" } else { "dot" };
+
+/// Whether the Graphviz `dot` binary is on `PATH`, so callers can choose between rendering SVG
+/// with it and a pure-Rust fallback layout. `PATH` is only searched on the first call.
+pub fn is_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        std::env::var_os("PATH")
+            .and_then(|path| find_in_path(DOT_BINARY, &path))
+            .is_some()
+    })
+}
+
+/// First file called `binary` in the directories of a `PATH`-style list.
+fn find_in_path(binary: &str, path: &OsStr) -> Option<PathBuf> {
+    std::env::split_paths(path)
+        .map(|dir| dir.join(binary))
+        .find(|candidate| is_file(candidate))
+}
+
+fn is_file(path: &Path) -> bool {
+    path.metadata().is_ok_and(|meta| meta.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_available_is_cached() {
+        let first = is_available();
+        assert_eq!(is_available(), first);
+    }
+
+    #[test]
+    fn test_find_in_path() {
+        let dir = std::env::temp_dir().join(format!("graph-delta-graphviz-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(DOT_BINARY), "").unwrap();
+
+        let missing = dir.join("missing");
+        let path = std::env::join_paths([missing.as_path(), dir.as_path()]).unwrap();
+        assert_eq!(find_in_path(DOT_BINARY, &path), Some(dir.join(DOT_BINARY)));
+        assert_eq!(find_in_path("no-such-binary", &path), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
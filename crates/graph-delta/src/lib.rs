@@ -1,9 +1,17 @@
 /// Handles to/from DOT format and changes in between.
 pub mod dot_chunks;
-pub use dot_chunks::{commands, parser};
+pub use dot_chunks::{analysis, color, commands, parser, patch, port};
+pub use analysis::strongly_connected_components;
 
 /// Domain specific language for generating graph deltas.
 pub mod dsl;
 
 /// LLM Tools
 pub mod tool;
+
+/// Detecting a local Graphviz install
+pub mod graphviz;
+
+/// Embedding-based search over node labels
+#[cfg(feature = "embeddings")]
+pub mod semantic;
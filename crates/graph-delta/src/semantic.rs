@@ -0,0 +1,92 @@
+//! Semantic node search with [candle_embeddings] sentence embeddings.
+use crate::parser::Chunk;
+use candle_embeddings::{Error, Model, Params};
+
+/// The text a node is matched on: its `label`, else its id, without surrounding quotes.
+fn node_text(chunk: &Chunk) -> Option<(&str, &str)> {
+    let id = chunk.id.as_deref()?;
+    let label = chunk.attrs.get("label").map_or(id, String::as_str);
+    Some((id, label.trim_matches('"')))
+}
+
+/// Ids of the (at most) `top_k` nodes whose labels are closest to `query`, most similar first.
+///
+/// The query and every declared node's label (or id, if it has none) are embedded in one batch
+/// and ranked by cosine similarity. Nodes declared more than once count once.
+pub fn semantic_find_nodes(
+    chunks: &[Chunk],
+    model: &mut Model,
+    query: &str,
+    top_k: usize,
+) -> Result<Vec<String>, Error> {
+    let mut nodes: Vec<(&str, &str)> = Vec::new();
+    for (id, label) in chunks
+        .iter()
+        .filter(|c| c.kind == "node")
+        .filter_map(node_text)
+    {
+        if !nodes.iter().any(|(seen, _)| *seen == id) {
+            nodes.push((id, label));
+        }
+    }
+    if nodes.is_empty() || top_k == 0 {
+        return Ok(Vec::new());
+    }
+
+    // E5 models expect a `query: ` prefix on both sides of a symmetric similarity task.
+    let sentences = std::iter::once(query)
+        .chain(nodes.iter().map(|(_, label)| *label))
+        .map(|text| format!("query: {}", text))
+        .collect();
+    let embeddings = model.get_embeddings(Params::new(sentences, true))?;
+    let (query_vec, node_vecs) = embeddings
+        .vectors()
+        .split_first()
+        .ok_or(Error::IndexOutOfBounds(0))?;
+
+    // Normalized, so the dot product is the cosine similarity.
+    let mut scored: Vec<(f32, &str)> = node_vecs
+        .iter()
+        .zip(&nodes)
+        .map(|(vec, (id, _))| (vec.iter().zip(query_vec).map(|(a, b)| a * b).sum(), *id))
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    Ok(scored
+        .into_iter()
+        .take(top_k)
+        .map(|(_, id)| id.to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use candle_embeddings::ModelArgs;
+
+    // Downloads intfloat/e5-small-v2 from the Hugging Face Hub.
+    // Run with: cargo test -p graph-delta --features embeddings -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_semantic_find_nodes() {
+        let chunks = parser::parse_dot_to_chunks(
+            r#"digraph G {
+                db [label="PostgreSQL database"];
+                ui [label="Login page"];
+                cache [label="Banana smoothie recipe"];
+            }"#,
+        )
+        .unwrap();
+        let mut model = Model::from_args(&ModelArgs::default()).await.unwrap();
+
+        let found = semantic_find_nodes(&chunks, &mut model, "where is data stored", 3).unwrap();
+        assert_eq!(found.len(), 3);
+        let rank = |id: &str| found.iter().position(|f| f == id).unwrap();
+        assert!(rank("db") < rank("cache"));
+
+        assert_eq!(
+            semantic_find_nodes(&chunks, &mut model, "storage", 1).unwrap(),
+            vec!["db".to_string()]
+        );
+    }
+}
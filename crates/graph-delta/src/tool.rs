@@ -2,8 +2,9 @@
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::commands::DotCommand;
+use crate::commands::{DotCommand, parent_subgraphs};
 use crate::parser::Chunk;
+use crate::port;
 
 /// Tool definitions that the LLM can call
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,7 +33,7 @@ pub fn get_tool_definitions() -> Vec<ToolDefinition> {
         },
         ToolDefinition {
             name: "list_nodes".to_string(),
-            description: "List all nodes in the graph or within a specific subgraph".to_string(),
+            description: "List all nodes in the graph or within a specific subgraph, with each node's innermost parent subgraph and nesting depth (0 = top level)".to_string(),
             parameters: json!({
                 "type": "object",
                 "properties": {
@@ -180,7 +181,7 @@ pub fn get_tool_definitions() -> Vec<ToolDefinition> {
                 "properties": {
                     "id": {
                         "type": "string",
-                        "description": "Cluster identifier (use cluster_ prefix)"
+                        "description": "Cluster identifier (cluster_ is prepended if missing)"
                     },
                     "label": {
                         "type": "string",
@@ -333,6 +334,21 @@ pub fn tool_call_to_command(
     }
 }
 
+/// Subgraphs enclosing `chunks[index]`, outermost first, as [parent_subgraphs] nests them:
+/// bounds are inclusive, so members of a subgraph written on one line count, and subgraphs with
+/// the same range nest in chunk order.
+fn enclosing_subgraphs<'a>(
+    index: usize,
+    parents: &[Option<usize>],
+    chunks: &'a [Chunk],
+) -> Vec<&'a Chunk> {
+    let mut enclosing: Vec<&Chunk> = std::iter::successors(parents[index], |&p| parents[p])
+        .map(|p| &chunks[p])
+        .collect();
+    enclosing.reverse();
+    enclosing
+}
+
 /// Query tools - these don't modify the graph, just return info
 pub fn execute_query_tool(
     tool_name: &str,
@@ -358,28 +374,28 @@ pub fn execute_query_tool(
         "list_nodes" => {
             let parent = params.get("parent").and_then(|v| v.as_str());
 
+            let parents = parent_subgraphs(chunks);
+
             let nodes: Vec<_> = chunks
                 .iter()
-                .filter(|c| c.kind == "node")
-                .filter(|c| {
+                .enumerate()
+                .filter(|(_, c)| c.kind == "node")
+                .filter_map(|(i, c)| {
+                    let enclosing = enclosing_subgraphs(i, &parents, chunks);
                     if let Some(parent_name) = parent {
-                        // Check if node is within parent's range
-                        if let Some(parent_chunk) = chunks.iter().find(|p| {
-                            p.kind == "subgraph" && p.id.as_ref() == Some(&parent_name.to_string())
-                        }) {
-                            c.range.0 > parent_chunk.range.0 && c.range.1 < parent_chunk.range.1
-                        } else {
-                            false
+                        if !enclosing
+                            .iter()
+                            .any(|p| p.id.as_deref() == Some(parent_name))
+                        {
+                            return None;
                         }
-                    } else {
-                        true
                     }
-                })
-                .map(|c| {
-                    json!({
+                    Some(json!({
                         "id": c.id,
-                        "attrs": c.attrs
-                    })
+                        "attrs": c.attrs,
+                        "parent": enclosing.last().and_then(|p| p.id.as_ref()),
+                        "depth": enclosing.len()
+                    }))
                 })
                 .collect();
 
@@ -395,8 +411,10 @@ pub fn execute_query_tool(
                 .iter()
                 .filter(|c| {
                     c.kind == "edge"
-                        && (c.id.as_ref() == Some(&node_id.to_string())
-                            || c.extra.as_ref() == Some(&node_id.to_string()))
+                        && [&c.id, &c.extra].into_iter().any(|end| {
+                            end.as_deref()
+                                .is_some_and(|end| port::same_endpoint(end, node_id))
+                        })
                 })
                 .map(|c| {
                     json!({
@@ -439,3 +457,67 @@ Example workflow for "change node A to be red":
 Keep responses brief. Focus on the tools, not explanations."#
         .to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_dot_to_chunks;
+
+    #[test]
+    fn test_list_nodes_parent_and_depth() {
+        let dot = r#"digraph G {
+    Root;
+    subgraph cluster_outer {
+        Outer;
+        subgraph cluster_inner {
+            Inner;
+        }
+    }
+}"#;
+        let chunks = parse_dot_to_chunks(dot).unwrap();
+        let result = execute_query_tool("list_nodes", json!({}), &chunks).unwrap();
+
+        let placement = |id: &str| {
+            let node = result["nodes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|n| n["id"] == id)
+                .unwrap();
+            (node["parent"].clone(), node["depth"].as_u64().unwrap())
+        };
+        assert_eq!(placement("Root"), (serde_json::Value::Null, 0));
+        assert_eq!(placement("Outer"), (json!("cluster_outer"), 1));
+        assert_eq!(placement("Inner"), (json!("cluster_inner"), 2));
+
+        let result =
+            execute_query_tool("list_nodes", json!({ "parent": "cluster_outer" }), &chunks)
+                .unwrap();
+        assert_eq!(result["nodes"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_list_nodes_in_one_line_subgraphs() {
+        let dot = "digraph G {\n    subgraph cluster_a { A; subgraph cluster_b { B; } C; }\n}";
+        let chunks = parse_dot_to_chunks(dot).unwrap();
+        let result = execute_query_tool("list_nodes", json!({}), &chunks).unwrap();
+        let placements: Vec<_> = result["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|n| (n["id"].clone(), n["parent"].clone(), n["depth"].clone()))
+            .collect();
+        assert_eq!(
+            placements,
+            [
+                (json!("A"), json!("cluster_a"), json!(1)),
+                (json!("B"), json!("cluster_b"), json!(2)),
+                (json!("C"), json!("cluster_a"), json!(1)),
+            ]
+        );
+
+        let result =
+            execute_query_tool("list_nodes", json!({ "parent": "cluster_b" }), &chunks).unwrap();
+        assert_eq!(result["nodes"].as_array().unwrap().len(), 1);
+    }
+}